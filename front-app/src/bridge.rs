@@ -0,0 +1,351 @@
+use crate::metrics::CmdMetrics;
+use crate::AppErr;
+use std::any::{self, Any, TypeId};
+use std::env;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TryRecvError, TrySendError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{oneshot, watch};
+
+/// Operation executed by tarantool command executor. Implemented by
+/// server function types, so server function args are command input.
+pub trait Command: Send + 'static {
+    type Output: Send + 'static;
+
+    /// Short command name, used in logs and metrics
+    fn name() -> &'static str {
+        let name = any::type_name::<Self>();
+        name.rsplit("::").next().unwrap_or(name)
+    }
+}
+
+/// Command executed on behalf of a user
+#[derive(Debug)]
+pub struct AsUser<C> {
+    pub user_id: u32,
+    pub cmd: C,
+}
+
+impl<C: Command> Command for AsUser<C> {
+    type Output = C::Output;
+
+    fn name() -> &'static str {
+        C::name()
+    }
+}
+
+/// Reply handle of a single command, answered by the command executor
+pub type Reply<C> = oneshot::Sender<Result<<C as Command>::Output, AppErr>>;
+
+/// Typed command with its reply handle
+pub struct Job<C: Command> {
+    pub cmd: C,
+    pub reply: Reply<C>,
+}
+
+/// Type erased command sent to command executor
+pub struct Cmd {
+    kind: TypeId,
+    name: &'static str,
+    job: Box<dyn Any + Send>,
+    is_cancelled: fn(&(dyn Any + Send)) -> bool,
+    created_at: Instant,
+}
+
+impl Cmd {
+    /// Wrap command, returns receiver of command reply
+    pub fn new<C: Command>(cmd: C) -> (Cmd, oneshot::Receiver<Result<C::Output, AppErr>>) {
+        let (reply, reply_rx) = oneshot::channel();
+        let cmd = Cmd {
+            kind: TypeId::of::<C>(),
+            name: C::name(),
+            job: Box::new(Job { cmd, reply }),
+            is_cancelled: job_cancelled::<C>,
+            created_at: Instant::now(),
+        };
+        (cmd, reply_rx)
+    }
+
+    /// Type id of wrapped command
+    pub fn kind(&self) -> TypeId {
+        self.kind
+    }
+
+    /// Name of wrapped command
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Time since command was created, i.e. time spent waiting for executor
+    pub fn age(&self) -> Duration {
+        self.created_at.elapsed()
+    }
+
+    /// Check if requester stopped waiting for the reply
+    pub fn is_cancelled(&self) -> bool {
+        (self.is_cancelled)(&*self.job)
+    }
+
+    /// Unwrap typed command. Command of other type is returned back.
+    pub fn downcast<C: Command>(self) -> Result<Job<C>, Cmd> {
+        match self.job.downcast::<Job<C>>() {
+            Ok(job) => Ok(*job),
+            Err(job) => Err(Cmd { job, ..self }),
+        }
+    }
+}
+
+impl fmt::Debug for Cmd {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Cmd").field("name", &self.name).finish()
+    }
+}
+
+fn job_cancelled<C: Command>(job: &(dyn Any + Send)) -> bool {
+    job.downcast_ref::<Job<C>>()
+        .map_or(true, |job| job.reply.is_closed())
+}
+
+/// Create command channel between http server and command executor,
+/// at most `depth` commands may be queued.
+/// Every sent command also writes a byte into the wakeup socket, so
+/// the executor may sleep on the socket fd while the queue is empty.
+pub fn channel(depth: usize) -> io::Result<(CmdSender, CmdReceiver)> {
+    let (notify, wakeup) = UnixStream::pair()?;
+    notify.set_nonblocking(true)?;
+    wakeup.set_nonblocking(true)?;
+    let (tx, rx) = mpsc::sync_channel(depth);
+    let metrics = Arc::new(CmdMetrics::new(depth));
+
+    let sender = CmdSender {
+        tx,
+        notify: Arc::new(notify),
+        metrics: metrics.clone(),
+    };
+    let receiver = CmdReceiver {
+        rx,
+        wakeup,
+        metrics,
+    };
+
+    Ok((sender, receiver))
+}
+
+/// Sending half of command channel
+#[derive(Debug, Clone)]
+pub struct CmdSender {
+    tx: SyncSender<Cmd>,
+    notify: Arc<UnixStream>,
+    metrics: Arc<CmdMetrics>,
+}
+
+impl CmdSender {
+    /// Queue command and wake up the receiver. Never blocks, command is
+    /// returned back when the queue is full.
+    pub fn send(&self, cmd: Cmd) -> Result<(), TrySendError<Cmd>> {
+        // Counted before sending, so receiver never sees negative depth
+        self.metrics.enqueue();
+        if let Err(err) = self.tx.try_send(cmd) {
+            self.metrics.dequeue();
+            if let TrySendError::Full(_) = err {
+                self.metrics.reject();
+            }
+            return Err(err);
+        }
+        // Full socket buffer means the receiver is already notified
+        let _ = (&*self.notify).write(&[1]);
+        Ok(())
+    }
+
+    /// Execute command outside of a server function, e.g. in http
+    /// middleware, waiting for its result at most `timeout`
+    pub async fn call<C: Command>(&self, cmd: C, timeout: Duration) -> Result<C::Output, AppErr> {
+        let (cmd, reply_rx) = Cmd::new(cmd);
+        self.send(cmd).map_err(|err| match err {
+            TrySendError::Full(_) => AppErr::Overloaded,
+            TrySendError::Disconnected(_) => AppErr::Bridge("command executor stopped".into()),
+        })?;
+
+        tokio::time::timeout(timeout, reply_rx)
+            .await
+            .map_err(|_| AppErr::Unavailable)?
+            .map_err(|_| AppErr::Bridge("command dropped without reply".into()))?
+    }
+
+    /// Metrics of the command queue
+    pub fn metrics(&self) -> &Arc<CmdMetrics> {
+        &self.metrics
+    }
+}
+
+/// Receiving half of command channel
+#[derive(Debug)]
+pub struct CmdReceiver {
+    rx: Receiver<Cmd>,
+    wakeup: UnixStream,
+    metrics: Arc<CmdMetrics>,
+}
+
+impl CmdReceiver {
+    /// Receive next command. While the queue is empty `wait` is called
+    /// with the wakeup socket fd, it should block until fd is readable.
+    /// Returns `None` when all senders are dropped.
+    pub fn recv_with<W>(&self, mut wait: W) -> Option<Cmd>
+    where
+        W: FnMut(RawFd),
+    {
+        loop {
+            match self.try_recv() {
+                Ok(cmd) => return Some(cmd),
+                Err(TryRecvError::Disconnected) => return None,
+                Err(TryRecvError::Empty) => {}
+            }
+
+            // Notifications are cleared before the queue is checked again,
+            // so a command sent after the check always wakes up `wait`.
+            self.clear_wakeups();
+
+            match self.try_recv() {
+                Ok(cmd) => return Some(cmd),
+                Err(TryRecvError::Disconnected) => return None,
+                Err(TryRecvError::Empty) => wait(self.wakeup.as_raw_fd()),
+            }
+        }
+    }
+
+    /// Metrics of the command queue
+    pub fn metrics(&self) -> &Arc<CmdMetrics> {
+        &self.metrics
+    }
+
+    fn try_recv(&self) -> Result<Cmd, TryRecvError> {
+        let cmd = self.rx.try_recv()?;
+        self.metrics.dequeue();
+        Ok(cmd)
+    }
+
+    /// Read out all pending wakeup notifications
+    fn clear_wakeups(&self) {
+        let mut buf = [0u8; 64];
+        while let Ok(n) = (&self.wakeup).read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+        }
+    }
+}
+
+/// Executes commands in place, without crossing threads. Provided to
+/// server functions running on tarantool fibers.
+#[derive(Clone)]
+pub struct InlineExecutor(Rc<dyn Fn(Cmd)>);
+
+impl InlineExecutor {
+    pub fn new<F>(execute: F) -> Self
+    where
+        F: Fn(Cmd) + 'static,
+    {
+        InlineExecutor(Rc::new(execute))
+    }
+
+    /// Execute command, returns its reply
+    pub fn execute<C: Command>(&self, cmd: C) -> Result<C::Output, AppErr> {
+        let (cmd, mut reply_rx) = Cmd::new(cmd);
+        (self.0)(cmd);
+        reply_rx
+            .try_recv()
+            .map_err(|_| AppErr::Bridge("command dropped without reply".into()))?
+    }
+}
+
+impl fmt::Debug for InlineExecutor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("InlineExecutor").finish()
+    }
+}
+
+/// Deadline for a command reply. Configured with `CMD_TIMEOUT_MS`
+/// environment variable, 5 seconds by default.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CmdTimeout(pub Duration);
+
+impl CmdTimeout {
+    pub fn from_env() -> Self {
+        env::var("CMD_TIMEOUT_MS")
+            .ok()
+            .and_then(|ms| ms.parse().ok())
+            .map(|ms| CmdTimeout(Duration::from_millis(ms)))
+            .unwrap_or_default()
+    }
+}
+
+impl Default for CmdTimeout {
+    fn default() -> Self {
+        CmdTimeout(Duration::from_secs(5))
+    }
+}
+
+/// Maximum number of queued commands. Configured with `CMD_QUEUE_DEPTH`
+/// environment variable, 1024 by default.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CmdQueueDepth(pub usize);
+
+impl CmdQueueDepth {
+    pub fn from_env() -> Self {
+        env::var("CMD_QUEUE_DEPTH")
+            .ok()
+            .and_then(|n| n.parse().ok())
+            .filter(|n| *n > 0)
+            .map(CmdQueueDepth)
+            .unwrap_or_default()
+    }
+}
+
+impl Default for CmdQueueDepth {
+    fn default() -> Self {
+        CmdQueueDepth(1024)
+    }
+}
+
+/// Flag raised when a command of the request was rejected by full queue,
+/// so http handler can answer with `503 Service Unavailable`
+#[derive(Debug, Clone, Default)]
+pub struct Overload(Arc<AtomicBool>);
+
+impl Overload {
+    pub fn raise(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_raised(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Create guard held by http handler while the request is served.
+/// Dropping the guard (e.g. client disconnected) resolves `Disconnect`.
+pub fn disconnect_guard() -> (DisconnectGuard, Disconnect) {
+    let (tx, rx) = watch::channel(());
+    (DisconnectGuard(tx), Disconnect(rx))
+}
+
+/// Guard of request lifetime
+#[derive(Debug)]
+pub struct DisconnectGuard(watch::Sender<()>);
+
+/// Notification of dropped request guard
+#[derive(Debug, Clone)]
+pub struct Disconnect(watch::Receiver<()>);
+
+impl Disconnect {
+    /// Wait until request guard is dropped
+    pub async fn wait(mut self) {
+        while self.0.changed().await.is_ok() {}
+    }
+}
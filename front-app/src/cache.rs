@@ -0,0 +1,170 @@
+use crate::{Todo, TodoCounts, TodoPage};
+use arc_swap::ArcSwap;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound;
+use std::sync::Arc;
+
+/// Todos of one owner in position order, indexed by id
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct OwnerTodos {
+    /// Todos by position and id
+    todos: BTreeMap<(u64, u32), Todo>,
+    /// Positions of todos by id
+    positions: HashMap<u32, u64>,
+    /// Number of completed todos
+    completed: usize,
+}
+
+impl OwnerTodos {
+    /// Insert todo or replace todo with the same id
+    pub fn insert(&mut self, todo: Todo) {
+        if let Some(position) = self.positions.insert(todo.id, todo.position) {
+            if let Some(old) = self.todos.remove(&(position, todo.id)) {
+                self.completed -= usize::from(old.completed);
+            }
+        }
+        self.completed += usize::from(todo.completed);
+        self.todos.insert((todo.position, todo.id), todo);
+    }
+
+    pub fn remove(&mut self, id: u32) -> Option<Todo> {
+        let position = self.positions.remove(&id)?;
+        let todo = self.todos.remove(&(position, id))?;
+        self.completed -= usize::from(todo.completed);
+        Some(todo)
+    }
+
+    pub fn counts(&self) -> TodoCounts {
+        TodoCounts {
+            active: self.todos.len() - self.completed,
+            completed: self.completed,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.todos.is_empty()
+    }
+
+    /// Todos in position order
+    pub fn iter(&self) -> impl Iterator<Item = &Todo> {
+        self.todos.values()
+    }
+
+    /// Todos following todo `id` in position order, `None` when there
+    /// is no such todo
+    pub fn after(&self, id: u32) -> Option<impl Iterator<Item = &Todo>> {
+        let position = *self.positions.get(&id)?;
+        Some(
+            self.todos
+                .range((Bound::Excluded((position, id)), Bound::Unbounded))
+                .map(|(_, todo)| todo),
+        )
+    }
+}
+
+/// Versioned snapshot of `todo` space. Todos are grouped by owner, so
+/// a new snapshot shares todos of owners that did not change.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct TodoSnapshot {
+    pub version: u64,
+    pub owners: BTreeMap<u32, Arc<OwnerTodos>>,
+}
+
+/// Read cache of todos. Snapshots are published by tarantool space
+/// triggers and read by http server threads without locking.
+#[derive(Debug, Clone)]
+pub struct TodoCache(Arc<ArcSwap<TodoSnapshot>>);
+
+impl TodoCache {
+    pub fn new(snapshot: TodoSnapshot) -> Self {
+        TodoCache(Arc::new(ArcSwap::from_pointee(snapshot)))
+    }
+
+    /// Current snapshot
+    pub fn snapshot(&self) -> Arc<TodoSnapshot> {
+        self.0.load_full()
+    }
+
+    /// Todos of `owner_id` in current snapshot, in position order
+    pub fn todos(&self, owner_id: u32) -> Vec<Todo> {
+        self.0
+            .load()
+            .owners
+            .get(&owner_id)
+            .map(|todos| todos.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Todos of `owner_id` tagged with `tag`, in position order
+    pub fn tagged_todos(&self, owner_id: u32, tag: &str) -> Vec<Todo> {
+        self.0
+            .load()
+            .owners
+            .get(&owner_id)
+            .map(|todos| {
+                todos
+                    .iter()
+                    .filter(|t| t.tags.iter().any(|t| t == tag))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Numbers of active and completed todos of `owner_id`
+    pub fn todo_counts(&self, owner_id: u32) -> TodoCounts {
+        self.0
+            .load()
+            .owners
+            .get(&owner_id)
+            .map(|todos| todos.counts())
+            .unwrap_or_default()
+    }
+
+    /// Page of at most `limit` todos of `owner_id` after todo `after_id`,
+    /// only todos of `completed` status when it is set. `None` when
+    /// `after_id` is not a todo of the owner.
+    pub fn todo_page(
+        &self,
+        owner_id: u32,
+        after_id: Option<u32>,
+        limit: usize,
+        completed: Option<bool>,
+    ) -> Option<TodoPage> {
+        let snapshot = self.0.load();
+        let empty = OwnerTodos::default();
+        let todos = snapshot
+            .owners
+            .get(&owner_id)
+            .map_or(&empty, |todos| todos.as_ref());
+        let todos: Box<dyn Iterator<Item = &Todo>> = match after_id {
+            Some(after_id) => Box::new(todos.after(after_id)?),
+            None => Box::new(todos.iter()),
+        };
+
+        let mut page: Vec<Todo> = todos
+            .filter(|t| completed.map_or(true, |completed| t.completed == completed))
+            .take(limit + 1)
+            .cloned()
+            .collect();
+        let next = if page.len() > limit {
+            page.truncate(limit);
+            page.last().map(|t| t.id)
+        } else {
+            None
+        };
+
+        Some(TodoPage { todos: page, next })
+    }
+
+    /// Replace current snapshot
+    pub fn publish(&self, snapshot: TodoSnapshot) {
+        self.0.store(Arc::new(snapshot));
+    }
+}
+
+impl Default for TodoCache {
+    fn default() -> Self {
+        TodoCache::new(TodoSnapshot::default())
+    }
+}
@@ -1,30 +1,247 @@
 use crate::components::{TodoFooter, TodoHeader, TodoItem};
 use crate::{
-    add_todo, change_all_completed, change_completed, change_title, delete_completed, delete_todo,
-    Todo,
+    add_tag, add_todo, change_all_completed, change_completed, change_due, change_title,
+    delete_completed, delete_todo, get_tagged_todos, get_todo_counts, get_todo_page, move_todo,
+    remove_tag, search_todos, undo, AppErr, Todo, TodoChanges, TodoCounts, UndoableChanges,
+    TODO_PAGE_SIZE,
 };
+use core::time::Duration;
 use leptos::ev::Event;
 use leptos::*;
-use leptos_router::use_location;
+use leptos_router::{use_location, use_params_map, use_query_map, Form};
 
+/// Distance to the page bottom, in pixels, when the next page is loaded
+const SCROLL_MARGIN: f64 = 300.0;
+
+/// Completion status of todos listed on route `path`, `None` for all
+fn route_completed(path: &str) -> Option<bool> {
+    match path {
+        "/active" => Some(false),
+        "/completed" => Some(true),
+        _ => None,
+    }
+}
+
+/// Replace todos with their first page, only todos of `completed` status
+/// when it is set
+async fn reload_todos(
+    completed: Option<bool>,
+    set_todos: WriteSignal<Vec<Todo>>,
+    set_next: WriteSignal<Option<u32>>,
+) {
+    match get_todo_page(None, TODO_PAGE_SIZE, completed).await {
+        Ok(page) => {
+            set_todos.set(page.todos);
+            set_next.set(page.next);
+        }
+        Err(err) => logging::error!("{}", AppErr::from(err)),
+    }
+}
+
+/// Merge todos changed on server into loaded ones, `next` is the cursor
+/// of the page following them. Logs the error otherwise.
+pub(super) fn update_todos(
+    next: ReadSignal<Option<u32>>,
+    set_todos: WriteSignal<Vec<Todo>>,
+    set_next: WriteSignal<Option<u32>>,
+    res: Result<TodoChanges, ServerFnError>,
+) {
+    match res {
+        Ok(changes) => {
+            let mut cursor = next.get_untracked();
+            let mut reload = false;
+            set_todos.update(|todos| reload = changes.apply(todos, &mut cursor));
+            set_next.set(cursor);
+            if reload {
+                let path = window().location().pathname().unwrap_or_default();
+                spawn_local(reload_todos(route_completed(&path), set_todos, set_next));
+            }
+        }
+        Err(err) => logging::error!("{}", AppErr::from(err)),
+    }
+}
+
+/// How long the undo toast is shown, a bit less than server keeps
+/// undo tokens
+const UNDO_TOAST: Duration = Duration::from_secs(25);
+
+/// Undo toast, message and undo token of the last destructive action
+type Toast = Option<(&'static str, String)>;
+
+/// Merge todos changed by destructive action and offer to undo it, log
+/// the error otherwise
+fn update_undoable(
+    next: ReadSignal<Option<u32>>,
+    set_todos: WriteSignal<Vec<Todo>>,
+    set_next: WriteSignal<Option<u32>>,
+    set_toast: WriteSignal<Toast>,
+    message: &'static str,
+    res: Result<UndoableChanges, ServerFnError>,
+) {
+    match res {
+        Ok(UndoableChanges {
+            changes,
+            undo_token,
+        }) => {
+            update_todos(next, set_todos, set_next, Ok(changes));
+            set_toast.set(Some((message, undo_token.clone())));
+            // Later toast is kept
+            set_timeout(
+                move || {
+                    set_toast.update(|toast| {
+                        if toast
+                            .as_ref()
+                            .is_some_and(|(_, token)| *token == undo_token)
+                        {
+                            *toast = None;
+                        }
+                    })
+                },
+                UNDO_TOAST,
+            );
+        }
+        Err(err) => logging::error!("{}", AppErr::from(err)),
+    }
+}
+
+/// Merge todos changed on server into `found` ones. Search results keep
+/// only todos found before, as changed titles are not searched again,
+/// while changed todos are listed in tag view if they have the tag.
+fn merge_found(found: &mut Vec<Todo>, changes: &TodoChanges, search: bool) {
+    found.retain(|t| !changes.deleted.contains(&t.id));
+    for todo in &changes.changed {
+        match found.iter_mut().find(|t| t.id == todo.id) {
+            Some(t) => *t = todo.clone(),
+            None if !search => found.push(todo.clone()),
+            None => {}
+        }
+    }
+    found.sort_by_key(|t| (t.position, t.id));
+}
+
+/// Check if page is scrolled close to its bottom
+fn near_bottom() -> bool {
+    let window = window();
+    let height = window
+        .inner_height()
+        .ok()
+        .and_then(|h| h.as_f64())
+        .unwrap_or_default();
+    let scrolled = window.scroll_y().unwrap_or_default();
+    let total = document().body().map_or(0, |b| b.scroll_height()) as f64;
+    scrolled + height + SCROLL_MARGIN >= total
+}
+
+/// Todo list, `next` is the cursor of the next page of `todos`, which
+/// are loaded of `loaded` completion status
 #[component]
-pub fn HomePage(todos: ReadSignal<Vec<Todo>>, set_todos: WriteSignal<Vec<Todo>>) -> impl IntoView {
+pub fn HomePage(
+    todos: ReadSignal<Vec<Todo>>,
+    set_todos: WriteSignal<Vec<Todo>>,
+    next: ReadSignal<Option<u32>>,
+    set_next: WriteSignal<Option<u32>>,
+    loaded: RwSignal<Option<bool>>,
+) -> impl IntoView {
     let (toggle_all, set_toggle_all) = create_signal(false);
-    let remaining = move || todos.with(|ts| ts.iter().filter(|t| !t.completed).count());
-    let completed = move || todos.with(|ts| ts.iter().filter(|t| t.completed).count());
+    let (toast, set_toast) = create_signal::<Toast>(None);
 
     let pathname = use_location().pathname;
+    // Route is kept while navigating between tags, so tag is tracked
+    let params = use_params_map();
+    let path = pathname.get();
+
+    // Pages are loaded by route completion status, so todos loaded for
+    // another route are replaced
+    let route = route_completed(&path);
+    create_effect(move |_| {
+        if loaded.get_untracked() != route {
+            loaded.set(route);
+            spawn_local(reload_todos(route, set_todos, set_next));
+        }
+    });
+
+    // Counts include todos on pages not loaded yet, so they are fetched
+    // again after every change
+    let (revision, set_revision) = create_signal(0u32);
+    let counts = create_local_resource(
+        move || revision.get(),
+        |_| async move {
+            match get_todo_counts().await {
+                Ok(counts) => Some(counts),
+                Err(err) => {
+                    logging::error!("{}", AppErr::from(err));
+                    None
+                }
+            }
+        },
+    );
+    let counts = move || counts.get().flatten().unwrap_or_default();
+    let remaining = move || counts().active;
+    let completed = move || counts().completed;
 
-    let filter = match pathname.get().as_str() {
-        "/" => |_t: &Todo| true,
-        "/active" => |t: &Todo| !t.completed,
-        "/completed" => |t: &Todo| t.completed,
-        &_ => unreachable!(),
+    let filter = move |t: &Todo| match path.as_str() {
+        "/" => true,
+        "/active" => !t.completed,
+        "/completed" => t.completed,
+        _ => params.with(|p| p.get("name").is_some_and(|tag| t.tags.contains(tag))),
+    };
+
+    // Search by `?q=` and tag view are done on server, so todos on pages
+    // not loaded yet are listed too. Changes are merged into found todos.
+    let query = use_query_map();
+    let q = move || query.with(|q| q.get("q").cloned().unwrap_or_default());
+    let tag = move || params.with(|p| p.get("name").cloned());
+    let fetched = create_local_resource(
+        move || (q(), tag()),
+        |(q, tag)| async move {
+            let res = match tag {
+                _ if !q.trim().is_empty() => search_todos(q).await,
+                Some(tag) => get_tagged_todos(tag).await,
+                None => return None,
+            };
+            match res {
+                Ok(fetched) => Some(fetched),
+                Err(err) => {
+                    logging::error!("{}", AppErr::from(err));
+                    None
+                }
+            }
+        },
+    );
+
+    let found = create_rw_signal(None::<Vec<Todo>>);
+    create_effect(move |_| found.set(fetched.get().flatten()));
+
+    // Changes are merged into loaded and found todos, then counts are
+    // fetched again
+    let merge_changes = move |changes: &TodoChanges| {
+        let search = query.with_untracked(|q| q.get("q").is_some_and(|q| !q.trim().is_empty()));
+        found.update(|found| {
+            if let Some(found) = found {
+                merge_found(found, changes, search);
+            }
+        });
+    };
+    let on_changes = move |res: Result<TodoChanges, ServerFnError>| {
+        if let Ok(changes) = &res {
+            merge_changes(changes);
+        }
+        update_todos(next, set_todos, set_next, res);
+        set_revision.update(|r| *r += 1);
+    };
+    let on_undoable = move |message: &'static str, res: Result<UndoableChanges, ServerFnError>| {
+        if let Ok(undoable) = &res {
+            merge_changes(&undoable.changes);
+        }
+        update_undoable(next, set_todos, set_next, set_toast, message, res);
+        set_revision.update(|r| *r += 1);
     };
 
+    // Found todos are filtered by route too, e.g. by tag
     let filter_todos = move || {
-        todos
+        found
             .get()
+            .unwrap_or_else(|| todos.get())
             .into_iter()
             .filter(|todo| filter(todo))
             .collect::<Vec<Todo>>()
@@ -34,52 +251,159 @@ pub fn HomePage(todos: ReadSignal<Vec<Todo>>, set_todos: WriteSignal<Vec<Todo>>)
         let new_value = event_target_checked(&ev);
         set_toggle_all.set(new_value);
         spawn_local(async move {
-            let new_todos = change_all_completed(new_value).await.unwrap();
-            set_todos.set(new_todos);
+            let res = change_all_completed(new_value).await;
+            let message = if new_value {
+                "All todos completed"
+            } else {
+                "All todos activated"
+            };
+            on_undoable(message, res);
         });
     };
 
     let on_add_todo = move |s| {
         spawn_local(async move {
-            let new_todos = add_todo(s).await.unwrap();
-            set_todos.set(new_todos);
+            on_changes(add_todo(s).await);
         });
     };
 
     let on_destroy = move |id| {
         spawn_local(async move {
-            let new_todos = delete_todo(id).await.unwrap();
-            set_todos.set(new_todos);
+            let res = delete_todo(id).await;
+            on_undoable("Todo deleted", res);
         });
     };
 
     let on_change_title = move |id, title| {
         spawn_local(async move {
-            let new_todos = change_title(id, title).await.unwrap();
-            set_todos.set(new_todos);
+            on_changes(change_title(id, title).await);
         });
     };
 
     let on_change_completed = move |id, completed| {
         spawn_local(async move {
-            let new_todos = change_completed(id, completed).await.unwrap();
-            set_todos.set(new_todos);
+            on_changes(change_completed(id, completed).await);
         });
     };
 
+    let on_change_due = move |id, due_at| {
+        spawn_local(async move {
+            on_changes(change_due(id, due_at).await);
+        });
+    };
+
+    let on_add_tag = move |id, tag| {
+        spawn_local(async move {
+            on_changes(add_tag(id, tag).await);
+        });
+    };
+
+    let on_remove_tag = move |id, tag| {
+        spawn_local(async move {
+            on_changes(remove_tag(id, tag).await);
+        });
+    };
+
+    // Dragged todo is dropped before the target, or after it when
+    // dragged down the list
+    let (dragged, set_dragged) = create_signal(None::<u32>);
+
+    let on_drag_start = move |id| set_dragged.set(Some(id));
+
+    let on_drop = move |target_id| {
+        let Some(id) = dragged.get_untracked() else {
+            return;
+        };
+        set_dragged.set(None);
+        if id == target_id {
+            return;
+        }
+        let before_id = todos.with_untracked(|ts| {
+            let from = ts.iter().position(|t| t.id == id);
+            let to = ts.iter().position(|t| t.id == target_id)?;
+            if from < Some(to) {
+                ts.get(to + 1).map(|t| t.id)
+            } else {
+                Some(target_id)
+            }
+        });
+        spawn_local(async move {
+            on_changes(move_todo(id, before_id).await);
+        });
+    };
+
+    // Next page is loaded when list bottom gets close to the viewport
+    let (loading, set_loading) = create_signal(false);
+    let load_more = move || {
+        let Some(after_id) = next.get_untracked() else {
+            return;
+        };
+        if loading.get_untracked() || !near_bottom() {
+            return;
+        }
+        set_loading.set(true);
+        spawn_local(async move {
+            let res = get_todo_page(Some(after_id), TODO_PAGE_SIZE, route).await;
+            match res {
+                // Todos may be replaced by other response meanwhile
+                Ok(page) if next.get_untracked() == Some(after_id) => {
+                    set_todos.update(|ts| ts.extend(page.todos));
+                    set_next.set(page.next);
+                }
+                Ok(_) => {}
+                // Failed page is not retried until todos are reloaded
+                Err(err) => {
+                    logging::error!("{}", AppErr::from(err));
+                    set_next.set(None);
+                }
+            }
+            set_loading.set(false);
+        });
+    };
+    let scroll_listener = window_event_listener(ev::scroll, move |_| load_more());
+    on_cleanup(move || scroll_listener.remove());
+    // List shorter than the viewport can't be scrolled, so it is filled
+    // after every loaded page
+    create_effect(move |_| {
+        if next.get().is_some() && !loading.get() {
+            load_more();
+        }
+    });
+
     let on_delete_completed = move || {
         spawn_local(async move {
-            let new_todos = delete_completed().await.unwrap();
-            set_todos.set(new_todos);
+            let res = delete_completed().await;
+            on_undoable("Completed todos deleted", res);
+        });
+    };
+
+    let on_undo = move |_| {
+        let Some((_, token)) = toast.get_untracked() else {
+            return;
+        };
+        set_toast.set(None);
+        spawn_local(async move {
+            on_changes(undo(token).await);
         });
     };
 
     view! {
         <TodoHeader on_add_todo=on_add_todo />
+        // Footer links stay when no todo is of route status
         <Show
-            when=move || todos.with(|t| !t.is_empty())
+            when=move || todos.with(|t| !t.is_empty()) || counts() != TodoCounts::default()
         >
             <main class="main">
+                <Form method="GET" action=move || pathname.get()>
+                    <input
+                        type="search"
+                        class="search"
+                        name="q"
+                        placeholder="Search todos"
+                        autocomplete="off"
+                        prop:value=q
+                    />
+                </Form>
                 <div class="toggle-all-container">
                     <input
                         type="checkbox"
@@ -99,7 +423,11 @@ pub fn HomePage(todos: ReadSignal<Vec<Todo>>, set_todos: WriteSignal<Vec<Todo>>)
                         key=|todo| todo.id
                         children=move |todo| {
                             let memo_todo = create_memo(move |_| {
-                                todos.with(|todos| todos.into_iter().find(|t| t.id == todo.id).unwrap_or(&todo).clone())
+                                let find = |ts: &Vec<Todo>| ts.iter().find(|t| t.id == todo.id).cloned();
+                                todos
+                                    .with(find)
+                                    .or_else(|| found.with(|f| f.as_ref().and_then(find)))
+                                    .unwrap_or_else(|| todo.clone())
                             });
 
                             view! {
@@ -107,7 +435,12 @@ pub fn HomePage(todos: ReadSignal<Vec<Todo>>, set_todos: WriteSignal<Vec<Todo>>)
                                     todo=memo_todo
                                     on_change_completed=on_change_completed
                                     on_change_title=on_change_title
+                                    on_change_due=on_change_due
+                                    on_add_tag=on_add_tag
+                                    on_remove_tag=on_remove_tag
                                     on_destroy=on_destroy
+                                    on_drag_start=on_drag_start
+                                    on_drop=on_drop
                                 />
                             }
                         }
@@ -120,5 +453,19 @@ pub fn HomePage(todos: ReadSignal<Vec<Todo>>, set_todos: WriteSignal<Vec<Todo>>)
                 on_delete_completed=on_delete_completed
             />
         </Show>
+        {move || {
+            toast
+                .get()
+                .map(|(message, _)| {
+                    view! {
+                        <div class="undo-toast">
+                            <span>{message}</span>
+                            <button class="undo" on:click=on_undo>
+                                "Undo"
+                            </button>
+                        </div>
+                    }
+                })
+        }}
     }
 }
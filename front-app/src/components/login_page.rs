@@ -0,0 +1,36 @@
+use crate::{AppErr, Login};
+use leptos::*;
+use leptos_router::{ActionForm, A};
+
+#[component]
+pub fn LoginPage() -> impl IntoView {
+    let login = create_server_action::<Login>();
+
+    // Reload application, so todos of signed in user are fetched
+    create_effect(move |_| {
+        if let Some(Ok(_)) = login.value().get() {
+            let _ = window().location().set_href("/");
+        }
+    });
+
+    let error = move || {
+        login
+            .value()
+            .get()
+            .and_then(Result::err)
+            .map(|err| AppErr::from(err).to_string())
+    };
+
+    view! {
+        <header class="header">
+            <h1>todos</h1>
+        </header>
+        <ActionForm action=login class="auth">
+            <input type="text" name="name" class="new-todo" placeholder="User name" required/>
+            <input type="password" name="password" class="new-todo" placeholder="Password" required/>
+            <p class="auth-error">{error}</p>
+            <button type="submit" class="auth-submit">Sign in</button>
+            <A href="/signup">Create account</A>
+        </ActionForm>
+    }
+}
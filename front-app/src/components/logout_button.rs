@@ -0,0 +1,22 @@
+use crate::{AppErr, Logout};
+use leptos::*;
+use leptos_router::ActionForm;
+
+#[component]
+pub fn LogoutButton() -> impl IntoView {
+    let logout = create_server_action::<Logout>();
+
+    create_effect(move |_| match logout.value().get() {
+        Some(Ok(())) => {
+            let _ = window().location().set_href("/login");
+        }
+        Some(Err(err)) => logging::error!("{}", AppErr::from(err)),
+        None => {}
+    });
+
+    view! {
+        <ActionForm action=logout class="logout">
+            <button type="submit">Sign out</button>
+        </ActionForm>
+    }
+}
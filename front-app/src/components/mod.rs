@@ -1,9 +1,19 @@
 mod home_page;
+mod login_page;
+mod logout_button;
+mod signup_page;
 mod todo_footer;
 mod todo_header;
+mod todo_history;
 mod todo_item;
+mod trash_page;
 
 pub use home_page::HomePage;
+pub use login_page::LoginPage;
+pub use logout_button::LogoutButton;
+pub use signup_page::SignupPage;
 pub use todo_footer::TodoFooter;
 pub use todo_header::TodoHeader;
+pub use todo_history::TodoHistory;
 pub use todo_item::TodoItem;
+pub use trash_page::TrashPage;
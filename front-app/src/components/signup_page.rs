@@ -0,0 +1,36 @@
+use crate::{AppErr, Signup};
+use leptos::*;
+use leptos_router::{ActionForm, A};
+
+#[component]
+pub fn SignupPage() -> impl IntoView {
+    let signup = create_server_action::<Signup>();
+
+    // New user is signed in, reload application with empty todo list
+    create_effect(move |_| {
+        if let Some(Ok(_)) = signup.value().get() {
+            let _ = window().location().set_href("/");
+        }
+    });
+
+    let error = move || {
+        signup
+            .value()
+            .get()
+            .and_then(Result::err)
+            .map(|err| AppErr::from(err).to_string())
+    };
+
+    view! {
+        <header class="header">
+            <h1>todos</h1>
+        </header>
+        <ActionForm action=signup class="auth">
+            <input type="text" name="name" class="new-todo" placeholder="User name" required/>
+            <input type="password" name="password" class="new-todo" placeholder="Password" required/>
+            <p class="auth-error">{error}</p>
+            <button type="submit" class="auth-submit">Sign up</button>
+            <A href="/login">Already have an account</A>
+        </ActionForm>
+    }
+}
@@ -17,6 +17,7 @@ where
                 <li><A href="/">All</A></li>
                 <li><A href="/active">Active</A></li>
                 <li><A href="/completed">Completed</A></li>
+                <li><A href="/trash">Trash</A></li>
             </ul>
                 <button
                     on:click=move |_| on_delete_completed()
@@ -0,0 +1,93 @@
+use super::todo_item::{now, relative_time};
+use crate::{get_audit, AppErr, AuditEntry, AuditPage, Todo, AUDIT_PAGE_SIZE};
+use leptos::*;
+
+/// Short description of a change, e.g. `renamed "A" to "B"`
+fn describe(entry: &AuditEntry) -> String {
+    let (before, after) = (entry.before.as_ref(), entry.after.as_ref());
+    match (entry.op.as_str(), before, after) {
+        ("create", _, Some(after)) => format!("created \"{}\"", after.title),
+        ("delete", _, _) => "deleted".to_string(),
+        ("restore", _, _) => "restored".to_string(),
+        ("purge", _, _) => "deleted permanently".to_string(),
+        ("change_title", Some(before), Some(after)) => {
+            format!("renamed \"{}\" to \"{}\"", before.title, after.title)
+        }
+        ("change_completed", _, Some(after)) if after.completed => "completed".to_string(),
+        ("change_completed", _, Some(_)) => "reopened".to_string(),
+        ("change_due", _, Some(after)) if after.due_at.is_some() => "due date set".to_string(),
+        ("change_due", _, Some(_)) => "due date removed".to_string(),
+        ("change_tags", _, Some(after)) if after.tags.is_empty() => "tags removed".to_string(),
+        ("change_tags", _, Some(after)) => format!("tags: {}", after.tags.join(", ")),
+        ("move", _, _) => "moved".to_string(),
+        (op, _, _) => op.to_string(),
+    }
+}
+
+/// Changes of `todo`, newest first, loaded by pages. History is fetched
+/// again when the todo changes.
+#[component]
+pub fn TodoHistory(todo: Memo<Todo>) -> impl IntoView {
+    let first_page = create_local_resource(
+        move || todo.get(),
+        |todo| async move {
+            get_audit(Some(todo.id), None, None, None, AUDIT_PAGE_SIZE)
+                .await
+                .unwrap_or_else(|err| {
+                    logging::error!("{}", AppErr::from(err));
+                    AuditPage::default()
+                })
+        },
+    );
+    let (history, set_history) = create_signal(AuditPage::default());
+    create_effect(move |_| {
+        if let Some(page) = first_page.get() {
+            set_history.set(page);
+        }
+    });
+
+    let load_more = move |_| {
+        let Some(after_id) = history.with_untracked(|h| h.next) else {
+            return;
+        };
+        let todo_id = todo.get_untracked().id;
+        spawn_local(async move {
+            match get_audit(Some(todo_id), None, None, Some(after_id), AUDIT_PAGE_SIZE).await {
+                // History may be fetched again meanwhile
+                Ok(page) if history.with_untracked(|h| h.next) == Some(after_id) => {
+                    set_history.update(|h| {
+                        h.entries.extend(page.entries);
+                        h.next = page.next;
+                    });
+                }
+                Ok(_) => {}
+                Err(err) => logging::error!("{}", AppErr::from(err)),
+            }
+        });
+    };
+
+    view! {
+        <ul class="todo-history">
+            <For
+                each=move || history.with(|h| h.entries.clone())
+                key=|entry| entry.id
+                children=move |entry| {
+                    let time = relative_time(entry.at, now());
+                    view! {
+                        <li>
+                            <span class="history-time">{time}</span>
+                            {describe(&entry)}
+                        </li>
+                    }
+                }
+            />
+            <Show when=move || history.with(|h| h.next.is_some())>
+                <li>
+                    <button class="history-more" on:click=load_more>
+                        "More"
+                    </button>
+                </li>
+            </Show>
+        </ul>
+    }
+}
@@ -1,19 +1,95 @@
+use super::TodoHistory;
 use crate::Todo;
 use leptos::ev::{Event, KeyboardEvent};
 use leptos::html::Input;
 use leptos::*;
+use leptos_router::A;
+use web_sys::DragEvent;
+
+/// Current unix time in seconds
+pub(super) fn now() -> u64 {
+    #[cfg(target_arch = "wasm32")]
+    {
+        (js_sys::Date::now() / 1000.0) as u64
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs())
+    }
+}
+
+/// Time passed since `time`, e.g. "2h ago"
+pub(super) fn relative_time(time: u64, now: u64) -> String {
+    let secs = now.saturating_sub(time);
+    match secs {
+        0..=59 => "just now".to_string(),
+        60..=3599 => format!("{}m ago", secs / 60),
+        3600..=86399 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86400),
+    }
+}
+
+/// Value of `datetime-local` input for unix `time`, in local time zone.
+/// Empty on server, input is filled after hydration.
+fn local_datetime(time: u64) -> String {
+    #[cfg(target_arch = "wasm32")]
+    {
+        let date = js_sys::Date::new(&((time * 1000) as f64).into());
+        format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}",
+            date.get_full_year(),
+            date.get_month() + 1,
+            date.get_date(),
+            date.get_hours(),
+            date.get_minutes()
+        )
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let _ = time;
+        String::new()
+    }
+}
+
+/// Unix time of `datetime-local` input value, `None` for empty or
+/// invalid value
+fn parse_local_datetime(value: &str) -> Option<u64> {
+    #[cfg(target_arch = "wasm32")]
+    {
+        // Date-time without offset is parsed in local time zone
+        let millis = js_sys::Date::parse(value);
+        (!value.is_empty() && millis.is_finite() && millis >= 0.0).then(|| (millis / 1000.0) as u64)
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let _ = value;
+        None
+    }
+}
 
 #[component]
-pub fn TodoItem<CC, CT, D>(
+pub fn TodoItem<CC, CT, CD, AT, RT, D, DS, DR>(
     todo: Memo<Todo>,
     on_change_completed: CC,
     on_change_title: CT,
+    on_change_due: CD,
+    on_add_tag: AT,
+    on_remove_tag: RT,
     on_destroy: D,
+    on_drag_start: DS,
+    on_drop: DR,
 ) -> impl IntoView
 where
     CC: Fn(u32, bool) + 'static,
     CT: Fn(u32, String) + 'static,
+    CD: Fn(u32, Option<u64>) + 'static,
+    AT: Fn(u32, String) + 'static,
+    RT: Fn(u32, String) + 'static + Copy,
     D: Fn(u32) + 'static + Clone,
+    DS: Fn(u32) + 'static,
+    DR: Fn(u32) + 'static,
 {
     let on_toggle = move |ev: Event| {
         let new_value = event_target_checked(&ev);
@@ -46,10 +122,61 @@ where
         set_editing.set(false);
     };
 
+    // Completed todo shows its completion time, other todos creation time
+    let time = move || {
+        todo.with(|t| match t.completed_at {
+            Some(completed_at) => format!("done {}", relative_time(completed_at, now())),
+            None => relative_time(t.created_at, now()),
+        })
+    };
+    let time_details =
+        move || todo.with(|t| format!("updated {}", relative_time(t.updated_at, now())));
+
+    let overdue =
+        move || todo.with(|t| !t.completed && t.due_at.is_some_and(|due_at| due_at <= now()));
+    let due_value = move || todo.with(|t| t.due_at.map(local_datetime).unwrap_or_default());
+    let change_due = move |ev: Event| {
+        let due_at = parse_local_datetime(&event_target_value(&ev));
+        on_change_due(todo.with(|t| t.id), due_at);
+    };
+
+    let add_tag = move |ev: KeyboardEvent| {
+        if ev.key() == "Enter" {
+            let input = event_target::<web_sys::HtmlInputElement>(&ev);
+            let tag = input.value();
+            if !tag.trim().is_empty() {
+                on_add_tag(todo.with(|t| t.id), tag);
+            }
+            input.set_value("");
+        }
+    };
+
+    let (history, set_history) = create_signal(false);
+
+    let start_drag = move |ev: DragEvent| {
+        let id = todo.with(|t| t.id);
+        // Firefox starts dragging only with some data set
+        if let Some(data) = ev.data_transfer() {
+            data.set_effect_allowed("move");
+            let _ = data.set_data("text/plain", &id.to_string());
+        }
+        on_drag_start(id);
+    };
+
+    let drop_on = move |ev: DragEvent| {
+        ev.prevent_default();
+        on_drop(todo.with(|t| t.id));
+    };
+
     view! {
         <li
             class:completed=move || todo.with(|t| t.completed)
             class:editing=editing
+            class:overdue=overdue
+            draggable=move || if editing.get() { "false" } else { "true" }
+            on:dragstart=start_drag
+            on:dragover=|ev: DragEvent| ev.prevent_default()
+            on:drop=drop_on
         >
             <div class="view">
                 <input
@@ -59,11 +186,52 @@ where
                     on:change=on_toggle
                 />
                 <label on:dblclick=start_editing>{ move || todo.with(|t| t.title.clone()) }</label>
+                <span class="todo-time" title=time_details>{time}</span>
+                <input
+                    type="datetime-local"
+                    class="todo-due"
+                    class:set=move || todo.with(|t| t.due_at.is_some())
+                    title="Due date"
+                    prop:value=due_value
+                    on:change=change_due
+                />
+                <ul class="todo-tags">
+                    <For
+                        each=move || todo.with(|t| t.tags.clone())
+                        key=|tag| tag.clone()
+                        children=move |tag| {
+                            let href = format!("/tag/{tag}");
+                            let removed = tag.clone();
+                            view! {
+                                <li>
+                                    <A href=href>{tag}</A>
+                                    <button
+                                        class="remove-tag"
+                                        title="Remove tag"
+                                        on:click=move |_| on_remove_tag(todo.with(|t| t.id), removed.clone())
+                                    >"×"</button>
+                                </li>
+                            }
+                        }
+                    />
+                    <li>
+                        <input class="new-tag" placeholder="+ tag" on:keyup=add_tag/>
+                    </li>
+                </ul>
+                <button
+                    class="history"
+                    class:open=history
+                    title="History"
+                    on:click=move |_| set_history.update(|h| *h = !*h)
+                >"⏱"</button>
                 <button
                     class="destroy"
                     on:click=move |_| on_destroy(todo.with(|t| t.id))
                 ></button>
             </div>
+            <Show when=move || history.get()>
+                <TodoHistory todo=todo />
+            </Show>
             <div class="input-container">
                 <input
                     id="edit-todo-input"
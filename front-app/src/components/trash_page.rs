@@ -0,0 +1,84 @@
+use super::home_page::update_todos;
+use super::todo_item::{now, relative_time};
+use crate::{empty_trash, get_trash, restore_todo, AppErr, Todo};
+use leptos::*;
+use leptos_router::A;
+
+/// Deleted todos, they are restored to the list or deleted permanently
+#[component]
+pub fn TrashPage(
+    next: ReadSignal<Option<u32>>,
+    set_todos: WriteSignal<Vec<Todo>>,
+    set_next: WriteSignal<Option<u32>>,
+) -> impl IntoView {
+    // Trash is fetched again after every change
+    let (version, set_version) = create_signal(0);
+    let trash = create_local_resource(
+        move || version.get(),
+        |_| async move {
+            get_trash().await.unwrap_or_else(|err| {
+                logging::error!("{}", AppErr::from(err));
+                Vec::new()
+            })
+        },
+    );
+
+    let on_restore = move |id| {
+        spawn_local(async move {
+            update_todos(next, set_todos, set_next, restore_todo(id).await);
+            set_version.update(|v| *v += 1);
+        });
+    };
+
+    let on_empty = move |_| {
+        spawn_local(async move {
+            if let Err(err) = empty_trash().await {
+                logging::error!("{}", AppErr::from(err));
+            }
+            set_version.update(|v| *v += 1);
+        });
+    };
+
+    let is_empty = move || trash.with(|t| t.as_ref().map_or(true, Vec::is_empty));
+
+    view! {
+        <header class="header">
+            <A href="/"><h1>todos</h1></A>
+        </header>
+        <main class="main trash">
+            <ul class="todo-list">
+                <For
+                    each=move || trash.get().unwrap_or_default()
+                    key=|trashed| trashed.todo.id
+                    children=move |trashed| {
+                        let id = trashed.todo.id;
+                        view! {
+                            <li class:completed=trashed.todo.completed>
+                                <div class="view">
+                                    <label>{trashed.todo.title}</label>
+                                    <span class="todo-time">
+                                        {format!("deleted {}", relative_time(trashed.deleted_at, now()))}
+                                    </span>
+                                    <button class="restore" on:click=move |_| on_restore(id)>
+                                        "Restore"
+                                    </button>
+                                </div>
+                            </li>
+                        }
+                    }
+                />
+            </ul>
+            <Show when=is_empty>
+                <p class="trash-empty">"Trash is empty"</p>
+            </Show>
+        </main>
+        <footer class="footer">
+            <A href="/">Back to todos</A>
+            <button
+                class="clear-completed"
+                prop:disabled=is_empty
+                on:click=on_empty
+            >"Empty Trash"</button>
+        </footer>
+    }
+}
@@ -1,33 +1,70 @@
+#[cfg(feature = "ssr")]
+pub mod bridge;
+#[cfg(feature = "ssr")]
+pub mod cache;
 mod components;
+#[cfg(feature = "ssr")]
+pub mod metrics;
 
-use crate::components::HomePage;
+#[cfg(feature = "ssr")]
+use crate::bridge::{
+    AsUser, Cmd, CmdSender, CmdTimeout, Command, Disconnect, InlineExecutor, Overload,
+};
+#[cfg(feature = "ssr")]
+use crate::cache::TodoCache;
+use crate::components::{HomePage, LoginPage, LogoutButton, SignupPage, TrashPage};
 #[cfg(feature = "ssr")]
 use axum::extract::FromRef;
 use leptos::*;
 use leptos_meta::*;
 use leptos_router::*;
 use serde::{Deserialize, Serialize};
-use std::env;
-use std::sync::mpsc::{Receiver, Sender};
-use std::sync::{Arc, Mutex};
+use std::collections::BTreeSet;
+use std::fmt;
+#[cfg(feature = "ssr")]
+use std::sync::mpsc::TrySendError;
+#[cfg(feature = "ssr")]
+use tokio::time;
 
-#[derive(Debug, Clone)]
-#[cfg_attr(feature = "ssr", derive(FromRef))]
+#[cfg(feature = "ssr")]
+#[derive(Debug, Clone, FromRef)]
 pub struct AppState {
-    pub cmd_tx: Sender<Cmd>,
-    pub todo_rx: Arc<Mutex<Receiver<Vec<Todo>>>>,
+    pub cmd_tx: CmdSender,
+    pub cmd_timeout: CmdTimeout,
+    pub todo_cache: TodoCache,
     pub leptos_options: LeptosOptions,
 }
 
-#[derive(Debug, PartialEq, Clone)]
-pub enum Cmd {
-    GetTodos,
-    AddTodo(String),
-    DeleteTodo(u32),
-    ChangeTitle((u32, String)),
-    ChangeCompleted((u32, bool)),
-    ChangeAllCompleted(bool),
-    DeleteCompleted,
+/// User sending the request, provided to server functions by http server
+#[cfg(feature = "ssr")]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CurrentUser(pub u32);
+
+/// Name of the cookie with session token
+#[cfg(feature = "ssr")]
+pub const SESSION_COOKIE: &str = "session";
+
+/// Session of a signed in user, expiry is unix time in seconds
+#[cfg(feature = "ssr")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Session {
+    pub token: String,
+    pub user_id: u32,
+    pub expires_at: u64,
+}
+
+/// Find valid session by token, used by http server to resolve
+/// `CurrentUser`
+#[cfg(feature = "ssr")]
+#[derive(Debug)]
+pub struct FindSession {
+    pub token: String,
+}
+
+#[cfg(feature = "ssr")]
+#[derive(Debug)]
+pub struct DeleteSession {
+    pub token: String,
 }
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
@@ -35,17 +72,234 @@ pub struct Todo {
     pub id: u32,
     pub title: String,
     pub completed: bool,
+    pub owner_id: u32,
+    /// Sort key of todo in the owner's list
+    pub position: u64,
+    /// Unix time in seconds
+    pub created_at: u64,
+    pub updated_at: u64,
+    pub completed_at: Option<u64>,
+    /// Todo not completed by this time is overdue
+    pub due_at: Option<u64>,
+    /// Free-form tags, todos are filtered by tag on `/tag/:name`
+    pub tags: Vec<String>,
+}
+
+/// Deleted todo kept in trash until restored or purged
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct TrashedTodo {
+    pub todo: Todo,
+    /// Unix time in seconds
+    pub deleted_at: u64,
+}
+
+/// Change of a todo in audit log. `before` is `None` for created todo,
+/// `after` is `None` for deleted one.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub id: u32,
+    pub todo_id: u32,
+    /// User made the change
+    pub user_id: u32,
+    /// Operation name, e.g. `create` or `change_title`
+    pub op: String,
+    /// Unix time in seconds
+    pub at: u64,
+    pub before: Option<Todo>,
+    pub after: Option<Todo>,
+}
+
+/// Page of audit entries, recent first. `next` is the cursor of the next
+/// page, `None` on the last page.
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+pub struct AuditPage {
+    pub entries: Vec<AuditEntry>,
+    pub next: Option<u32>,
+}
+
+/// Audit entries loaded by todo history at once
+pub const AUDIT_PAGE_SIZE: u32 = 20;
+
+/// Largest page returned by `get_audit`
+pub const MAX_AUDIT_PAGE_SIZE: u32 = 500;
+
+/// Todos changed by a mutation, merged by client into loaded todos
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+pub struct TodoChanges {
+    /// Created or changed todos in their new state
+    pub changed: Vec<Todo>,
+    /// Ids of deleted todos
+    pub deleted: Vec<u32>,
+}
+
+impl TodoChanges {
+    pub fn changed(changed: Vec<Todo>) -> Self {
+        TodoChanges {
+            changed,
+            deleted: Vec::new(),
+        }
+    }
+
+    pub fn deleted(deleted: Vec<u32>) -> Self {
+        TodoChanges {
+            changed: Vec::new(),
+            deleted,
+        }
+    }
+
+    /// Merge changes into `todos`, loaded in position order up to the
+    /// cursor `next` of the following page. Todos not loaded yet are
+    /// after the last loaded one, so changed todos past it are left to
+    /// the following pages and the cursor is moved to the new last todo.
+    /// Returns `true` when no loaded todo is left while more todos
+    /// exist, then todos have to be loaded again.
+    pub fn apply(self, todos: &mut Vec<Todo>, next: &mut Option<u32>) -> bool {
+        let end = next.map(|_| todos.last().map_or(0, |t| t.position));
+        let replaced: BTreeSet<u32> = self
+            .deleted
+            .into_iter()
+            .chain(self.changed.iter().map(|t| t.id))
+            .collect();
+
+        todos.retain(|t| !replaced.contains(&t.id));
+        todos.extend(
+            self.changed
+                .into_iter()
+                .filter(|t| end.map_or(true, |end| t.position <= end)),
+        );
+        todos.sort_by_key(|t| (t.position, t.id));
+
+        if next.is_none() {
+            return false;
+        }
+        *next = todos.last().map(|t| t.id);
+        next.is_none()
+    }
+}
+
+/// Todos changed by destructive action, the action is undone with
+/// `undo_token` for a short time
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct UndoableChanges {
+    pub changes: TodoChanges,
+    pub undo_token: String,
+}
+
+/// Page of todos in position order. `next` is the cursor of the next
+/// page, `None` on the last page.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct TodoPage {
+    pub todos: Vec<Todo>,
+    pub next: Option<u32>,
+}
+
+/// Number of active and completed todos of a user
+#[derive(Debug, Default, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub struct TodoCounts {
+    pub active: usize,
+    pub completed: usize,
+}
+
+/// Todos loaded by `HomePage` at once
+pub const TODO_PAGE_SIZE: u32 = 50;
+
+/// Largest page returned by `get_todo_page`
+pub const MAX_TODO_PAGE_SIZE: u32 = 500;
+
+/// error returned by server functions, serialized into `ServerFnError`
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub enum AppErr {
+    SpaceNotFound(String),
+    FieldNotExist(String),
+    EmptyTodoTitle,
+    EmptyTag,
+    EmptyUserName,
+    EmptyPassword,
+    UserExists(String),
+    InvalidCredentials,
+    UserNotFound(u32),
+    TodoNotFound(u32),
+    UndoNotFound,
+    Database(String),
+    Unavailable,
+    Overloaded,
+    Unauthorized,
+    Bridge(String),
+}
+
+impl fmt::Display for AppErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AppErr::SpaceNotFound(space) => write!(f, "space {space} not found"),
+            AppErr::FieldNotExist(field) => write!(f, "field {field} not exist"),
+            AppErr::EmptyTodoTitle => write!(f, "empty todo title"),
+            AppErr::EmptyTag => write!(f, "empty tag"),
+            AppErr::EmptyUserName => write!(f, "empty user name"),
+            AppErr::EmptyPassword => write!(f, "empty password"),
+            AppErr::UserExists(name) => write!(f, "user {name} already exists"),
+            AppErr::InvalidCredentials => write!(f, "invalid user name or password"),
+            AppErr::UserNotFound(id) => write!(f, "user {id} not found"),
+            AppErr::TodoNotFound(id) => write!(f, "todo {id} not found"),
+            AppErr::UndoNotFound => write!(f, "nothing to undo"),
+            AppErr::Database(msg) => write!(f, "database error: {msg}"),
+            AppErr::Unavailable => write!(f, "database busy or unavailable"),
+            AppErr::Overloaded => write!(f, "server overloaded, retry later"),
+            AppErr::Unauthorized => write!(f, "sign in required"),
+            AppErr::Bridge(msg) => write!(f, "bridge error: {msg}"),
+        }
+    }
+}
+
+impl From<AppErr> for ServerFnError {
+    fn from(err: AppErr) -> ServerFnError {
+        match serde_json::to_string(&err) {
+            Ok(json) => ServerFnError::ServerError(json),
+            Err(_) => ServerFnError::ServerError(err.to_string()),
+        }
+    }
+}
+
+impl From<ServerFnError> for AppErr {
+    fn from(err: ServerFnError) -> AppErr {
+        match err {
+            ServerFnError::ServerError(msg) => {
+                serde_json::from_str(&msg).unwrap_or(AppErr::Bridge(msg))
+            }
+            err => AppErr::Bridge(err.to_string()),
+        }
+    }
 }
 
 #[component]
 pub fn App() -> impl IntoView {
     provide_meta_context();
     let (todos, set_todos) = create_signal(Vec::<Todo>::new());
+    let (next, set_next) = create_signal(None::<u32>);
+    // Completion status of loaded todos, `HomePage` reloads them for
+    // its route
+    let loaded = create_rw_signal(None::<bool>);
 
     #[cfg(not(feature = "ssr"))]
     spawn_local(async move {
-        let new_todos = get_todos().await.unwrap();
-        set_todos.set(new_todos);
+        match get_todo_page(None, TODO_PAGE_SIZE, None)
+            .await
+            .map_err(AppErr::from)
+        {
+            // Todos may be reloaded for the route meanwhile
+            Ok(page) if loaded.get_untracked().is_none() => {
+                set_todos.set(page.todos);
+                set_next.set(page.next);
+            }
+            Ok(_) => {}
+            // Sign in pages don't need todos
+            Err(AppErr::Unauthorized) => {
+                let location = window().location();
+                if !matches!(location.pathname().as_deref(), Ok("/login" | "/signup")) {
+                    let _ = location.set_href("/login");
+                }
+            }
+            Err(err) => logging::error!("{err}"),
+        }
     });
 
     view! {
@@ -59,23 +313,36 @@ pub fn App() -> impl IntoView {
                 <Routes>
                     <Route path="/" view=move || {
                         view! {
-                            <HomePage todos=todos set_todos=set_todos/>
+                            <HomePage todos=todos set_todos=set_todos next=next set_next=set_next loaded=loaded/>
                         }
                     }/>
                     <Route path="/active" view=move || {
                         view! {
-                            <HomePage todos=todos set_todos=set_todos/>
+                            <HomePage todos=todos set_todos=set_todos next=next set_next=set_next loaded=loaded/>
                         }
                     }/>
                     <Route path="/completed" view=move || {
                         view! {
-                            <HomePage todos=todos set_todos=set_todos/>
+                            <HomePage todos=todos set_todos=set_todos next=next set_next=set_next loaded=loaded/>
                         }
                     }/>
+                    <Route path="/tag/:name" view=move || {
+                        view! {
+                            <HomePage todos=todos set_todos=set_todos next=next set_next=set_next loaded=loaded/>
+                        }
+                    }/>
+                    <Route path="/trash" view=move || {
+                        view! {
+                            <TrashPage next=next set_todos=set_todos set_next=set_next/>
+                        }
+                    }/>
+                    <Route path="/login" view=LoginPage/>
+                    <Route path="/signup" view=SignupPage/>
                 </Routes>
             </section>
         </Router>
         <footer class="info">
+            <LogoutButton/>
             <p>Double-click to edit a todo</p>
             <p>Created by the TodoMVC Team</p>
             <p>Part of <a href="http://todomvc.com">TodoMVC</a></p>
@@ -83,75 +350,322 @@ pub fn App() -> impl IntoView {
     }
 }
 
-pub fn cmd_tx() -> Result<Sender<Cmd>, ServerFnError> {
-    use_context::<Sender<Cmd>>()
+#[cfg(feature = "ssr")]
+pub fn current_user() -> Result<CurrentUser, ServerFnError> {
+    use_context::<CurrentUser>().ok_or_else(|| AppErr::Unauthorized.into())
+}
+
+#[cfg(feature = "ssr")]
+pub fn cmd_tx() -> Result<CmdSender, ServerFnError> {
+    use_context::<CmdSender>()
         .ok_or_else(|| ServerFnError::ServerError("Sender cmd missing.".into()))
 }
 
-pub fn todo_rx() -> Result<Arc<Mutex<Receiver<Vec<Todo>>>>, ServerFnError> {
-    use_context::<Arc<Mutex<Receiver<Vec<Todo>>>>>()
-        .ok_or_else(|| ServerFnError::ServerError("Receiver missing.".into()))
+/// Send command to executor and wait for its result.
+/// Waiting stops on command timeout or when client disconnects, dropped
+/// reply handle cancels the command if it is still queued.
+/// Command rejected by full queue raises the request `Overload` flag.
+/// On tarantool fibers command is executed in place.
+#[cfg(feature = "ssr")]
+pub async fn exec<C: Command>(cmd: C) -> Result<C::Output, ServerFnError> {
+    if let Some(executor) = use_context::<InlineExecutor>() {
+        return Ok(executor.execute(cmd)?);
+    }
+
+    let cmd_tx = cmd_tx()?;
+    let CmdTimeout(timeout) = use_context::<CmdTimeout>().unwrap_or_default();
+    let disconnect = use_context::<Disconnect>();
+
+    let (cmd, reply_rx) = Cmd::new(cmd);
+    match cmd_tx.send(cmd) {
+        Ok(()) => {}
+        Err(TrySendError::Full(_)) => {
+            if let Some(overload) = use_context::<Overload>() {
+                overload.raise();
+            }
+            return Err(AppErr::Overloaded.into());
+        }
+        Err(TrySendError::Disconnected(_)) => {
+            return Err(AppErr::Bridge("command executor stopped".into()).into())
+        }
+    }
+
+    let disconnected = async move {
+        match disconnect {
+            Some(disconnect) => disconnect.wait().await,
+            None => std::future::pending().await,
+        }
+    };
+    let reply = tokio::select! {
+        reply = time::timeout(timeout, reply_rx) => reply.map_err(|_| AppErr::Unavailable)?,
+        _ = disconnected => return Err(AppErr::Bridge("client disconnected".into()).into()),
+    };
+    let output = reply.map_err(|_| AppErr::Bridge("command dropped without reply".into()))??;
+    Ok(output)
+}
+
+/// Set session cookie of the response, cookie expires with the session
+#[cfg(feature = "ssr")]
+fn set_session_cookie(token: &str, max_age: u64) {
+    use axum::http::header::{HeaderValue, SET_COOKIE};
+
+    if let Some(res) = use_context::<leptos_axum::ResponseOptions>() {
+        let cookie = format!(
+            "{SESSION_COOKIE}={token}; Path=/; Max-Age={max_age}; HttpOnly; Secure; SameSite=Lax"
+        );
+        if let Ok(cookie) = HeaderValue::from_str(&cookie) {
+            res.insert_header(SET_COOKIE, cookie);
+        }
+    }
+}
+
+/// Remember signed in user in session cookie
+#[cfg(feature = "ssr")]
+fn sign_in(session: &Session) {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    set_session_cookie(&session.token, session.expires_at.saturating_sub(now));
+}
+
+/// Execute command on behalf of the current user
+#[cfg(feature = "ssr")]
+pub async fn exec_for_user<C: Command>(cmd: C) -> Result<C::Output, ServerFnError> {
+    let CurrentUser(user_id) = current_user()?;
+    exec(AsUser { user_id, cmd }).await
 }
 
 #[server(GetTodos, "/api")]
 pub async fn get_todos() -> Result<Vec<Todo>, ServerFnError> {
-    let cmd_tx = cmd_tx()?;
-    let _ = cmd_tx.send(Cmd::GetTodos);
-    let todo_rx = todo_rx()?;
-    let todos = todo_rx.lock().unwrap().recv().unwrap();
-    Ok(todos)
+    let CurrentUser(user_id) = current_user()?;
+    // Cache is maintained by tarantool triggers, so reads don't wait for executor
+    match use_context::<TodoCache>() {
+        Some(todo_cache) => Ok(todo_cache.todos(user_id)),
+        None => exec_for_user(GetTodos {}).await,
+    }
+}
+
+/// Page of at most `limit` todos after todo `after_id`, only todos of
+/// `completed` status when it is set
+#[server(GetTodoPage, "/api")]
+pub async fn get_todo_page(
+    after_id: Option<u32>,
+    limit: u32,
+    completed: Option<bool>,
+) -> Result<TodoPage, ServerFnError> {
+    let CurrentUser(user_id) = current_user()?;
+    let limit = limit.clamp(1, MAX_TODO_PAGE_SIZE);
+    match use_context::<TodoCache>() {
+        Some(todo_cache) => todo_cache
+            .todo_page(user_id, after_id, limit as usize, completed)
+            .ok_or_else(|| AppErr::TodoNotFound(after_id.unwrap_or_default()).into()),
+        None => {
+            exec_for_user(GetTodoPage {
+                after_id,
+                limit,
+                completed,
+            })
+            .await
+        }
+    }
+}
+
+/// Counts of all todos, not only of loaded pages
+#[server(GetTodoCounts, "/api")]
+pub async fn get_todo_counts() -> Result<TodoCounts, ServerFnError> {
+    let CurrentUser(user_id) = current_user()?;
+    match use_context::<TodoCache>() {
+        Some(todo_cache) => Ok(todo_cache.todo_counts(user_id)),
+        None => exec_for_user(GetTodoCounts {}).await,
+    }
+}
+
+#[server(GetTaggedTodos, "/api")]
+pub async fn get_tagged_todos(tag: String) -> Result<Vec<Todo>, ServerFnError> {
+    let CurrentUser(user_id) = current_user()?;
+    match use_context::<TodoCache>() {
+        Some(todo_cache) => Ok(todo_cache.tagged_todos(user_id, &tag)),
+        None => exec_for_user(GetTaggedTodos { tag }).await,
+    }
+}
+
+#[server(SearchTodos, "/api")]
+pub async fn search_todos(query: String) -> Result<Vec<Todo>, ServerFnError> {
+    exec_for_user(SearchTodos { query }).await
 }
 
 #[server(AddTodo, "/api")]
-pub async fn add_todo(title: String) -> Result<Vec<Todo>, ServerFnError> {
-    let cmd_tx = cmd_tx()?;
-    let _ = cmd_tx.send(Cmd::AddTodo(title));
-    let todo_rx = todo_rx()?;
-    let todos = todo_rx.lock().unwrap().recv().unwrap();
-    Ok(todos)
+pub async fn add_todo(title: String) -> Result<TodoChanges, ServerFnError> {
+    exec_for_user(AddTodo { title }).await
 }
 
 #[server(DeleteTodo, "/api")]
-pub async fn delete_todo(id: u32) -> Result<Vec<Todo>, ServerFnError> {
-    let cmd_tx = cmd_tx()?;
-    let _ = cmd_tx.send(Cmd::DeleteTodo(id));
-    let todo_rx = todo_rx()?;
-    let todos = todo_rx.lock().unwrap().recv().unwrap();
-    Ok(todos)
+pub async fn delete_todo(id: u32) -> Result<UndoableChanges, ServerFnError> {
+    exec_for_user(DeleteTodo { id }).await
 }
 
 #[server(ChangeTitle, "/api")]
-pub async fn change_title(id: u32, title: String) -> Result<Vec<Todo>, ServerFnError> {
-    let cmd_tx = cmd_tx()?;
-    let _ = cmd_tx.send(Cmd::ChangeTitle((id, title)));
-    let todo_rx = todo_rx()?;
-    let todos = todo_rx.lock().unwrap().recv().unwrap();
-    Ok(todos)
+pub async fn change_title(id: u32, title: String) -> Result<TodoChanges, ServerFnError> {
+    exec_for_user(ChangeTitle { id, title }).await
 }
 
 #[server(ChangeCompleted, "/api")]
-pub async fn change_completed(id: u32, completed: bool) -> Result<Vec<Todo>, ServerFnError> {
-    let cmd_tx = cmd_tx()?;
-    let _ = cmd_tx.send(Cmd::ChangeCompleted((id, completed)));
-    let todo_rx = todo_rx()?;
-    let todos = todo_rx.lock().unwrap().recv().unwrap();
-    Ok(todos)
+pub async fn change_completed(id: u32, completed: bool) -> Result<TodoChanges, ServerFnError> {
+    exec_for_user(ChangeCompleted { id, completed }).await
+}
+
+#[server(ChangeDue, "/api")]
+pub async fn change_due(id: u32, due_at: Option<u64>) -> Result<TodoChanges, ServerFnError> {
+    exec_for_user(ChangeDue { id, due_at }).await
+}
+
+#[server(AddTag, "/api")]
+pub async fn add_tag(id: u32, tag: String) -> Result<TodoChanges, ServerFnError> {
+    exec_for_user(AddTag { id, tag }).await
+}
+
+#[server(RemoveTag, "/api")]
+pub async fn remove_tag(id: u32, tag: String) -> Result<TodoChanges, ServerFnError> {
+    exec_for_user(RemoveTag { id, tag }).await
+}
+
+#[server(GetTrash, "/api")]
+pub async fn get_trash() -> Result<Vec<TrashedTodo>, ServerFnError> {
+    exec_for_user(GetTrash {}).await
+}
+
+/// Page of changes of todos made by the user, newest first, after entry
+/// `after_id`. Only changes of todo `todo_id` are listed when it is set,
+/// `from` and `to` limit change time, both inclusive.
+#[server(GetAudit, "/api")]
+pub async fn get_audit(
+    todo_id: Option<u32>,
+    from: Option<u64>,
+    to: Option<u64>,
+    after_id: Option<u32>,
+    limit: u32,
+) -> Result<AuditPage, ServerFnError> {
+    let limit = limit.clamp(1, MAX_AUDIT_PAGE_SIZE);
+    exec_for_user(GetAudit {
+        todo_id,
+        from,
+        to,
+        after_id,
+        limit,
+    })
+    .await
+}
+
+#[server(RestoreTodo, "/api")]
+pub async fn restore_todo(id: u32) -> Result<TodoChanges, ServerFnError> {
+    exec_for_user(RestoreTodo { id }).await
+}
+
+#[server(EmptyTrash, "/api")]
+pub async fn empty_trash() -> Result<(), ServerFnError> {
+    exec_for_user(EmptyTrash {}).await
+}
+
+#[server(MoveTodo, "/api")]
+pub async fn move_todo(id: u32, before_id: Option<u32>) -> Result<TodoChanges, ServerFnError> {
+    exec_for_user(MoveTodo { id, before_id }).await
 }
 
 #[server(ChangeAllCompleted, "/api")]
-pub async fn change_all_completed(completed: bool) -> Result<Vec<Todo>, ServerFnError> {
-    let cmd_tx = cmd_tx()?;
-    let _ = cmd_tx.send(Cmd::ChangeAllCompleted(completed));
-    let todo_rx = todo_rx()?;
-    let todos = todo_rx.lock().unwrap().recv().unwrap();
-    Ok(todos)
+pub async fn change_all_completed(completed: bool) -> Result<UndoableChanges, ServerFnError> {
+    exec_for_user(ChangeAllCompleted { completed }).await
 }
 
 #[server(DeleteCompleted, "/api")]
-pub async fn delete_completed() -> Result<Vec<Todo>, ServerFnError> {
-    let cmd_tx = cmd_tx()?;
-    let _ = cmd_tx.send(Cmd::DeleteCompleted);
-    let todo_rx = todo_rx()?;
-    let todos = todo_rx.lock().unwrap().recv().unwrap();
-    Ok(todos)
+pub async fn delete_completed() -> Result<UndoableChanges, ServerFnError> {
+    exec_for_user(DeleteCompleted {}).await
 }
+
+#[server(Undo, "/api")]
+pub async fn undo(token: String) -> Result<TodoChanges, ServerFnError> {
+    exec_for_user(Undo { token }).await
+}
+
+#[server(Signup, "/api")]
+pub async fn signup(name: String, password: String) -> Result<u32, ServerFnError> {
+    let session = exec(Signup { name, password }).await?;
+    sign_in(&session);
+    Ok(session.user_id)
+}
+
+#[server(Login, "/api")]
+pub async fn login(name: String, password: String) -> Result<u32, ServerFnError> {
+    let session = exec(Login { name, password }).await?;
+    sign_in(&session);
+    Ok(session.user_id)
+}
+
+#[server(Logout, "/api")]
+pub async fn logout() -> Result<(), ServerFnError> {
+    if let Some(session) = use_context::<Session>() {
+        exec(DeleteSession {
+            token: session.token,
+        })
+        .await?;
+    }
+    set_session_cookie("", 0);
+    Ok(())
+}
+
+#[server(ChangePassword, "/api")]
+pub async fn change_password(current: String, password: String) -> Result<(), ServerFnError> {
+    exec_for_user(ChangePassword { current, password }).await
+}
+
+/// Calls macro `$m` with every command executed by tarantool, as
+/// `[kind] Command => Output` entries. Commands of `user` kind are
+/// executed on behalf of the signed in user, as `AsUser<Command>`.
+/// `Command` impls and executor handler registrations are generated from
+/// this list, so an operation is declared once besides its server fn.
+#[macro_export]
+macro_rules! for_each_command {
+    ($m:ident) => {
+        $m! {
+            [user] GetTodos => Vec<Todo>,
+            [user] GetTodoPage => TodoPage,
+            [user] GetTodoCounts => TodoCounts,
+            [user] GetTaggedTodos => Vec<Todo>,
+            [user] SearchTodos => Vec<Todo>,
+            [user] AddTodo => TodoChanges,
+            [user] DeleteTodo => UndoableChanges,
+            [user] ChangeTitle => TodoChanges,
+            [user] ChangeCompleted => TodoChanges,
+            [user] ChangeDue => TodoChanges,
+            [user] AddTag => TodoChanges,
+            [user] RemoveTag => TodoChanges,
+            [user] MoveTodo => TodoChanges,
+            [user] ChangeAllCompleted => UndoableChanges,
+            [user] DeleteCompleted => UndoableChanges,
+            [user] Undo => TodoChanges,
+            [user] GetTrash => Vec<TrashedTodo>,
+            [user] RestoreTodo => TodoChanges,
+            [user] EmptyTrash => (),
+            [user] GetAudit => AuditPage,
+            [public] Signup => Session,
+            [public] Login => Session,
+            [user] ChangePassword => (),
+            [public] FindSession => Option<Session>,
+            [public] DeleteSession => (),
+        }
+    };
+}
+
+/// Implement `Command` for every command of `for_each_command`
+#[cfg(feature = "ssr")]
+macro_rules! impl_commands {
+    ($([$kind:ident] $cmd:ident => $out:ty),* $(,)?) => {
+        $(
+            impl Command for $cmd {
+                type Output = $out;
+            }
+        )*
+    };
+}
+
+#[cfg(feature = "ssr")]
+for_each_command!(impl_commands);
@@ -0,0 +1,138 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Counters of a single command kind
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct KindStats {
+    /// Number of executed commands
+    pub count: u64,
+    /// Total time commands spent in the queue
+    pub wait: Duration,
+    /// Total time of command execution
+    pub processing: Duration,
+}
+
+/// Metrics of command queue, shared by http server and command executor
+#[derive(Debug, Default)]
+pub struct CmdMetrics {
+    capacity: usize,
+    depth: AtomicUsize,
+    rejected: AtomicU64,
+    kinds: Mutex<BTreeMap<&'static str, KindStats>>,
+}
+
+impl CmdMetrics {
+    pub fn new(capacity: usize) -> Self {
+        CmdMetrics {
+            capacity,
+            ..Default::default()
+        }
+    }
+
+    /// Maximum number of queued commands
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Number of queued commands
+    pub fn depth(&self) -> usize {
+        self.depth.load(Ordering::Relaxed)
+    }
+
+    /// Number of commands rejected because queue was full
+    pub fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+
+    /// Counters by command kind
+    pub fn kinds(&self) -> BTreeMap<&'static str, KindStats> {
+        self.kinds.lock().map(|k| k.clone()).unwrap_or_default()
+    }
+
+    pub(crate) fn enqueue(&self) {
+        self.depth.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn dequeue(&self) {
+        self.depth.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn reject(&self) {
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record executed command of kind `name`
+    pub fn observe(&self, name: &'static str, wait: Duration, processing: Duration) {
+        if let Ok(mut kinds) = self.kinds.lock() {
+            let stats = kinds.entry(name).or_default();
+            stats.count += 1;
+            stats.wait += wait;
+            stats.processing += processing;
+        }
+    }
+
+    /// Metrics in prometheus text format
+    pub fn render(&self) -> String {
+        let kinds = self.kinds();
+        let mut out = String::new();
+
+        let _ = writeln!(out, "# TYPE cmd_queue_capacity gauge");
+        let _ = writeln!(out, "cmd_queue_capacity {}", self.capacity());
+        let _ = writeln!(out, "# TYPE cmd_queue_depth gauge");
+        let _ = writeln!(out, "cmd_queue_depth {}", self.depth());
+        let _ = writeln!(out, "# TYPE cmd_rejected_total counter");
+        let _ = writeln!(out, "cmd_rejected_total {}", self.rejected());
+
+        let _ = writeln!(out, "# TYPE cmd_total counter");
+        for (kind, stats) in &kinds {
+            let _ = writeln!(out, "cmd_total{{kind=\"{kind}\"}} {}", stats.count);
+        }
+        let _ = writeln!(out, "# TYPE cmd_wait_seconds_total counter");
+        for (kind, stats) in &kinds {
+            let wait = stats.wait.as_secs_f64();
+            let _ = writeln!(out, "cmd_wait_seconds_total{{kind=\"{kind}\"}} {wait}");
+        }
+        let _ = writeln!(out, "# TYPE cmd_processing_seconds_total counter");
+        for (kind, stats) in &kinds {
+            let processing = stats.processing.as_secs_f64();
+            let _ = writeln!(
+                out,
+                "cmd_processing_seconds_total{{kind=\"{kind}\"}} {processing}"
+            );
+        }
+
+        out
+    }
+}
+
+/// Bearer token required to read metrics. Configured with `METRICS_TOKEN`
+/// environment variable, metrics are not served when it is not set.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MetricsToken(pub Option<String>);
+
+impl MetricsToken {
+    pub fn from_env() -> Self {
+        MetricsToken(env::var("METRICS_TOKEN").ok().filter(|t| !t.is_empty()))
+    }
+
+    /// Check value of `Authorization` header. Tokens are compared in
+    /// constant time.
+    pub fn allows(&self, authorization: Option<&str>) -> bool {
+        let (Some(token), Some(bearer)) = (
+            &self.0,
+            authorization.and_then(|auth| auth.strip_prefix("Bearer ")),
+        ) else {
+            return false;
+        };
+        token.len() == bearer.len()
+            && token
+                .bytes()
+                .zip(bearer.bytes())
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    }
+}
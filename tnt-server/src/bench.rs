@@ -0,0 +1,117 @@
+use crate::dispatcher::Dispatcher;
+use crate::lifecycle;
+use crate::repo::{clock, session};
+use crate::wait_readable;
+use core::time::Duration;
+use front_app::bridge::{self, AsUser, Cmd};
+use front_app::{GetTodos, SESSION_COOKIE};
+use leptos::server_fn::ServerFn;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::os::unix::io::RawFd;
+use std::time::Instant;
+use tarantool::fiber;
+
+const ROUND_TRIPS: u32 = 200;
+const HTTP_REQUESTS: u32 = 200;
+
+/// Http server execution mode
+const HTTP_MODE: &str = if cfg!(feature = "fiber-http") {
+    "on fibers"
+} else {
+    "on tokio thread"
+};
+
+/// Mean round trip of `GetTodos` commands sent from a foreign thread,
+/// while executor fiber waits for commands with `wait`.
+fn round_trip<W>(mut wait: W) -> Duration
+where
+    W: FnMut(RawFd) + 'static,
+{
+    let (cmd_tx, cmd_rx) = bridge::channel(1).unwrap();
+    let executor = fiber::start(move || {
+        let dispatcher = Dispatcher::app();
+        while let Some(cmd) = cmd_rx.recv_with(&mut wait) {
+            dispatcher.dispatch(cmd);
+        }
+    });
+
+    let client = std::thread::spawn(move || {
+        let start = Instant::now();
+        for _ in 0..ROUND_TRIPS {
+            let (cmd, reply_rx) = Cmd::new(AsUser {
+                user_id: 0,
+                cmd: GetTodos {},
+            });
+            cmd_tx.send(cmd).unwrap();
+            reply_rx.blocking_recv().unwrap().unwrap();
+        }
+        start.elapsed() / ROUND_TRIPS
+    });
+
+    // Keep tx thread free for executor fiber until client is done
+    while !client.is_finished() {
+        fiber::sleep(Duration::from_millis(1));
+    }
+    executor.join();
+
+    client.join().unwrap()
+}
+
+/// Requests per second served by running application on an ephemeral
+/// port. Requests for a page and for todos are sent one after another
+/// from a foreign thread.
+fn http_throughput() -> f64 {
+    let todos_path = format!("{}/{}", GetTodos::prefix(), GetTodos::url());
+    let session = session::create_session(0, clock::now(), Duration::from_secs(3600)).unwrap();
+    let addr = lifecycle::start_on(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+
+    let client = std::thread::spawn(move || {
+        let start = Instant::now();
+        for i in 0..HTTP_REQUESTS {
+            let path = if i % 2 == 0 { "/" } else { &todos_path };
+            let response = http_get(addr, path, &session.token);
+            assert!(response.starts_with(b"HTTP/1.1 200"));
+        }
+        HTTP_REQUESTS as f64 / start.elapsed().as_secs_f64()
+    });
+
+    // Keep tx thread free for application fibers until client is done
+    while !client.is_finished() {
+        fiber::sleep(Duration::from_millis(1));
+    }
+    lifecycle::stop().unwrap();
+
+    client.join().unwrap()
+}
+
+/// Send GET request on a new connection and read the whole response
+fn http_get(addr: SocketAddr, path: &str, token: &str) -> Vec<u8> {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(
+        stream,
+        "GET {path} HTTP/1.1\r\nHost: {addr}\r\nCookie: {SESSION_COOKIE}={token}\r\nConnection: close\r\n\r\n"
+    )
+    .unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+    response
+}
+
+mod tests {
+    use super::*;
+
+    #[tarantool_test::test]
+    fn command_round_trip() {
+        let polling = round_trip(|_| fiber::sleep(Duration::from_millis(10)));
+        let event_driven = round_trip(wait_readable);
+        println!("command round trip: polling {polling:?}, event driven {event_driven:?}");
+    }
+
+    #[tarantool_test::test]
+    fn http_requests_per_second() {
+        let throughput = http_throughput();
+        println!("http {HTTP_MODE}: {throughput:.0} requests per second");
+        assert!(throughput > 0.0);
+    }
+}
@@ -0,0 +1,390 @@
+use crate::reminder;
+use crate::repo::audit::list_audit;
+use crate::repo::clock;
+use crate::repo::search::search_todos;
+use crate::repo::session::{self, create_session, delete_session, find_session};
+use crate::repo::todo::{
+    add_tag, change_completed, change_due, change_title, count_todos, create_todo, list_todos,
+    list_todos_by_tag, list_todos_page, move_todo, remove_tag,
+};
+use crate::repo::trash::{empty_trash, list_trash, restore_todo};
+use crate::repo::undo::{
+    undo, undoable_change_all_completed, undoable_delete_completed, undoable_delete_todo,
+};
+use crate::repo::user::{change_password, create_user, verify_credentials};
+use crate::repo::RepoErr;
+use front_app::bridge::{AsUser, Cmd, Command, Job};
+use front_app::{
+    AddTag, AddTodo, AppErr, AuditPage, ChangeAllCompleted, ChangeCompleted, ChangeDue,
+    ChangePassword, ChangeTitle, DeleteCompleted, DeleteSession, DeleteTodo, EmptyTrash,
+    FindSession, GetAudit, GetTaggedTodos, GetTodoCounts, GetTodoPage, GetTodos, GetTrash, Login,
+    MoveTodo, RemoveTag, RestoreTodo, SearchTodos, Session, Signup, Todo, TodoChanges, TodoCounts,
+    TodoPage, TrashedTodo, Undo, UndoableChanges,
+};
+use std::any::TypeId;
+use std::collections::HashMap;
+
+/// Repo operation executed for a command
+pub trait Handler: Command {
+    /// Read-only operations may run concurrently with other operations,
+    /// so may operations writing by single autocommit statements
+    const READ_ONLY: bool = false;
+
+    fn handle(self) -> Result<Self::Output, RepoErr>;
+}
+
+/// Registered handler of a command type
+struct Registration {
+    run: fn(Cmd),
+    read_only: bool,
+}
+
+/// Dispatcher with a handler of every command listed by
+/// `for_each_command`, commands of `user` kind are handled as `AsUser`.
+/// Command without `Handler` impl fails to compile.
+macro_rules! register_commands {
+    (@handler user $cmd:ident) => { AsUser<$cmd> };
+    (@handler public $cmd:ident) => { $cmd };
+    ($([$kind:ident] $cmd:ident => $out:ty),* $(,)?) => {
+        Dispatcher::default()
+            $(.register::<register_commands!(@handler $kind $cmd)>())*
+    };
+}
+
+/// Routes commands to handlers registered by command type
+#[derive(Default)]
+pub struct Dispatcher {
+    handlers: HashMap<TypeId, Registration>,
+}
+
+impl Dispatcher {
+    /// Dispatcher with all application operations registered
+    pub fn app() -> Self {
+        front_app::for_each_command!(register_commands)
+    }
+
+    pub fn register<H: Handler>(mut self) -> Self {
+        let registration = Registration {
+            run: run::<H>,
+            read_only: H::READ_ONLY,
+        };
+        self.handlers.insert(TypeId::of::<H>(), registration);
+        self
+    }
+
+    /// Check if command is handled by read-only operation
+    pub fn is_read_only(&self, cmd: &Cmd) -> bool {
+        self.handlers
+            .get(&cmd.kind())
+            .is_some_and(|registration| registration.read_only)
+    }
+
+    /// Execute command and send result to its reply handle.
+    /// Commands nobody waits for anymore are skipped. Command without
+    /// handler is dropped, so the requester gets a bridge error.
+    pub fn dispatch(&self, cmd: Cmd) {
+        if cmd.is_cancelled() {
+            return;
+        }
+
+        match self.handlers.get(&cmd.kind()) {
+            Some(registration) => (registration.run)(cmd),
+            None => eprintln!("no handler for command {}", cmd.name()),
+        }
+    }
+}
+
+fn run<H: Handler>(cmd: Cmd) {
+    if let Ok(Job { cmd, reply }) = cmd.downcast::<H>() {
+        let _ = reply.send(cmd.handle().map_err(AppErr::from));
+    }
+}
+
+impl Handler for AsUser<GetTodos> {
+    const READ_ONLY: bool = true;
+
+    fn handle(self) -> Result<Vec<Todo>, RepoErr> {
+        list_todos(self.user_id)
+    }
+}
+
+impl Handler for AsUser<GetTodoPage> {
+    const READ_ONLY: bool = true;
+
+    fn handle(self) -> Result<TodoPage, RepoErr> {
+        let GetTodoPage {
+            after_id,
+            limit,
+            completed,
+        } = self.cmd;
+        list_todos_page(self.user_id, after_id, limit as usize, completed)
+    }
+}
+
+impl Handler for AsUser<GetTodoCounts> {
+    const READ_ONLY: bool = true;
+
+    fn handle(self) -> Result<TodoCounts, RepoErr> {
+        count_todos(self.user_id)
+    }
+}
+
+impl Handler for AsUser<GetTaggedTodos> {
+    const READ_ONLY: bool = true;
+
+    fn handle(self) -> Result<Vec<Todo>, RepoErr> {
+        list_todos_by_tag(self.user_id, &self.cmd.tag)
+    }
+}
+
+impl Handler for AsUser<SearchTodos> {
+    const READ_ONLY: bool = true;
+
+    fn handle(self) -> Result<Vec<Todo>, RepoErr> {
+        search_todos(self.user_id, &self.cmd.query)
+    }
+}
+
+impl Handler for AsUser<AddTodo> {
+    fn handle(self) -> Result<TodoChanges, RepoErr> {
+        let todo = create_todo(self.user_id, &self.cmd.title)?;
+        Ok(TodoChanges::changed(vec![todo]))
+    }
+}
+
+impl Handler for AsUser<DeleteTodo> {
+    fn handle(self) -> Result<UndoableChanges, RepoErr> {
+        undoable_delete_todo(self.user_id, self.cmd.id)
+    }
+}
+
+impl Handler for AsUser<ChangeTitle> {
+    fn handle(self) -> Result<TodoChanges, RepoErr> {
+        let todo = change_title(self.user_id, self.cmd.id, &self.cmd.title)?;
+        Ok(TodoChanges::changed(vec![todo]))
+    }
+}
+
+impl Handler for AsUser<ChangeCompleted> {
+    fn handle(self) -> Result<TodoChanges, RepoErr> {
+        let todo = change_completed(self.user_id, self.cmd.id, self.cmd.completed)?;
+        reminder::recheck(std::slice::from_ref(&todo));
+        Ok(TodoChanges::changed(vec![todo]))
+    }
+}
+
+impl Handler for AsUser<ChangeDue> {
+    fn handle(self) -> Result<TodoChanges, RepoErr> {
+        let todo = change_due(self.user_id, self.cmd.id, self.cmd.due_at)?;
+        reminder::recheck(std::slice::from_ref(&todo));
+        Ok(TodoChanges::changed(vec![todo]))
+    }
+}
+
+impl Handler for AsUser<AddTag> {
+    fn handle(self) -> Result<TodoChanges, RepoErr> {
+        let todo = add_tag(self.user_id, self.cmd.id, &self.cmd.tag)?;
+        Ok(TodoChanges::changed(vec![todo]))
+    }
+}
+
+impl Handler for AsUser<RemoveTag> {
+    fn handle(self) -> Result<TodoChanges, RepoErr> {
+        let todo = remove_tag(self.user_id, self.cmd.id, &self.cmd.tag)?;
+        Ok(TodoChanges::changed(vec![todo]))
+    }
+}
+
+impl Handler for AsUser<MoveTodo> {
+    fn handle(self) -> Result<TodoChanges, RepoErr> {
+        let moved = move_todo(self.user_id, self.cmd.id, self.cmd.before_id)?;
+        Ok(TodoChanges::changed(moved))
+    }
+}
+
+impl Handler for AsUser<ChangeAllCompleted> {
+    fn handle(self) -> Result<UndoableChanges, RepoErr> {
+        let changes = undoable_change_all_completed(self.user_id, self.cmd.completed)?;
+        reminder::recheck(&changes.changes.changed);
+        Ok(changes)
+    }
+}
+
+impl Handler for AsUser<DeleteCompleted> {
+    fn handle(self) -> Result<UndoableChanges, RepoErr> {
+        undoable_delete_completed(self.user_id)
+    }
+}
+
+impl Handler for AsUser<Undo> {
+    fn handle(self) -> Result<TodoChanges, RepoErr> {
+        let changes = undo(self.user_id, &self.cmd.token)?;
+        reminder::recheck(&changes.changed);
+        Ok(changes)
+    }
+}
+
+impl Handler for AsUser<GetTrash> {
+    const READ_ONLY: bool = true;
+
+    fn handle(self) -> Result<Vec<TrashedTodo>, RepoErr> {
+        list_trash(self.user_id)
+    }
+}
+
+impl Handler for AsUser<RestoreTodo> {
+    fn handle(self) -> Result<TodoChanges, RepoErr> {
+        let todo = restore_todo(self.user_id, self.cmd.id)?;
+        reminder::recheck(std::slice::from_ref(&todo));
+        Ok(TodoChanges::changed(vec![todo]))
+    }
+}
+
+impl Handler for AsUser<EmptyTrash> {
+    fn handle(self) -> Result<(), RepoErr> {
+        empty_trash(self.user_id).map(|_| ())
+    }
+}
+
+impl Handler for AsUser<GetAudit> {
+    const READ_ONLY: bool = true;
+
+    fn handle(self) -> Result<AuditPage, RepoErr> {
+        let GetAudit {
+            todo_id,
+            from,
+            to,
+            after_id,
+            limit,
+        } = self.cmd;
+        list_audit(self.user_id, todo_id, from, to, after_id, limit as usize)
+    }
+}
+
+// Password is hashed without write lock, user and session are
+// inserted by separate statements
+impl Handler for Signup {
+    const READ_ONLY: bool = true;
+
+    fn handle(self) -> Result<Session, RepoErr> {
+        let user = create_user(&self.name, &self.password)?;
+        create_session(user.id, clock::now(), session::ttl_from_env())
+    }
+}
+
+impl Handler for Login {
+    const READ_ONLY: bool = true;
+
+    fn handle(self) -> Result<Session, RepoErr> {
+        let user = verify_credentials(&self.name, &self.password)?;
+        create_session(user.id, clock::now(), session::ttl_from_env())
+    }
+}
+
+impl Handler for AsUser<ChangePassword> {
+    const READ_ONLY: bool = true;
+
+    fn handle(self) -> Result<(), RepoErr> {
+        change_password(self.user_id, &self.cmd.current, &self.cmd.password)
+    }
+}
+
+impl Handler for FindSession {
+    const READ_ONLY: bool = true;
+
+    fn handle(self) -> Result<Option<Session>, RepoErr> {
+        find_session(&self.token, clock::now())
+    }
+}
+
+impl Handler for DeleteSession {
+    fn handle(self) -> Result<(), RepoErr> {
+        delete_session(&self.token)
+    }
+}
+
+#[cfg(feature = "test")]
+mod tests {
+    use super::*;
+
+    const USER_ID: u32 = 3001;
+
+    fn as_user<C>(cmd: C) -> AsUser<C> {
+        AsUser {
+            user_id: USER_ID,
+            cmd,
+        }
+    }
+
+    #[tarantool_test::test]
+    fn dispatch_command() {
+        let title = "Dispatched todo".to_string();
+        let (cmd, mut reply_rx) = Cmd::new(as_user(AddTodo {
+            title: title.clone(),
+        }));
+        Dispatcher::app().dispatch(cmd);
+        let result = reply_rx.try_recv().unwrap().unwrap();
+        assert!(result.changed.iter().any(|t| t.title == title));
+    }
+
+    #[tarantool_test::test]
+    fn dispatch_error() {
+        let (cmd, mut reply_rx) = Cmd::new(as_user(AddTodo {
+            title: String::new(),
+        }));
+        Dispatcher::app().dispatch(cmd);
+        let result = reply_rx.try_recv().unwrap();
+        assert_eq!(result, Err(AppErr::EmptyTodoTitle));
+    }
+
+    #[tarantool_test::test]
+    fn skip_cancelled_command() {
+        let title = "Cancelled todo".to_string();
+        let (cmd, reply_rx) = Cmd::new(as_user(AddTodo {
+            title: title.clone(),
+        }));
+        drop(reply_rx);
+        Dispatcher::app().dispatch(cmd);
+        let result = list_todos(USER_ID).unwrap();
+        assert!(!result.iter().any(|t| t.title == title));
+    }
+
+    #[tarantool_test::test]
+    fn dispatch_login() {
+        let name = "Dispatched user".to_string();
+        let password = "password".to_string();
+        let (cmd, mut reply_rx) = Cmd::new(Signup {
+            name: name.clone(),
+            password: password.clone(),
+        });
+        Dispatcher::app().dispatch(cmd);
+        let signup_session = reply_rx.try_recv().unwrap().unwrap();
+
+        let (cmd, mut reply_rx) = Cmd::new(Login { name, password });
+        Dispatcher::app().dispatch(cmd);
+        let session = reply_rx.try_recv().unwrap().unwrap();
+        assert_eq!(session.user_id, signup_session.user_id);
+
+        let (cmd, mut reply_rx) = Cmd::new(FindSession {
+            token: session.token.clone(),
+        });
+        Dispatcher::app().dispatch(cmd);
+        assert_eq!(reply_rx.try_recv().unwrap(), Ok(Some(session)));
+    }
+
+    #[tarantool_test::test]
+    fn read_only_command() {
+        let dispatcher = Dispatcher::app();
+        let (read_cmd, _) = Cmd::new(as_user(GetTodos {}));
+        let (write_cmd, _) = Cmd::new(as_user(DeleteCompleted {}));
+        assert!(dispatcher.is_read_only(&read_cmd));
+        assert!(!dispatcher.is_read_only(&write_cmd));
+    }
+
+    #[tarantool_test::test]
+    fn drop_unregistered_command() {
+        let (cmd, mut reply_rx) = Cmd::new(as_user(GetTodos {}));
+        Dispatcher::default().dispatch(cmd);
+        assert!(reply_rx.try_recv().is_err());
+    }
+}
@@ -0,0 +1,549 @@
+use crate::dispatcher::Dispatcher;
+use crate::pool;
+use crate::session_token;
+use axum::body::Body;
+use axum::extract::{FromRef, State};
+use axum::http::request::Parts;
+use axum::http::{header, HeaderMap, Request, StatusCode, Uri};
+use axum::response::{Html, IntoResponse, Response};
+use axum::Router;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use core::time::Duration;
+use front_app::bridge::InlineExecutor;
+use front_app::cache::TodoCache;
+use front_app::metrics::CmdMetrics;
+use front_app::{App, CurrentUser, FindSession};
+use http_body::{LengthLimitError, Limited};
+use hyper::body::Bytes;
+use hyper::rt::Executor;
+use hyper::server::conn::Http;
+use hyper::service::{service_fn, Service};
+use leptos::leptos_server::{server_fn_by_path, Payload};
+use leptos::server_fn::Encoding;
+use leptos::*;
+use leptos_axum::ResponseOptions;
+use leptos_meta::{provide_meta_context, use_head};
+use leptos_router::{RouterIntegrationContext, ServerIntegration};
+use std::cell::{Cell, RefCell};
+use std::env;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Instant;
+use tarantool::ffi::tarantool::{coio_wait, CoIOFlags};
+use tarantool::fiber::{self, Channel, Mutex, SendError};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Interval of stop flag checks while waiting for sockets, in seconds
+const POLL_INTERVAL: f64 = 0.1;
+
+/// Connection without any io for this long is closed, in seconds
+const IDLE_TIMEOUT: f64 = 30.0;
+
+/// Largest request body read by server functions, in bytes
+const MAX_BODY_SIZE: usize = 64 * 1024;
+
+/// Number of fibers serving connections. Configured with `HTTP_FIBERS`
+/// environment variable, 16 by default.
+pub fn fibers_from_env() -> usize {
+    env::var("HTTP_FIBERS")
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .filter(|n| *n > 0)
+        .unwrap_or(16)
+}
+
+/// State of http server handlers
+#[derive(Debug, Clone, FromRef)]
+pub struct FiberState {
+    pub todo_cache: TodoCache,
+    pub leptos_options: LeptosOptions,
+    /// Execution time of commands, there is no command queue on fibers
+    pub metrics: Arc<CmdMetrics>,
+}
+
+thread_local! {
+    /// Executor of the running server. Handlers run on fibers of tx
+    /// thread, but axum requires `Send` state, so executor is kept here.
+    static EXECUTOR: RefCell<Option<InlineExecutor>> = RefCell::new(None);
+}
+
+/// State shared by connection fibers
+struct Shared {
+    router: Router,
+    stopped: Cell<bool>,
+    /// Accepted connections waiting for a connection fiber
+    queued: Cell<usize>,
+}
+
+/// Http server running on tarantool fibers. Requests are routed by
+/// application router, server functions execute commands in place,
+/// without the bridge to a foreign thread.
+pub struct FiberServer {
+    shared: Rc<Shared>,
+    acceptor: fiber::JoinHandle<'static, ()>,
+}
+
+impl FiberServer {
+    /// Start accepting connections on `listener`, every connection is
+    /// served by one of `fibers` connection fibers. Idle keep-alive
+    /// connections are closed when accepted ones wait for a fiber.
+    /// Executed commands are recorded in `metrics`.
+    pub fn start(
+        listener: TcpListener,
+        router: Router,
+        metrics: Arc<CmdMetrics>,
+        fibers: usize,
+    ) -> io::Result<FiberServer> {
+        listener.set_nonblocking(true)?;
+
+        let dispatcher = Dispatcher::app();
+        let write_lock = Mutex::new(());
+        let executor = InlineExecutor::new(move |cmd| {
+            let name = cmd.name();
+            let started = Instant::now();
+            pool::execute(&dispatcher, &write_lock, cmd);
+            metrics.observe(name, Duration::ZERO, started.elapsed());
+        });
+        EXECUTOR.with(|e| *e.borrow_mut() = Some(executor));
+
+        let shared = Rc::new(Shared {
+            router,
+            stopped: Cell::new(false),
+            queued: Cell::new(0),
+        });
+
+        let acceptor = {
+            let shared = shared.clone();
+            fiber::start(move || accept(listener, shared, fibers))
+        };
+
+        Ok(FiberServer { shared, acceptor })
+    }
+
+    /// Stop accepting connections and wait for connection fibers.
+    /// Responses being written are completed, idle connections are closed.
+    pub fn stop(self) {
+        self.shared.stopped.set(true);
+        self.acceptor.join();
+        EXECUTOR.with(|e| e.borrow_mut().take());
+    }
+}
+
+/// Accept connections until server is stopped
+fn accept(listener: TcpListener, shared: Rc<Shared>, fibers: usize) {
+    // `None` stops a connection fiber after queued connections are served
+    let conns = Rc::new(Channel::<Option<TcpStream>>::new(fibers as u32));
+
+    let handles: Vec<_> = (0..fibers)
+        .map(|_| {
+            let conns = conns.clone();
+            let shared = shared.clone();
+            fiber::start(move || {
+                while let Some(Some(stream)) = conns.recv() {
+                    shared.queued.set(shared.queued.get() - 1);
+                    serve_connection(stream, &shared);
+                }
+            })
+        })
+        .collect();
+
+    while !shared.stopped.get() {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                if stream.set_nonblocking(true).is_ok() {
+                    queue(&conns, stream, &shared);
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                wait_fd(listener.as_raw_fd(), CoIOFlags::READ, POLL_INTERVAL);
+            }
+            Err(err) => {
+                eprintln!("http accept error: {err}");
+                fiber::sleep(Duration::from_secs_f64(POLL_INTERVAL));
+            }
+        }
+    }
+
+    for _ in 0..fibers {
+        let _ = conns.send(None);
+    }
+    for handle in handles {
+        handle.join();
+    }
+}
+
+/// Queue accepted connection for connection fibers. Queue is full while
+/// every fiber serves a connection, then waiting is rechecked, so stop
+/// is not delayed. Connection is dropped when server is stopped.
+fn queue(conns: &Channel<Option<TcpStream>>, stream: TcpStream, shared: &Shared) {
+    shared.queued.set(shared.queued.get() + 1);
+    let mut conn = Some(stream);
+    while !shared.stopped.get() {
+        match conns.send_timeout(conn, Duration::from_secs_f64(POLL_INTERVAL)) {
+            Ok(()) => return,
+            Err(SendError::Timeout(back)) => conn = back,
+            Err(SendError::Disconnected(_)) => break,
+        }
+    }
+    shared.queued.set(shared.queued.get() - 1);
+}
+
+/// Serve http/1 requests of a connection until it is closed
+fn serve_connection(stream: TcpStream, shared: &Rc<Shared>) {
+    let busy = Rc::new(Cell::new(false));
+    let io = FiberStream {
+        stream,
+        shared: shared.clone(),
+        busy: busy.clone(),
+    };
+    // Connection handling a request is not closed as idle
+    let router = shared.router.clone();
+    let service = service_fn(move |req| {
+        busy.set(true);
+        let busy = busy.clone();
+        let res = router.clone().call(req);
+        async move {
+            let res = res.await;
+            busy.set(false);
+            res
+        }
+    });
+    let conn = Http::new()
+        .http1_only(true)
+        .with_executor(FiberExec)
+        .serve_connection(io, service);
+
+    // Connection errors (reset, idle timeout) concern only this client
+    let _ = fiber::block_on(conn);
+}
+
+/// Call leptos server function, commands are executed in place
+pub async fn server_fn_handler(
+    State(todo_cache): State<TodoCache>,
+    req: Request<Body>,
+) -> Response {
+    let (parts, body) = req.into_parts();
+    match read_body(&parts.headers, body).await {
+        Ok(body) => call_server_fn(&parts, &body, &todo_cache),
+        Err(status) => status.into_response(),
+    }
+}
+
+/// Render application page
+pub async fn page_handler(State(options): State<LeptosOptions>, uri: Uri) -> Html<String> {
+    Html(render_page(&uri, &options))
+}
+
+/// Read request body of at most `MAX_BODY_SIZE` bytes. Body with
+/// larger declared length is refused without reading it.
+async fn read_body(headers: &HeaderMap, body: Body) -> Result<Bytes, StatusCode> {
+    let content_length = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|len| len.to_str().ok())
+        .and_then(|len| len.parse::<usize>().ok());
+    if content_length.is_some_and(|len| len > MAX_BODY_SIZE) {
+        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    hyper::body::to_bytes(Limited::new(body, MAX_BODY_SIZE))
+        .await
+        .map_err(|err| {
+            if err.is::<LengthLimitError>() {
+                StatusCode::PAYLOAD_TOO_LARGE
+            } else {
+                StatusCode::BAD_REQUEST
+            }
+        })
+}
+
+/// Call server function of the request with its `body`. Server function
+/// future is driven in place, as commands it sends are executed inline.
+fn call_server_fn(parts: &Parts, body: &[u8], todo_cache: &TodoCache) -> Response {
+    let fn_name = parts.uri.path().strip_prefix("/api/").unwrap_or_default();
+    let Some(server_fn) = server_fn_by_path(fn_name) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let Some(executor) = EXECUTOR.with(|e| e.borrow().clone()) else {
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    };
+
+    let session = session_token(&parts.headers)
+        .and_then(|token| executor.execute(FindSession { token }).ok().flatten());
+    let query = parts.uri.query().unwrap_or_default().as_bytes();
+    let data = match server_fn.encoding() {
+        Encoding::Url | Encoding::Cbor => body,
+        Encoding::GetJSON | Encoding::GetCBOR => query,
+    };
+
+    let runtime = create_runtime();
+    provide_context(executor);
+    provide_context(todo_cache.clone());
+    let res_options = ResponseOptions::default();
+    provide_context(res_options.clone());
+    if let Some(session) = session {
+        provide_context(CurrentUser(session.user_id));
+        provide_context(session);
+    }
+    let res = fiber::block_on(server_fn.call((), data));
+    runtime.dispose();
+
+    let (content_type, body) = match res {
+        Ok(Payload::Binary(data)) => ("application/cbor", data),
+        Ok(Payload::Url(data)) => ("application/x-www-form-urlencoded", data.into_bytes()),
+        Ok(Payload::Json(data)) => ("application/json", data.into_bytes()),
+        Err(err) => {
+            let err = serde_json::to_string(&err).unwrap_or_else(|_| err.to_string());
+            return (StatusCode::INTERNAL_SERVER_ERROR, err).into_response();
+        }
+    };
+
+    let mut res = ([(header::CONTENT_TYPE, content_type)], body).into_response();
+    // Headers set by server function, e.g. cookies
+    res.headers_mut()
+        .extend(res_options.0.read().headers.clone());
+    res
+}
+
+/// Render application page with hydration script
+fn render_page(uri: &Uri, options: &LeptosOptions) -> String {
+    let path = format!("http://leptos.dev{uri}");
+    let head = Rc::new(RefCell::new(String::new()));
+
+    let body = {
+        let head = head.clone();
+        leptos::ssr::render_to_string(move || {
+            provide_context(RouterIntegrationContext::new(ServerIntegration { path }));
+            provide_meta_context();
+            let app = view! { <App/> }.into_view();
+            *head.borrow_mut() = use_head().dehydrate();
+            app
+        })
+    };
+
+    let pkg_dir = &options.site_pkg_dir;
+    let output_name = &options.output_name;
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8"/>
+<meta name="viewport" content="width=device-width, initial-scale=1"/>
+{head}
+<script type="module">
+import init, {{ hydrate }} from '/{pkg_dir}/{output_name}.js';
+init('/{pkg_dir}/{output_name}_bg.wasm').then(hydrate);
+</script>
+</head>
+<body>{body}</body>
+</html>"#,
+        head = head.take()
+    )
+}
+
+/// Yield current fiber until `fd` is ready for `events`, at most
+/// `timeout` seconds. Returns `false` on timeout.
+fn wait_fd(fd: RawFd, events: CoIOFlags, timeout: f64) -> bool {
+    unsafe { coio_wait(fd, events.bits(), timeout) != 0 }
+}
+
+/// Nonblocking socket of a connection. Io that would block yields
+/// current fiber until socket is ready, so the connection future is
+/// always ready and may be driven by `fiber::block_on`.
+struct FiberStream {
+    stream: TcpStream,
+    shared: Rc<Shared>,
+    /// Request of the connection is being handled
+    busy: Rc<Cell<bool>>,
+}
+
+impl FiberStream {
+    fn retry<T, F>(&mut self, events: CoIOFlags, mut io: F) -> io::Result<T>
+    where
+        F: FnMut(&mut TcpStream) -> io::Result<T>,
+    {
+        let mut waited = 0.0;
+        loop {
+            match io(&mut self.stream) {
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                res => return res,
+            }
+
+            // Connection waiting for next request is closed on stop, and
+            // when accepted connections wait for its fiber
+            let reading = events.contains(CoIOFlags::READ);
+            let stopped = reading && self.shared.stopped.get();
+            let needed = reading && !self.busy.get() && self.shared.queued.get() > 0;
+            if stopped || needed || waited >= IDLE_TIMEOUT {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+            if !wait_fd(self.stream.as_raw_fd(), events, POLL_INTERVAL) {
+                waited += POLL_INTERVAL;
+            }
+        }
+    }
+}
+
+impl AsyncRead for FiberStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let res = self.get_mut().retry(CoIOFlags::READ, |stream| {
+            stream.read(buf.initialize_unfilled())
+        });
+        Poll::Ready(res.map(|n| buf.advance(n)))
+    }
+}
+
+impl AsyncWrite for FiberStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Ready(
+            self.get_mut()
+                .retry(CoIOFlags::WRITE, |stream| stream.write(buf)),
+        )
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let _ = self.stream.shutdown(Shutdown::Write);
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Hyper executor for connection background tasks. Http/1 connections
+/// don't spawn tasks, so a task is just run to completion in place.
+#[derive(Clone, Copy)]
+struct FiberExec;
+
+impl<F> Executor<F> for FiberExec
+where
+    F: Future<Output = ()> + 'static,
+{
+    fn execute(&self, fut: F) {
+        fiber::block_on(fut);
+    }
+}
+
+#[cfg(feature = "test")]
+mod tests {
+    use super::*;
+    use crate::router;
+    use axum::http::HeaderValue;
+    use front_app::GetTodos;
+    use leptos::server_fn::ServerFn;
+    use std::net::SocketAddr;
+
+    /// Start server with application router on an ephemeral port
+    fn start_server(fibers: usize) -> (FiberServer, SocketAddr) {
+        let conf = fiber::block_on(get_configuration(Some("./Cargo.toml"))).unwrap();
+        let metrics = Arc::new(CmdMetrics::default());
+        let state = FiberState {
+            todo_cache: TodoCache::default(),
+            leptos_options: conf.leptos_options,
+            metrics: metrics.clone(),
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = FiberServer::start(listener, router(state), metrics, fibers).unwrap();
+        (server, addr)
+    }
+
+    /// Send raw http `request` and read response until `end` is received,
+    /// to connection close when `None`
+    fn exchange(stream: &mut TcpStream, request: &str, end: Option<&str>) -> String {
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut res = Vec::new();
+        let mut buf = [0; 4096];
+        while !end.is_some_and(|end| String::from_utf8_lossy(&res).contains(end)) {
+            match stream.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => res.extend_from_slice(&buf[..n]),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    let ready = wait_fd(stream.as_raw_fd(), CoIOFlags::READ, 5.0);
+                    assert!(ready, "response timed out");
+                }
+                Err(err) => panic!("{err}"),
+            }
+        }
+        String::from_utf8_lossy(&res).into_owned()
+    }
+
+    fn connect(addr: SocketAddr) -> TcpStream {
+        let stream = TcpStream::connect(addr).unwrap();
+        stream.set_nonblocking(true).unwrap();
+        stream
+    }
+
+    #[tarantool_test::test]
+    fn serve_page_and_server_fn() {
+        let (server, addr) = start_server(2);
+
+        let request = "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+        let res = exchange(&mut connect(addr), request, None);
+        assert!(res.starts_with("HTTP/1.1 200"));
+        assert!(res.contains("<!DOCTYPE html>"));
+
+        let path = format!("/api/{}", <GetTodos as ServerFn<()>>::url());
+        let request = format!(
+            "POST {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\
+             Content-Type: application/x-www-form-urlencoded\r\nContent-Length: 0\r\n\r\n"
+        );
+        let res = exchange(&mut connect(addr), &request, None);
+        assert!(res.starts_with("HTTP/1.1 500"));
+        assert!(res.contains("sign in required"));
+
+        server.stop();
+    }
+
+    #[tarantool_test::test]
+    fn serve_more_idle_connections_than_fibers() {
+        let (server, addr) = start_server(2);
+        let request = "GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n";
+
+        // Keep-alive connections keep both fibers
+        let mut idle: Vec<TcpStream> = (0..2).map(|_| connect(addr)).collect();
+        for stream in &mut idle {
+            assert!(exchange(stream, request, Some("\r\n\r\n")).starts_with("HTTP/1.1 "));
+        }
+
+        let res = exchange(&mut connect(addr), request, Some("\r\n\r\n"));
+        assert!(res.starts_with("HTTP/1.1 "));
+        // Idle connection was closed for the new one
+        assert!(idle
+            .iter_mut()
+            .any(|stream| matches!(stream.read(&mut [0; 1]), Ok(0))));
+
+        server.stop();
+    }
+
+    #[tarantool_test::test]
+    fn read_body_within_limit() {
+        let body = fiber::block_on(read_body(&HeaderMap::new(), Body::from("args=1")));
+        assert_eq!(body, Ok(Bytes::from("args=1")));
+    }
+
+    #[tarantool_test::test]
+    fn refuse_body_over_limit() {
+        let body = Body::from(vec![0; MAX_BODY_SIZE + 1]);
+        let res = fiber::block_on(read_body(&HeaderMap::new(), body));
+        assert_eq!(res, Err(StatusCode::PAYLOAD_TOO_LARGE));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONTENT_LENGTH, HeaderValue::from(MAX_BODY_SIZE + 1));
+        let res = fiber::block_on(read_body(&headers, Body::empty()));
+        assert_eq!(res, Err(StatusCode::PAYLOAD_TOO_LARGE));
+    }
+}
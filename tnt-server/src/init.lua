@@ -29,6 +29,7 @@ package.cpath = package_location .. '/?.so;' .. package_location .. '/?.dylib;'
 
 -- Run tests
 box.schema.func.create(package_entrypoint, { language = 'C', if_not_exists = true })
+box.schema.func.create(package_name .. '.stop', { language = 'C', if_not_exists = true })
 
 box.func[package_entrypoint]:call({input})
 
@@ -1,57 +1,166 @@
-use crate::repo::todo::{
-    change_all_completed, change_completed, change_title, create_todo, delete_completed,
-    delete_todo, list_todos,
+#[cfg(not(feature = "fiber-http"))]
+use axum::{
+    body::Body,
+    extract::{RawQuery, State},
+    http::header::HeaderValue,
+    http::Request,
+    middleware::{self, Next},
+    Extension,
 };
-use axum::body::Body;
-use axum::extract::{RawQuery, State};
-use axum::http::header::{self, HeaderMap};
-use axum::http::Request;
-use axum::response::Response;
-use axum::Router;
-use axum::{extract::Path as ExtractPath, http::StatusCode, response::IntoResponse, routing::get};
-use core::time::Duration;
+use axum::{
+    extract::Path as ExtractPath,
+    http::header::{self, HeaderMap},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, MethodRouter},
+    Router,
+};
+#[cfg(feature = "fiber-http")]
+use fiber_http::FiberState;
+#[cfg(not(feature = "fiber-http"))]
+use front_app::bridge::{self, CmdTimeout, Overload};
+#[cfg(feature = "fiber-http")]
+use front_app::App;
+#[cfg(not(feature = "fiber-http"))]
 use front_app::*;
+#[cfg(not(feature = "fiber-http"))]
 use leptos::*;
-use leptos_axum::{
-    generate_route_list, handle_server_fns_with_context, render_app_to_stream, LeptosRoutes,
-};
+use leptos_axum::{generate_route_list, LeptosRoutes};
+#[cfg(not(feature = "fiber-http"))]
+use leptos_axum::{handle_server_fns_with_context, render_app_to_stream};
 
+use front_app::metrics::{CmdMetrics, MetricsToken};
+use front_app::SESSION_COOKIE;
 use std::fs::{self, File};
 use std::io::{self, BufReader, Read};
+use std::os::unix::io::RawFd;
 use std::path::Path;
-use std::sync::mpsc::{self, TryRecvError};
-use std::sync::{Arc, Mutex};
-use strum::IntoEnumIterator;
-use tarantool::fiber;
+use std::sync::Arc;
+use tarantool::ffi::tarantool::{coio_wait, CoIOFlags};
 use tarantool::space::{FieldType, Space};
 
+#[cfg(feature = "bench")]
+mod bench;
+mod dispatcher;
+#[cfg(feature = "fiber-http")]
+mod fiber_http;
+mod lifecycle;
+mod periodic;
+mod pool;
+mod reminder;
 mod repo;
 mod test;
 
-use repo::RepoSpaces;
+use lifecycle::LifecycleErr;
+use repo::{migration, schema, RepoErr};
+
+/// Timeout used by tarantool for infinite waits, in seconds
+const TIMEOUT_INFINITY: f64 = 100.0 * 365.0 * 86400.0;
 
 /// Custom axum handler for leptos server functions
+#[cfg(not(feature = "fiber-http"))]
 async fn server_fn_handler(
     State(app_state): State<AppState>,
     path: ExtractPath<String>,
     headers: HeaderMap,
     raw_query: RawQuery,
+    session: Option<Extension<Session>>,
     request: Request<Body>,
 ) -> impl IntoResponse {
-    handle_server_fns_with_context(
+    // Guard is dropped with this future when client disconnects
+    let (guard, disconnect) = bridge::disconnect_guard();
+    let overload = Overload::default();
+    let request_overload = overload.clone();
+    let mut response = handle_server_fns_with_context(
         path,
         headers,
         raw_query,
         move || {
+            if let Some(Extension(session)) = &session {
+                provide_context(CurrentUser(session.user_id));
+                provide_context(session.clone());
+            }
             provide_context(app_state.cmd_tx.clone());
-            provide_context(app_state.todo_rx.clone());
+            provide_context(app_state.cmd_timeout);
+            provide_context(app_state.todo_cache.clone());
+            provide_context(disconnect.clone());
+            provide_context(request_overload.clone());
         },
         request,
     )
     .await
+    .into_response();
+    drop(guard);
+
+    if overload.is_raised() {
+        *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+        response
+            .headers_mut()
+            .insert(header::RETRY_AFTER, HeaderValue::from_static("1"));
+    }
+    response
+}
+
+/// Resolve session cookie of the request. Valid session is added to
+/// request extensions, request without it is served as anonymous.
+#[cfg(not(feature = "fiber-http"))]
+async fn session_layer(
+    State(app_state): State<AppState>,
+    mut request: Request<Body>,
+    next: Next<Body>,
+) -> Response {
+    if let Some(token) = session_token(request.headers()) {
+        let CmdTimeout(timeout) = app_state.cmd_timeout;
+        match app_state.cmd_tx.call(FindSession { token }, timeout).await {
+            Ok(Some(session)) => {
+                request.extensions_mut().insert(session);
+            }
+            Ok(None) => {}
+            Err(err @ (AppErr::Overloaded | AppErr::Unavailable)) => {
+                let headers = [(header::RETRY_AFTER, "1")];
+                return (StatusCode::SERVICE_UNAVAILABLE, headers, err.to_string()).into_response();
+            }
+            Err(err) => eprintln!("session lookup failed: {err}"),
+        }
+    }
+    next.run(request).await
+}
+
+/// Command metrics in prometheus text format, served to requests with
+/// `METRICS_TOKEN` bearer token
+async fn metrics_handler(
+    metrics: Arc<CmdMetrics>,
+    token: MetricsToken,
+    headers: HeaderMap,
+) -> Response {
+    if token.0.is_none() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    let authorization = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok());
+    if !token.allows(authorization) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics.render(),
+    )
+        .into_response()
+}
+
+/// `/metrics` route. It is mounted outside of session layer, metrics
+/// don't depend on the user and are protected by their own token.
+fn metrics_route<S>(metrics: Arc<CmdMetrics>) -> MethodRouter<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let token = MetricsToken::from_env();
+    get(move |headers: HeaderMap| metrics_handler(metrics, token, headers))
 }
 
 /// Custom axum handler for leptos server functions
+#[cfg(not(feature = "fiber-http"))]
 async fn leptos_routes_handler(State(app_state): State<AppState>, req: Request<Body>) -> Response {
     let handler = render_app_to_stream(app_state.leptos_options.clone(), || view! { <App/> });
     handler(req).await.into_response()
@@ -59,165 +168,125 @@ async fn leptos_routes_handler(State(app_state): State<AppState>, req: Request<B
 
 /// Tarantool entry point (main function)
 #[tarantool::proc]
-fn start() {
-    create_spaces();
+fn start() -> Result<(), LifecycleErr> {
+    create_spaces()?;
     let _ = read_files();
-    let (cmd_tx, cmd_rx) = mpsc::channel();
-    let (todo_tx, todo_rx) = mpsc::channel::<Vec<Todo>>();
-    let todo_rx = Arc::new(Mutex::new(todo_rx));
-
-    let jh = std::thread::spawn(move || {
-        tokio::runtime::Builder::new_multi_thread()
-            .enable_all()
-            .build()
-            .expect("Failed building the Runtime")
-            .block_on(async move {
-                let conf = get_configuration(Some("./Cargo.toml")).await.unwrap();
-                let leptos_options = conf.leptos_options;
-                let addr = leptos_options.site_addr.clone();
-                let shared_state = AppState {
-                    todo_rx,
-                    cmd_tx,
-                    leptos_options,
-                };
-
-                let routes = generate_route_list(App);
-
-                let app = Router::new()
-                    .route("/pkg/:path", get(file_server))
-                    .route(
-                        "/api/*fn_name",
-                        get(server_fn_handler).post(server_fn_handler),
-                    )
-                    .leptos_routes_with_handler(routes, get(leptos_routes_handler))
-                    .with_state(shared_state);
-
-                println!("listening on http://{}", &addr);
-                axum::Server::bind(&addr)
-                    .serve(app.into_make_service())
-                    .await
-                    .unwrap();
-
-                Ok::<(), Box<dyn std::error::Error + Send + Sync>>(())
-            })
-    });
-
-    drop(jh);
-
-    static mut FIBER_JOIN_HANDLE: Option<fiber::JoinHandle<()>> = None;
-    let jh = fiber::start(move || loop {
-        match cmd_rx.try_recv() {
-            Err(TryRecvError::Empty) => {
-                fiber::sleep(Duration::from_millis(10));
-                continue;
-            }
-            Err(TryRecvError::Disconnected) => break,
-            Ok(Cmd::GetTodos) => {
-                if let Ok(todos) = list_todos() {
-                    let _ = todo_tx.send(todos);
-                }
-            }
-            Ok(Cmd::AddTodo(title)) => {
-                let opes = move || {
-                    create_todo(&title)?;
-                    list_todos()
-                };
-                if let Ok(todos) = opes() {
-                    let _ = todo_tx.send(todos);
-                }
-            }
-            Ok(Cmd::DeleteTodo(id)) => {
-                let opes = move || {
-                    delete_todo(id)?;
-                    list_todos()
-                };
-                if let Ok(todos) = opes() {
-                    let _ = todo_tx.send(todos);
-                }
-            }
-            Ok(Cmd::ChangeTitle((id, title))) => {
-                let opes = move || {
-                    change_title(id, &title)?;
-                    list_todos()
-                };
-                if let Ok(todos) = opes() {
-                    let _ = todo_tx.send(todos);
-                }
-            }
-            Ok(Cmd::ChangeAllCompleted(completed)) => {
-                if let Ok(todos) = change_all_completed(completed) {
-                    let _ = todo_tx.send(todos);
-                }
-            }
-            Ok(Cmd::ChangeCompleted((id, completed))) => {
-                let opes = move || {
-                    change_completed(id, completed)?;
-                    list_todos()
-                };
-                if let Ok(todos) = opes() {
-                    let _ = todo_tx.send(todos);
-                }
-            }
-            Ok(Cmd::DeleteCompleted) => {
-                if let Ok(todos) = delete_completed() {
-                    let _ = todo_tx.send(todos);
-                }
-            }
-        }
-    });
+    lifecycle::start()
+}
+
+/// Stop application, so it can be started again without restarting
+/// tarantool instance
+#[tarantool::proc]
+fn stop() -> Result<(), LifecycleErr> {
+    lifecycle::stop()
+}
+
+/// Build application router
+#[cfg(not(feature = "fiber-http"))]
+fn router(shared_state: AppState) -> Router {
+    let routes = generate_route_list(App);
+
+    Router::new()
+        .route("/pkg/:path", get(file_server))
+        .route(
+            "/api/*fn_name",
+            get(server_fn_handler).post(server_fn_handler),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            shared_state.clone(),
+            session_layer,
+        ))
+        .route(
+            "/metrics",
+            metrics_route(shared_state.cmd_tx.metrics().clone()),
+        )
+        .leptos_routes_with_handler(routes, get(leptos_routes_handler))
+        .with_state(shared_state)
+}
+
+/// Build application router served on fibers. Server functions and
+/// pages are handled in place instead of leptos_axum handlers, which
+/// spawn tokio tasks.
+#[cfg(feature = "fiber-http")]
+fn router(state: FiberState) -> Router {
+    let routes = generate_route_list(App);
+
+    Router::new()
+        .route("/pkg/:path", get(file_server))
+        .route(
+            "/api/*fn_name",
+            get(fiber_http::server_fn_handler).post(fiber_http::server_fn_handler),
+        )
+        .route("/metrics", metrics_route(state.metrics.clone()))
+        .leptos_routes_with_handler(routes, get(fiber_http::page_handler))
+        .with_state(state)
+}
 
+/// Yield current fiber until `fd` becomes readable
+fn wait_readable(fd: RawFd) {
     unsafe {
-        FIBER_JOIN_HANDLE = Some(jh);
+        coio_wait(fd, CoIOFlags::READ.bits(), TIMEOUT_INFINITY);
     }
 }
 
-/// Create tarantool spaces
-fn create_spaces() {
-    for space in RepoSpaces::iter() {
-        let _ = space.create();
-    }
+/// Migrate application spaces to the current schema version, verify
+/// them and create space of application files
+fn create_spaces() -> Result<(), RepoErr> {
+    migration::migrate()?;
+    schema::verify()?;
 
     let files_space = Space::builder("files")
         .format([("name", FieldType::String), ("content", FieldType::Any)])
         .if_not_exists(true)
-        .create()
-        .unwrap();
+        .create()?;
 
     files_space
         .index_builder("files_idx")
         .part("name")
         .if_not_exists(true)
-        .create()
-        .unwrap();
+        .create()?;
+
+    Ok(())
 }
 
 /// Serve application files on /pkg route
 async fn file_server(ExtractPath(path): ExtractPath<String>) -> impl IntoResponse {
-    let file_space = Space::find_cached("files").unwrap();
-    let ext = path.split('.').last();
-    let mime;
-    match ext {
-        Some("wasm") => {
-            mime = "application/wasm";
-        }
-        Some("js") => {
-            mime = "application/javascript";
-        }
-        Some("css") => {
-            mime = "text/css";
-        }
-        _ => return Err(StatusCode::NOT_FOUND),
-    }
-
-    if let Ok(Some(tuple)) = file_space.get(&(path,)) {
-        if let Ok(Some(file)) = tuple.field::<Vec<u8>>(1) {
+    match find_file(&path) {
+        Some((mime, file)) => {
             let mut headers = HeaderMap::new();
             headers.insert(header::CONTENT_TYPE, mime.parse().unwrap());
-            return Ok((headers, file));
+            Ok((headers, file))
         }
+        None => Err(StatusCode::NOT_FOUND),
     }
+}
+
+/// Find application file in `files` space, returns file mime type and content
+fn find_file(path: &str) -> Option<(&'static str, Vec<u8>)> {
+    let mime = match path.split('.').last() {
+        Some("wasm") => "application/wasm",
+        Some("js") => "application/javascript",
+        Some("css") => "text/css",
+        _ => return None,
+    };
+
+    let file_space = Space::find_cached("files")?;
+    let tuple = file_space.get(&(path,)).ok()??;
+    let file = tuple.field::<Vec<u8>>(1).ok()??;
+
+    Some((mime, file))
+}
 
-    Err(StatusCode::NOT_FOUND)
+/// Session token of a request, taken from session cookie
+fn session_token(headers: &axum::http::HeaderMap) -> Option<String> {
+    headers
+        .get_all(axum::http::header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, token)| *name == SESSION_COOKIE && !token.is_empty())
+        .map(|(_, token)| token.to_string())
 }
 
 /// Read application files and store in `files` space
@@ -0,0 +1,521 @@
+#[cfg(not(feature = "fiber-http"))]
+use crate::dispatcher::Dispatcher;
+#[cfg(feature = "fiber-http")]
+use crate::fiber_http::{self, FiberServer, FiberState};
+use crate::periodic::Periodic;
+#[cfg(not(feature = "fiber-http"))]
+use crate::pool;
+use crate::reminder;
+use crate::repo::{cache, clock, session, trash, undo, RepoErr};
+use crate::router;
+use core::fmt;
+use core::time::Duration;
+#[cfg(not(feature = "fiber-http"))]
+use front_app::bridge::{self, CmdQueueDepth, CmdSender, CmdTimeout};
+use front_app::cache::TodoCache;
+#[cfg(feature = "fiber-http")]
+use front_app::metrics::CmdMetrics;
+#[cfg(not(feature = "fiber-http"))]
+use front_app::AppState;
+use leptos::get_configuration;
+use std::cell::{Cell, RefCell};
+use std::error::Error;
+use std::io;
+use std::net::SocketAddr;
+#[cfg(feature = "fiber-http")]
+use std::net::TcpListener;
+#[cfg(not(feature = "fiber-http"))]
+use std::sync::mpsc;
+#[cfg(feature = "fiber-http")]
+use std::sync::Arc;
+#[cfg(not(feature = "fiber-http"))]
+use std::thread;
+use tarantool::error::Error as TrtlErr;
+use tarantool::fiber;
+use tarantool::trigger;
+#[cfg(not(feature = "fiber-http"))]
+use tokio::sync::oneshot;
+
+/// Interval between purges of expired sessions
+const SESSION_PURGE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Interval between purges of expired undo actions
+const UNDO_PURGE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Interval between purges of todos kept in trash over retention
+const TRASH_PURGE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Running application. By default it is http server thread and command
+/// executor fiber, which runs the pool of worker fibers. With `fiber-http`
+/// feature http server itself runs on fibers. Expired sessions and undo
+/// actions and old trash are purged and overdue todos are notified by
+/// background fibers in both modes.
+struct Instance {
+    /// Address http server is bound to
+    addr: SocketAddr,
+    #[cfg(not(feature = "fiber-http"))]
+    shutdown_tx: oneshot::Sender<()>,
+    #[cfg(not(feature = "fiber-http"))]
+    http: thread::JoinHandle<()>,
+    #[cfg(not(feature = "fiber-http"))]
+    executor: fiber::JoinHandle<'static, ()>,
+    #[cfg(feature = "fiber-http")]
+    server: FiberServer,
+    session_purge: Periodic,
+    trash_purge: Periodic,
+    undo_purge: Periodic,
+    reminder: Periodic,
+}
+
+thread_local! {
+    static INSTANCE: RefCell<Option<Instance>> = RefCell::new(None);
+    static SHUTDOWN_HOOK: Cell<bool> = Cell::new(false);
+}
+
+/// Start http server and command executor. Returns after server is
+/// bound, so configuration and bind errors are reported to the caller.
+pub fn start() -> Result<(), LifecycleErr> {
+    launch(None).map(|_| ())
+}
+
+/// Start application with http server bound to `addr` instead of
+/// configured site address, e.g. to an ephemeral port. Returns address
+/// server is bound to.
+#[cfg(any(feature = "test", feature = "bench"))]
+pub fn start_on(addr: SocketAddr) -> Result<SocketAddr, LifecycleErr> {
+    launch(Some(addr))
+}
+
+fn launch(addr: Option<SocketAddr>) -> Result<SocketAddr, LifecycleErr> {
+    if INSTANCE.with(|i| i.borrow().is_some()) {
+        return Err(LifecycleErr::AlreadyRunning);
+    }
+
+    let todo_cache = TodoCache::default();
+    cache::install(&todo_cache)?;
+
+    let instance = match run(todo_cache, addr) {
+        Ok(instance) => instance,
+        Err(err) => {
+            // Nobody reads the cache of a server failed to start
+            cache::uninstall()?;
+            return Err(err);
+        }
+    };
+    let addr = instance.addr;
+    INSTANCE.with(|i| *i.borrow_mut() = Some(instance));
+
+    register_shutdown_hook()?;
+    Ok(addr)
+}
+
+/// Stop http server, waiting for in-flight requests, then stop
+/// command executor and todo cache updates.
+pub fn stop() -> Result<(), LifecycleErr> {
+    let instance = INSTANCE
+        .with(|i| i.borrow_mut().take())
+        .ok_or(LifecycleErr::NotRunning)?;
+
+    // Cache is read by in-flight requests until server is stopped
+    let res = shutdown(instance);
+    cache::uninstall()?;
+    res
+}
+
+/// Run http server on a tokio thread and command executor fiber
+#[cfg(not(feature = "fiber-http"))]
+fn run(todo_cache: TodoCache, addr: Option<SocketAddr>) -> Result<Instance, LifecycleErr> {
+    let CmdQueueDepth(depth) = CmdQueueDepth::from_env();
+    let (cmd_tx, cmd_rx) = bridge::channel(depth)?;
+    let (shutdown_tx, shutdown_rx) = oneshot::channel();
+    let (ready_tx, ready_rx) = mpsc::channel();
+
+    let http = thread::Builder::new().name("http".into()).spawn(move || {
+        let runtime = match tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+        {
+            Ok(runtime) => runtime,
+            Err(err) => {
+                let _ = ready_tx.send(Err(LifecycleErr::Io(err)));
+                return;
+            }
+        };
+        runtime.block_on(serve(cmd_tx, todo_cache, addr, shutdown_rx, ready_tx));
+    })?;
+
+    let addr = match ready_rx.recv() {
+        Ok(Ok(addr)) => addr,
+        Ok(Err(err)) => {
+            let _ = http.join();
+            return Err(err);
+        }
+        Err(_) => {
+            let _ = http.join();
+            return Err(LifecycleErr::HttpThreadPanicked);
+        }
+    };
+    println!("listening on http://{}", &addr);
+
+    let workers = pool::workers_from_env();
+    let executor = fiber::start(move || pool::run(cmd_rx, Dispatcher::app(), workers));
+
+    Ok(Instance {
+        addr,
+        shutdown_tx,
+        http,
+        executor,
+        session_purge: start_session_purge(),
+        trash_purge: start_trash_purge(),
+        undo_purge: start_undo_purge(),
+        reminder: reminder::start(),
+    })
+}
+
+/// Run http server on fibers of tx thread
+#[cfg(feature = "fiber-http")]
+fn run(todo_cache: TodoCache, addr: Option<SocketAddr>) -> Result<Instance, LifecycleErr> {
+    let conf = fiber::block_on(get_configuration(Some("./Cargo.toml")))
+        .map_err(|err| LifecycleErr::Config(err.to_string()))?;
+    let leptos_options = conf.leptos_options;
+    let addr = addr.unwrap_or(leptos_options.site_addr);
+
+    let listener =
+        TcpListener::bind(addr).map_err(|err| LifecycleErr::Bind(format!("{addr}: {err}")))?;
+    let addr = listener.local_addr()?;
+    let fibers = fiber_http::fibers_from_env();
+    let metrics = Arc::new(CmdMetrics::default());
+    let state = FiberState {
+        todo_cache,
+        leptos_options,
+        metrics: metrics.clone(),
+    };
+    let server = FiberServer::start(listener, router(state), metrics, fibers)?;
+    println!("listening on http://{}", &addr);
+
+    Ok(Instance {
+        addr,
+        server,
+        session_purge: start_session_purge(),
+        trash_purge: start_trash_purge(),
+        undo_purge: start_undo_purge(),
+        reminder: reminder::start(),
+    })
+}
+
+#[cfg(not(feature = "fiber-http"))]
+fn shutdown(instance: Instance) -> Result<(), LifecycleErr> {
+    let Instance {
+        addr: _,
+        shutdown_tx,
+        http,
+        executor,
+        session_purge,
+        trash_purge,
+        undo_purge,
+        reminder,
+    } = instance;
+
+    let _ = shutdown_tx.send(());
+
+    // Executor fiber keeps serving in-flight requests while server drains
+    while !http.is_finished() {
+        fiber::sleep(Duration::from_millis(10));
+    }
+    let res = http.join();
+
+    // Command senders are dropped with the server, so executor finishes
+    executor.join();
+    session_purge.stop();
+    trash_purge.stop();
+    undo_purge.stop();
+    reminder.stop();
+
+    res.map_err(|_| LifecycleErr::HttpThreadPanicked)
+}
+
+#[cfg(feature = "fiber-http")]
+fn shutdown(instance: Instance) -> Result<(), LifecycleErr> {
+    instance.server.stop();
+    instance.session_purge.stop();
+    instance.trash_purge.stop();
+    instance.undo_purge.stop();
+    instance.reminder.stop();
+    Ok(())
+}
+
+/// Start fiber deleting expired sessions
+fn start_session_purge() -> Periodic {
+    Periodic::start("session purge", SESSION_PURGE_INTERVAL, || {
+        session::purge_expired(clock::now()).map(|_| ())
+    })
+}
+
+/// Start fiber deleting expired undo actions
+fn start_undo_purge() -> Periodic {
+    Periodic::start("undo purge", UNDO_PURGE_INTERVAL, || {
+        undo::purge_expired(clock::now()).map(|_| ())
+    })
+}
+
+/// Start fiber deleting todos kept in trash longer than retention
+fn start_trash_purge() -> Periodic {
+    let retention = trash::retention_from_env().as_secs();
+    Periodic::start("trash purge", TRASH_PURGE_INTERVAL, move || {
+        trash::purge_trash(clock::now().saturating_sub(retention)).map(|_| ())
+    })
+}
+
+/// Stop application on tarantool shutdown. Hook is registered once,
+/// it is a no-op when application is already stopped.
+fn register_shutdown_hook() -> Result<(), LifecycleErr> {
+    if SHUTDOWN_HOOK.with(|h| h.replace(true)) {
+        return Ok(());
+    }
+
+    trigger::on_shutdown(|| {
+        let _ = stop();
+    })?;
+
+    Ok(())
+}
+
+/// Bind and run http server until shutdown signal is received. Server
+/// is bound to `addr` when it is set, otherwise to configured site
+/// address. Bind result is reported with `ready_tx`.
+#[cfg(not(feature = "fiber-http"))]
+async fn serve(
+    cmd_tx: CmdSender,
+    todo_cache: TodoCache,
+    addr: Option<SocketAddr>,
+    shutdown_rx: oneshot::Receiver<()>,
+    ready_tx: mpsc::Sender<Result<SocketAddr, LifecycleErr>>,
+) {
+    let conf = match get_configuration(Some("./Cargo.toml")).await {
+        Ok(conf) => conf,
+        Err(err) => {
+            let _ = ready_tx.send(Err(LifecycleErr::Config(err.to_string())));
+            return;
+        }
+    };
+    let leptos_options = conf.leptos_options;
+    let addr = addr.unwrap_or(leptos_options.site_addr);
+
+    let server = match axum::Server::try_bind(&addr) {
+        Ok(server) => server,
+        Err(err) => {
+            let _ = ready_tx.send(Err(LifecycleErr::Bind(format!("{addr}: {err}"))));
+            return;
+        }
+    };
+
+    let shared_state = AppState {
+        cmd_tx,
+        cmd_timeout: CmdTimeout::from_env(),
+        todo_cache,
+        leptos_options,
+    };
+    let app = router(shared_state);
+
+    let server = server.serve(app.into_make_service());
+    let _ = ready_tx.send(Ok(server.local_addr()));
+
+    let res = server
+        .with_graceful_shutdown(async {
+            let _ = shutdown_rx.await;
+        })
+        .await;
+
+    if let Err(err) = res {
+        eprintln!("http server error: {err}");
+    }
+}
+
+/// error returned by application lifecycle procs
+#[derive(Debug)]
+pub enum LifecycleErr {
+    AlreadyRunning,
+    NotRunning,
+    Config(String),
+    Bind(String),
+    HttpThreadPanicked,
+    Io(io::Error),
+    RepoErr(RepoErr),
+    TrtlErr(TrtlErr),
+}
+
+impl From<io::Error> for LifecycleErr {
+    fn from(err: io::Error) -> LifecycleErr {
+        LifecycleErr::Io(err)
+    }
+}
+
+impl From<RepoErr> for LifecycleErr {
+    fn from(err: RepoErr) -> LifecycleErr {
+        LifecycleErr::RepoErr(err)
+    }
+}
+
+impl From<TrtlErr> for LifecycleErr {
+    fn from(err: TrtlErr) -> LifecycleErr {
+        LifecycleErr::TrtlErr(err)
+    }
+}
+
+impl fmt::Display for LifecycleErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LifecycleErr::AlreadyRunning => write!(f, "application already running"),
+            LifecycleErr::NotRunning => write!(f, "application not running"),
+            LifecycleErr::Config(msg) => write!(f, "configuration error: {msg}"),
+            LifecycleErr::Bind(msg) => write!(f, "bind error: {msg}"),
+            LifecycleErr::HttpThreadPanicked => write!(f, "http server thread panicked"),
+            LifecycleErr::Io(err) => write!(f, "io error: {err}"),
+            LifecycleErr::RepoErr(err) => write!(f, "repo error: {err}"),
+            LifecycleErr::TrtlErr(err) => write!(f, "tarantool error: {err}"),
+        }
+    }
+}
+
+impl Error for LifecycleErr {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            LifecycleErr::Io(ref e) => Some(e),
+            LifecycleErr::RepoErr(ref e) => Some(e),
+            LifecycleErr::TrtlErr(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "test")]
+mod tests {
+    use super::*;
+    use crate::repo::user::create_user;
+    use front_app::Login;
+    use leptos::server_fn::ServerFn;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    fn ephemeral() -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], 0))
+    }
+
+    /// Send raw http `request` on a new connection and read the whole
+    /// response
+    fn http(addr: SocketAddr, request: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        let _ = stream.read_to_string(&mut response);
+        response
+    }
+
+    /// Run `client` on a separate thread, tx thread is kept free for
+    /// application fibers until it is done
+    fn run_client<T, F>(client: F) -> T
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let client = thread::spawn(client);
+        while !client.is_finished() {
+            fiber::sleep(Duration::from_millis(1));
+        }
+        client.join().unwrap()
+    }
+
+    fn get_page(addr: SocketAddr) -> String {
+        run_client(move || {
+            http(
+                addr,
+                "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            )
+        })
+    }
+
+    fn trigger_installed() -> bool {
+        tarantool::lua_state()
+            .eval::<bool>("return rawget(_G, 'todo_cache_trigger') ~= nil")
+            .unwrap()
+    }
+
+    #[tarantool_test::test]
+    fn start_on_ephemeral_port() {
+        let addr = start_on(ephemeral()).unwrap();
+        assert_ne!(addr.port(), 0);
+        assert!(matches!(
+            start_on(ephemeral()),
+            Err(LifecycleErr::AlreadyRunning)
+        ));
+        assert!(get_page(addr).starts_with("HTTP/1.1 200"));
+
+        stop().unwrap();
+        assert!(matches!(stop(), Err(LifecycleErr::NotRunning)));
+        assert!(!trigger_installed());
+    }
+
+    #[tarantool_test::test]
+    fn restart_without_instance_restart() {
+        start_on(ephemeral()).unwrap();
+        stop().unwrap();
+
+        let addr = start_on(ephemeral()).unwrap();
+        assert!(get_page(addr).starts_with("HTTP/1.1 200"));
+        stop().unwrap();
+    }
+
+    #[tarantool_test::test]
+    fn report_occupied_port() {
+        let listener = std::net::TcpListener::bind(ephemeral()).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        assert!(matches!(start_on(addr), Err(LifecycleErr::Bind(_))));
+        assert!(!trigger_installed());
+        assert!(matches!(stop(), Err(LifecycleErr::NotRunning)));
+    }
+
+    #[tarantool_test::test]
+    fn drain_request_on_stop() {
+        let (name, password) = ("Lifecycle user 1", "password");
+        create_user(name, password).unwrap();
+        let addr = start_on(ephemeral()).unwrap();
+
+        // Login takes an argon2 run, it is in flight when server stops
+        let body = format!("name={name}&password={password}").replace(' ', "+");
+        let request = format!(
+            "POST {}/{} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\
+             Content-Type: application/x-www-form-urlencoded\r\n\
+             Content-Length: {}\r\n\r\n{body}",
+            Login::prefix(),
+            Login::url(),
+            body.len()
+        );
+        let sent = Arc::new(AtomicBool::new(false));
+        let client = {
+            let sent = sent.clone();
+            thread::spawn(move || {
+                let mut stream = TcpStream::connect(addr).unwrap();
+                stream.write_all(request.as_bytes()).unwrap();
+                sent.store(true, Ordering::Release);
+                let mut response = String::new();
+                let _ = stream.read_to_string(&mut response);
+                response
+            })
+        };
+        while !sent.load(Ordering::Acquire) {
+            fiber::sleep(Duration::from_millis(1));
+        }
+        fiber::sleep(Duration::from_millis(50));
+
+        stop().unwrap();
+        while !client.is_finished() {
+            fiber::sleep(Duration::from_millis(1));
+        }
+        let response = client.join().unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+    }
+}
@@ -0,0 +1,75 @@
+use core::time::Duration;
+use std::cell::Cell;
+use std::rc::Rc;
+use tarantool::fiber::{self, Cond};
+
+/// Stop signal of a periodic fiber
+struct Stop {
+    stopped: Cell<bool>,
+    cond: Cond,
+}
+
+/// Background fiber running a task every `interval`, e.g. purge of
+/// expired records. Task errors are logged, the fiber keeps running.
+pub struct Periodic {
+    stop: Rc<Stop>,
+    handle: fiber::JoinHandle<'static, ()>,
+}
+
+/// Handle running task of a periodic fiber before its wait is over
+#[derive(Clone)]
+pub struct Waker(Rc<Stop>);
+
+impl Waker {
+    pub fn wake(&self) {
+        self.0.cond.signal();
+    }
+}
+
+impl Periodic {
+    pub fn start<F, E>(name: &'static str, interval: Duration, task: F) -> Periodic
+    where
+        F: Fn() -> Result<(), E> + 'static,
+        E: std::fmt::Display,
+    {
+        Periodic::schedule(name, interval, move || task().map(|_| interval))
+    }
+
+    /// Run a task again after the wait it returns, after `retry` when it
+    /// fails
+    pub fn schedule<F, E>(name: &'static str, retry: Duration, task: F) -> Periodic
+    where
+        F: Fn() -> Result<Duration, E> + 'static,
+        E: std::fmt::Display,
+    {
+        let stop = Rc::new(Stop {
+            stopped: Cell::new(false),
+            cond: Cond::new(),
+        });
+        let handle = {
+            let stop = stop.clone();
+            fiber::start(move || {
+                while !stop.stopped.get() {
+                    let wait = task().unwrap_or_else(|err| {
+                        eprintln!("{name} failed: {err}");
+                        retry
+                    });
+                    stop.cond.wait_timeout(wait);
+                }
+            })
+        };
+
+        Periodic { stop, handle }
+    }
+
+    pub fn waker(&self) -> Waker {
+        Waker(self.stop.clone())
+    }
+
+    /// Wake the fiber and wait until it exits, running task is completed
+    pub fn stop(self) {
+        self.stop.stopped.set(true);
+        self.stop.cond.signal();
+        self.handle.join();
+    }
+}
@@ -0,0 +1,170 @@
+use crate::dispatcher::Dispatcher;
+use crate::wait_readable;
+use front_app::bridge::{Cmd, CmdReceiver};
+use std::env;
+use std::rc::Rc;
+use std::time::Instant;
+use tarantool::fiber::{self, Channel, Mutex};
+
+/// Number of worker fibers. Configured with `CMD_WORKERS` environment
+/// variable, 4 by default.
+#[cfg_attr(feature = "fiber-http", allow(dead_code))]
+pub fn workers_from_env() -> usize {
+    env::var("CMD_WORKERS")
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .filter(|n| *n > 0)
+        .unwrap_or(4)
+}
+
+/// Receive commands and execute them on a pool of worker fibers, until
+/// all command senders are dropped. Read-only commands run concurrently,
+/// mutating commands are executed one at a time. Queue wait and execution
+/// time of every command are recorded in queue metrics.
+#[cfg_attr(feature = "fiber-http", allow(dead_code))]
+pub fn run(cmd_rx: CmdReceiver, dispatcher: Dispatcher, workers: usize) {
+    let dispatcher = Rc::new(dispatcher);
+    let metrics = cmd_rx.metrics().clone();
+    // `None` stops a worker after queued commands are executed
+    let queue = Rc::new(Channel::<Option<Cmd>>::new(workers as u32));
+    let write_lock = Rc::new(Mutex::new(()));
+
+    let handles: Vec<_> = (0..workers)
+        .map(|_| {
+            let dispatcher = dispatcher.clone();
+            let queue = queue.clone();
+            let write_lock = write_lock.clone();
+            let metrics = metrics.clone();
+            fiber::start(move || {
+                while let Some(Some(cmd)) = queue.recv() {
+                    let (name, wait) = (cmd.name(), cmd.age());
+                    let started = Instant::now();
+                    execute(&dispatcher, &write_lock, cmd);
+                    metrics.observe(name, wait, started.elapsed());
+                }
+            })
+        })
+        .collect();
+
+    while let Some(cmd) = cmd_rx.recv_with(wait_readable) {
+        let _ = queue.send(Some(cmd));
+    }
+
+    for _ in 0..workers {
+        let _ = queue.send(None);
+    }
+    for handle in handles {
+        handle.join();
+    }
+}
+
+/// Dispatch command, mutating commands take `write_lock` first
+pub fn execute(dispatcher: &Dispatcher, write_lock: &Mutex<()>, cmd: Cmd) {
+    if dispatcher.is_read_only(&cmd) {
+        dispatcher.dispatch(cmd);
+    } else {
+        let _guard = write_lock.lock();
+        dispatcher.dispatch(cmd);
+    }
+}
+
+#[cfg(feature = "test")]
+mod tests {
+    use super::*;
+    use crate::repo::user::create_user;
+    use core::time::Duration;
+    use front_app::bridge::{self, AsUser};
+    use front_app::{AddTodo, GetTodos, Login};
+    use std::sync::mpsc::TrySendError;
+
+    const USER_ID: u32 = 4001;
+
+    #[tarantool_test::test]
+    fn execute_commands_on_pool() {
+        let (cmd_tx, cmd_rx) = bridge::channel(4).unwrap();
+        let executor = fiber::start(move || run(cmd_rx, Dispatcher::app(), 2));
+
+        let replies: Vec<_> = (0..4)
+            .map(|i| {
+                let (cmd, reply_rx) = Cmd::new(AsUser {
+                    user_id: USER_ID,
+                    cmd: AddTodo {
+                        title: format!("Pool todo {i}"),
+                    },
+                });
+                cmd_tx.send(cmd).unwrap();
+                reply_rx
+            })
+            .collect();
+        drop(cmd_tx);
+        executor.join();
+
+        for mut reply_rx in replies {
+            assert!(reply_rx.try_recv().unwrap().is_ok());
+        }
+    }
+
+    #[tarantool_test::test]
+    fn record_command_metrics() {
+        let (cmd_tx, cmd_rx) = bridge::channel(4).unwrap();
+        let metrics = cmd_rx.metrics().clone();
+        let executor = fiber::start(move || run(cmd_rx, Dispatcher::app(), 2));
+
+        let (cmd, reply_rx) = Cmd::new(AsUser {
+            user_id: USER_ID,
+            cmd: GetTodos {},
+        });
+        cmd_tx.send(cmd).unwrap();
+        drop(cmd_tx);
+        executor.join();
+        drop(reply_rx);
+
+        assert_eq!(metrics.depth(), 0);
+        assert_eq!(metrics.kinds()["GetTodos"].count, 1);
+    }
+
+    #[tarantool_test::test]
+    fn write_while_logging_in() {
+        let (name, password) = ("Pool user 1".to_string(), "password".to_string());
+        create_user(&name, &password).unwrap();
+        let (cmd_tx, cmd_rx) = bridge::channel(4).unwrap();
+        let executor = fiber::start(move || run(cmd_rx, Dispatcher::app(), 2));
+
+        let (login, mut login_rx) = Cmd::new(Login { name, password });
+        let (write, mut write_rx) = Cmd::new(AsUser {
+            user_id: USER_ID,
+            cmd: AddTodo {
+                title: "Todo added during login".to_string(),
+            },
+        });
+        cmd_tx.send(login).unwrap();
+        cmd_tx.send(write).unwrap();
+
+        // Password is checked without write lock
+        let written = loop {
+            if let Ok(written) = write_rx.try_recv() {
+                break written;
+            }
+            fiber::sleep(Duration::from_millis(1));
+        };
+        assert!(written.is_ok());
+        assert!(login_rx.try_recv().is_err());
+
+        drop(cmd_tx);
+        executor.join();
+        assert!(login_rx.try_recv().unwrap().is_ok());
+    }
+
+    #[tarantool_test::test]
+    fn reject_command_on_full_queue() {
+        let (cmd_tx, cmd_rx) = bridge::channel(1).unwrap();
+
+        let (first, _first_rx) = Cmd::new(GetTodos {});
+        let (second, _second_rx) = Cmd::new(GetTodos {});
+        assert!(cmd_tx.send(first).is_ok());
+        assert!(matches!(cmd_tx.send(second), Err(TrySendError::Full(_))));
+
+        assert_eq!(cmd_rx.metrics().depth(), 1);
+        assert_eq!(cmd_rx.metrics().rejected(), 1);
+    }
+}
@@ -0,0 +1,57 @@
+use crate::periodic::{Periodic, Waker};
+use crate::repo::{clock, notification, RepoErr};
+use core::time::Duration;
+use front_app::Todo;
+use std::cell::{Cell, RefCell};
+
+/// Longest sleep of the reminder fiber, due dates are rechecked at
+/// least this often
+const MAX_WAIT: Duration = Duration::from_secs(600);
+
+/// Wait before the next attempt after failure
+const RETRY: Duration = Duration::from_secs(10);
+
+thread_local! {
+    static WAKER: RefCell<Option<Waker>> = RefCell::new(None);
+    /// Due dates up to this time are checked, all of them after start
+    static CHECKED: Cell<u64> = Cell::new(0);
+}
+
+/// Start fiber recording notifications of overdue todos. Fiber sleeps
+/// until the next due date, it is woken by `recheck` when todos change.
+/// Each run checks only due dates passed since the previous one.
+pub fn start() -> Periodic {
+    let reminder = Periodic::schedule("overdue reminder", RETRY, || -> Result<_, RepoErr> {
+        let now = clock::now();
+        notification::record_overdue(CHECKED.get(), now)?;
+        CHECKED.set(now);
+        let wait = notification::next_due(now)?
+            .map_or(MAX_WAIT, |due_at| Duration::from_secs(due_at - now));
+        Ok(wait.min(MAX_WAIT))
+    });
+    WAKER.with(|w| *w.borrow_mut() = Some(reminder.waker()));
+    reminder
+}
+
+/// Recheck due dates of changed `todos`. Due dates already checked are
+/// checked again, e.g. of todo reopened or restored after its due date.
+pub fn recheck(todos: &[Todo]) {
+    let due_at = todos
+        .iter()
+        .filter(|t| !t.completed)
+        .filter_map(|t| t.due_at)
+        .min();
+    if let Some(due_at) = due_at {
+        CHECKED.set(CHECKED.get().min(due_at.saturating_sub(1)));
+    }
+    wake();
+}
+
+/// Recheck due dates, no-op when reminder is not started
+fn wake() {
+    WAKER.with(|w| {
+        if let Some(waker) = &*w.borrow() {
+            waker.wake();
+        }
+    });
+}
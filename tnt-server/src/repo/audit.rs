@@ -0,0 +1,285 @@
+use crate::repo::RepoErr;
+use crate::repo::RepoSpaces;
+use front_app::{AuditEntry, AuditPage, Todo};
+use tarantool::error::Error as TrtlErr;
+use tarantool::index::IteratorType;
+use tarantool::space::Space;
+use tarantool::tuple::Tuple;
+
+// Operations recorded in audit log
+pub const CREATE: &str = "create";
+pub const DELETE: &str = "delete";
+pub const RESTORE: &str = "restore";
+pub const PURGE: &str = "purge";
+pub const CHANGE_COMPLETED: &str = "change_completed";
+pub const CHANGE_TITLE: &str = "change_title";
+pub const CHANGE_DUE: &str = "change_due";
+pub const CHANGE_TAGS: &str = "change_tags";
+pub const MOVE: &str = "move";
+
+/// Append-only log of todo changes in `todo_audit` space. Changes are
+/// recorded by repo functions in the transaction changing the todo,
+/// entries are never changed or deleted.
+pub struct Audit {
+    space: Space,
+}
+
+impl Audit {
+    pub fn find() -> Result<Audit, RepoErr> {
+        let space = RepoSpaces::TodoAudit.find()?;
+        Ok(Audit { space })
+    }
+
+    /// Record change `op` of todo by `user_id` at `now`, todo is
+    /// missing `before` creation and `after` deletion
+    pub fn record(
+        &self,
+        op: &str,
+        user_id: u32,
+        before: Option<&Todo>,
+        after: Option<&Todo>,
+        now: u64,
+    ) -> Result<(), TrtlErr> {
+        let Some(todo_id) = before.or(after).map(|t| t.id) else {
+            return Ok(());
+        };
+        self.space
+            .insert(&(None::<u32>, todo_id, user_id, op, now, before, after))?;
+        Ok(())
+    }
+}
+
+/// List at most `limit` changes of todos made by `owner_id`, recent
+/// first, starting after entry `after_id`, from the most recent one when
+/// `None`. Only changes of todo `todo_id` are listed when it is set,
+/// `from` and `to` limit change time, both inclusive. Page is empty when
+/// `after_id` is not an entry of the owner.
+/// Returns `Result` with AuditPage on success, otherwise
+/// returns an `repo error`.
+pub fn list_audit(
+    owner_id: u32,
+    todo_id: Option<u32>,
+    from: Option<u64>,
+    to: Option<u64>,
+    after_id: Option<u32>,
+    limit: usize,
+) -> Result<AuditPage, RepoErr> {
+    let space_audit = RepoSpaces::TodoAudit.find()?;
+    let from = from.unwrap_or(0);
+    let mut to = to.unwrap_or(u64::MAX);
+    let mut cursor = None;
+    if let Some(after_id) = after_id {
+        let after = match space_audit.get(&(after_id,))? {
+            Some(tuple) => decode(&tuple)?,
+            None => return Ok(AuditPage::default()),
+        };
+        if after.user_id != owner_id {
+            return Ok(AuditPage::default());
+        }
+        to = to.min(after.at);
+        cursor = Some((after.at, after.id));
+    }
+
+    // Both indexes are ordered by change time after todo or user, and
+    // by id within the same time
+    let (index, prefix) = match todo_id {
+        Some(todo_id) => ("todo_audit_todo", todo_id),
+        None => ("todo_audit_user", owner_id),
+    };
+    let tuples = space_audit
+        .index_cached(index)
+        .ok_or(RepoErr::IndexNotFound(String::from(index)))?
+        .select(IteratorType::LE, &(prefix, to))?;
+
+    // One extra entry tells that the next page exists
+    let mut entries = Vec::with_capacity(limit + 1);
+    for tuple in tuples {
+        let entry = decode(&tuple)?;
+        let entry_prefix = match todo_id {
+            Some(_) => entry.todo_id,
+            None => entry.user_id,
+        };
+        if entry_prefix != prefix || entry.at < from || entries.len() > limit {
+            break;
+        }
+        if cursor.is_some_and(|cursor| (entry.at, entry.id) >= cursor) {
+            continue;
+        }
+        if entry.user_id == owner_id {
+            entries.push(entry);
+        }
+    }
+    let next = if entries.len() > limit {
+        entries.truncate(limit);
+        entries.last().map(|e| e.id)
+    } else {
+        None
+    };
+
+    Ok(AuditPage { entries, next })
+}
+
+/// Decode `todo_audit` tuple
+fn decode(tuple: &Tuple) -> Result<AuditEntry, TrtlErr> {
+    let (id, todo_id, user_id, op, at, before, after) =
+        tuple.decode::<(u32, u32, u32, String, u64, Option<Todo>, Option<Todo>)>()?;
+    Ok(AuditEntry {
+        id,
+        todo_id,
+        user_id,
+        op,
+        at,
+        before,
+        after,
+    })
+}
+
+#[cfg(feature = "test")]
+mod tests {
+    use super::*;
+    use crate::repo::clock;
+    use crate::repo::todo::{change_completed, change_title, create_todo, delete_todo};
+    use crate::repo::trash::{empty_trash, purge_trash, restore_todo};
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn ops(entries: &[AuditEntry]) -> Vec<&str> {
+        entries.iter().map(|e| e.op.as_str()).collect()
+    }
+
+    #[tarantool_test::test]
+    fn audit_todo_changes() {
+        let owner_id = 11001;
+        let _clock = clock::freeze(NOW);
+        let todo = create_todo(owner_id, "Old title").unwrap();
+        let changed = change_title(owner_id, todo.id, "New title").unwrap();
+
+        let entries = list_audit(owner_id, Some(todo.id), None, None, None, 10)
+            .unwrap()
+            .entries;
+        assert_eq!(ops(&entries), [CHANGE_TITLE, CREATE]);
+        let expected = AuditEntry {
+            id: entries[0].id,
+            todo_id: todo.id,
+            user_id: owner_id,
+            op: String::from(CHANGE_TITLE),
+            at: NOW,
+            before: Some(todo.clone()),
+            after: Some(changed),
+        };
+        assert_eq!(entries[0], expected);
+        assert_eq!(entries[1].before, None);
+        assert_eq!(entries[1].after, Some(todo));
+    }
+
+    #[tarantool_test::test]
+    fn audit_delete_and_restore() {
+        let owner_id = 11002;
+        let todo = create_todo(owner_id, "Deleted todo").unwrap();
+        delete_todo(owner_id, todo.id).unwrap();
+        restore_todo(owner_id, todo.id).unwrap();
+
+        let entries = list_audit(owner_id, Some(todo.id), None, None, None, 10)
+            .unwrap()
+            .entries;
+        assert_eq!(ops(&entries), [RESTORE, DELETE, CREATE]);
+        assert_eq!(entries[1].before, Some(todo));
+        assert_eq!(entries[1].after, None);
+    }
+
+    #[tarantool_test::test]
+    fn audit_purge() {
+        let owner_id = 11005;
+        let clock = clock::freeze(NOW);
+        let emptied = create_todo(owner_id, "Emptied todo").unwrap();
+        let expired = create_todo(owner_id, "Expired todo").unwrap();
+        delete_todo(owner_id, expired.id).unwrap();
+        clock.advance(60);
+        purge_trash(NOW).unwrap();
+        delete_todo(owner_id, emptied.id).unwrap();
+        empty_trash(owner_id).unwrap();
+
+        let entries = list_audit(owner_id, Some(expired.id), None, None, None, 10)
+            .unwrap()
+            .entries;
+        assert_eq!(ops(&entries), [PURGE, DELETE, CREATE]);
+        assert_eq!(entries[0].before, Some(expired));
+        assert_eq!(entries[0].after, None);
+        let entries = list_audit(owner_id, Some(emptied.id), None, None, None, 10)
+            .unwrap()
+            .entries;
+        assert_eq!(ops(&entries), [PURGE, DELETE, CREATE]);
+    }
+
+    #[tarantool_test::test]
+    fn audit_in_time_range() {
+        let owner_id = 11003;
+        let clock = clock::freeze(NOW);
+        let first = create_todo(owner_id, "First").unwrap();
+        clock.advance(60);
+        let second = create_todo(owner_id, "Second").unwrap();
+        clock.advance(60);
+        change_completed(owner_id, first.id, true).unwrap();
+
+        let entries = list_audit(owner_id, None, Some(NOW + 60), Some(NOW + 60), None, 10)
+            .unwrap()
+            .entries;
+        assert_eq!(ops(&entries), [CREATE]);
+        assert_eq!(entries[0].todo_id, second.id);
+        let entries = list_audit(owner_id, None, Some(NOW + 60), None, None, 10)
+            .unwrap()
+            .entries;
+        assert_eq!(ops(&entries), [CHANGE_COMPLETED, CREATE]);
+    }
+
+    #[tarantool_test::test]
+    fn audit_by_pages() {
+        let owner_id = 11006;
+        let clock = clock::freeze(NOW);
+        let todo = create_todo(owner_id, "Title 0").unwrap();
+        for i in 1..5 {
+            // Some changes are made at the same time
+            if i % 2 == 0 {
+                clock.advance(60);
+            }
+            change_title(owner_id, todo.id, &format!("Title {i}")).unwrap();
+        }
+        let all = list_audit(owner_id, None, None, None, None, 10)
+            .unwrap()
+            .entries;
+        assert_eq!(all.len(), 5);
+
+        for todo_id in [None, Some(todo.id)] {
+            let mut entries = Vec::new();
+            let mut after_id = None;
+            loop {
+                let page = list_audit(owner_id, todo_id, None, None, after_id, 2).unwrap();
+                assert!(page.entries.len() <= 2);
+                entries.extend(page.entries);
+                after_id = match page.next {
+                    Some(next) => Some(next),
+                    None => break,
+                };
+            }
+            assert_eq!(entries, all);
+        }
+        let other = list_audit(owner_id + 1, None, None, None, Some(all[0].id), 2).unwrap();
+        assert_eq!(other, AuditPage::default());
+    }
+
+    #[tarantool_test::test]
+    fn audit_of_other_user() {
+        let owner_id = 11004;
+        let todo = create_todo(owner_id, "Other owner todo").unwrap();
+        assert_eq!(
+            list_audit(owner_id + 1, None, None, None, None, 10)
+                .unwrap()
+                .entries,
+            Vec::new()
+        );
+        let result = list_audit(owner_id + 1, Some(todo.id), None, None, None, 10)
+            .unwrap()
+            .entries;
+        assert_eq!(result, Vec::new());
+    }
+}
@@ -0,0 +1,278 @@
+use crate::repo::RepoErr;
+use crate::repo::RepoSpaces;
+use front_app::cache::{OwnerTodos, TodoCache, TodoSnapshot};
+use front_app::Todo;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tarantool::error::Error as TrtlErr;
+use tarantool::index::IteratorType;
+use tarantool::tlua;
+use tarantool::tuple::Tuple;
+
+/// `todo` space trigger. Every committed transaction passes committed
+/// tuples of changed todos and ids and owners of deleted ones to
+/// `todo_cache_refresh`, rolled back changes are skipped. Space is not
+/// read, as it may have changes of transactions not committed yet.
+const TRIGGER: &str = r#"
+local space = box.space.todo
+local last_txn
+
+local function trigger()
+    local txn = box.txn_id()
+    if txn == last_txn then
+        return
+    end
+    last_txn = txn
+    box.on_commit(function(iter)
+        -- Last state of every changed todo, false when deleted
+        local states, owners = {}, {}
+        for _, old, new, space_id in iter() do
+            if space_id == space.id then
+                local todo = new or old
+                states[todo[1]] = new or false
+                owners[todo[1]] = todo[4]
+            end
+        end
+        local changed, deleted, deleted_owners = {}, {}, {}
+        for id, new in pairs(states) do
+            if new then
+                table.insert(changed, new)
+            else
+                table.insert(deleted, id)
+                table.insert(deleted_owners, owners[id])
+            end
+        end
+        todo_cache_refresh(changed, deleted, deleted_owners)
+    end)
+end
+
+space:on_replace(trigger, rawget(_G, 'todo_cache_trigger'))
+rawset(_G, 'todo_cache_trigger', trigger)
+"#;
+
+/// Remove `todo` space trigger and refresh function installed by
+/// `install`
+const UNINSTALL: &str = r#"
+local trigger = rawget(_G, 'todo_cache_trigger')
+if trigger ~= nil then
+    box.space.todo:on_replace(nil, trigger)
+end
+rawset(_G, 'todo_cache_trigger', nil)
+rawset(_G, 'todo_cache_refresh', nil)
+"#;
+
+/// Load all todos into `cache` and install `todo` space trigger keeping
+/// it up to date. Installing again replaces previous trigger.
+pub fn install(cache: &TodoCache) -> Result<(), RepoErr> {
+    let mut owners: BTreeMap<u32, OwnerTodos> = BTreeMap::new();
+    for tuple in RepoSpaces::Todo.find()?.select(IteratorType::All, &())? {
+        let todo = tuple.decode::<Todo>()?;
+        owners.entry(todo.owner_id).or_default().insert(todo);
+    }
+    let snapshot = TodoSnapshot {
+        version: cache.snapshot().version + 1,
+        owners: owners
+            .into_iter()
+            .map(|(owner_id, todos)| (owner_id, Arc::new(todos)))
+            .collect(),
+    };
+    cache.publish(snapshot);
+
+    let lua = tarantool::lua_state();
+    let refresh_cache = cache.clone();
+    lua.set(
+        "todo_cache_refresh",
+        tlua::Function::new(
+            move |changed: Vec<Tuple>, deleted: Vec<u32>, owners: Vec<u32>| {
+                let changed = changed.iter().filter_map(|t| t.decode::<Todo>().ok());
+                refresh(&refresh_cache, changed, &deleted, &owners)
+            },
+        ),
+    );
+    lua.exec(TRIGGER).map_err(TrtlErr::from)?;
+
+    Ok(())
+}
+
+/// Remove `todo` space trigger, cache is not refreshed anymore
+pub fn uninstall() -> Result<(), RepoErr> {
+    let lua = tarantool::lua_state();
+    lua.exec(UNINSTALL).map_err(TrtlErr::from)?;
+    Ok(())
+}
+
+/// Publish new snapshot with `changed` todos and without `deleted` ones
+/// of `owners`. Only todos of owners of changed todos are copied, todos
+/// of other owners are shared with current snapshot.
+fn refresh(
+    cache: &TodoCache,
+    changed: impl Iterator<Item = Todo>,
+    deleted: &[u32],
+    owners: &[u32],
+) {
+    let mut snapshot = TodoSnapshot::clone(&cache.snapshot());
+    for todo in changed {
+        let todos = Arc::make_mut(snapshot.owners.entry(todo.owner_id).or_default());
+        todos.insert(todo);
+    }
+    for (&id, &owner_id) in deleted.iter().zip(owners) {
+        let Some(todos) = snapshot.owners.get_mut(&owner_id) else {
+            continue;
+        };
+        let todos = Arc::make_mut(todos);
+        todos.remove(id);
+        if todos.is_empty() {
+            snapshot.owners.remove(&owner_id);
+        }
+    }
+    snapshot.version += 1;
+
+    cache.publish(snapshot);
+}
+
+#[cfg(feature = "test")]
+mod tests {
+    use super::*;
+    use crate::repo::todo::{
+        change_all_completed, change_completed, change_title, count_todos, create_todo,
+        delete_todo, list_todos, list_todos_page, move_todo,
+    };
+    use tarantool::transaction::{transaction, TransactionError};
+
+    const OWNER_ID: u32 = 5001;
+
+    #[tarantool_test::test]
+    fn cache_follows_todo_space() {
+        let cache = TodoCache::default();
+        install(&cache).unwrap();
+        assert_eq!(cache.todos(OWNER_ID), list_todos(OWNER_ID).unwrap());
+
+        let todo = create_todo(OWNER_ID, "Cached todo").unwrap();
+        change_completed(OWNER_ID, todo.id, true).unwrap();
+        change_title(OWNER_ID, todo.id, "Cached todo 2").unwrap();
+        let deleted = create_todo(OWNER_ID, "Deleted cached todo").unwrap();
+        delete_todo(OWNER_ID, deleted.id).unwrap();
+        change_all_completed(OWNER_ID, false).unwrap();
+
+        assert_eq!(cache.todos(OWNER_ID), list_todos(OWNER_ID).unwrap());
+    }
+
+    #[tarantool_test::test]
+    fn cache_takes_last_state_of_transaction() {
+        let owner_id = OWNER_ID + 3;
+        let cache = TodoCache::default();
+        install(&cache).unwrap();
+        let kept = create_todo(owner_id, "Kept todo").unwrap();
+
+        transaction(|| -> Result<(), RepoErr> {
+            let todo = create_todo(owner_id, "Changed todo")?;
+            change_title(owner_id, todo.id, "Changed todo 2")?;
+            change_completed(owner_id, todo.id, true)?;
+            let deleted = create_todo(owner_id, "Deleted todo")?;
+            delete_todo(owner_id, deleted.id)?;
+            delete_todo(owner_id, kept.id)?;
+            Ok(())
+        })
+        .unwrap();
+
+        let todos = cache.todos(owner_id);
+        assert_eq!(todos, list_todos(owner_id).unwrap());
+        assert_eq!(todos.len(), 1);
+        assert_eq!(todos[0].title, "Changed todo 2");
+    }
+
+    #[tarantool_test::test]
+    fn cache_version_grows() {
+        let cache = TodoCache::default();
+        install(&cache).unwrap();
+        let version = cache.snapshot().version;
+
+        create_todo(OWNER_ID, "Versioned todo").unwrap();
+
+        assert_eq!(cache.snapshot().version, version + 1);
+    }
+
+    #[tarantool_test::test]
+    fn cache_skips_rolled_back_changes() {
+        let cache = TodoCache::default();
+        install(&cache).unwrap();
+        let version = cache.snapshot().version;
+
+        let result = transaction(|| -> Result<(), RepoErr> {
+            create_todo(OWNER_ID, "Rolled back todo")?;
+            Err(RepoErr::EmptyTodoTitle)
+        });
+
+        // Todo was inserted, transaction failed after it
+        assert!(matches!(
+            result,
+            Err(TransactionError::RolledBack(RepoErr::EmptyTodoTitle))
+        ));
+        assert_eq!(cache.snapshot().version, version);
+        assert_eq!(cache.todos(OWNER_ID), list_todos(OWNER_ID).unwrap());
+    }
+
+    #[tarantool_test::test]
+    fn cache_shares_unchanged_owners() {
+        let cache = TodoCache::default();
+        create_todo(OWNER_ID, "Copied todo").unwrap();
+        create_todo(OWNER_ID + 1, "Shared todo").unwrap();
+        install(&cache).unwrap();
+        let before = cache.snapshot();
+
+        create_todo(OWNER_ID, "Changed owner todo").unwrap();
+
+        let after = cache.snapshot();
+        let other = OWNER_ID + 1;
+        assert!(Arc::ptr_eq(&before.owners[&other], &after.owners[&other]));
+        assert!(!Arc::ptr_eq(
+            &before.owners[&OWNER_ID],
+            &after.owners[&OWNER_ID]
+        ));
+    }
+
+    #[tarantool_test::test]
+    fn cache_stops_following_after_uninstall() {
+        let cache = TodoCache::default();
+        install(&cache).unwrap();
+        uninstall().unwrap();
+        let version = cache.snapshot().version;
+
+        create_todo(OWNER_ID, "Uncached todo").unwrap();
+
+        assert_eq!(cache.snapshot().version, version);
+        let lua = tarantool::lua_state();
+        let removed = lua
+            .eval::<bool>("return rawget(_G, 'todo_cache_refresh') == nil")
+            .unwrap();
+        assert!(removed);
+    }
+
+    #[tarantool_test::test]
+    fn cache_pages_follow_moves() {
+        let owner_id = OWNER_ID + 2;
+        let cache = TodoCache::default();
+        install(&cache).unwrap();
+        let todos: Vec<_> = (1..6)
+            .map(|i| create_todo(owner_id, &format!("Todo {i}")).unwrap())
+            .collect();
+        move_todo(owner_id, todos[4].id, Some(todos[1].id)).unwrap();
+        change_completed(owner_id, todos[2].id, true).unwrap();
+
+        for completed in [None, Some(false), Some(true)] {
+            let mut after_id = None;
+            loop {
+                let page = cache.todo_page(owner_id, after_id, 2, completed).unwrap();
+                let expected = list_todos_page(owner_id, after_id, 2, completed).unwrap();
+                assert_eq!(page, expected);
+                after_id = match page.next {
+                    Some(next) => Some(next),
+                    None => break,
+                };
+            }
+        }
+        let other = create_todo(OWNER_ID + 1, "Other owner todo").unwrap();
+        assert_eq!(cache.todo_page(owner_id, Some(other.id), 2, None), None);
+        assert_eq!(cache.todo_counts(owner_id), count_todos(owner_id).unwrap());
+    }
+}
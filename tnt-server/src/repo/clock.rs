@@ -0,0 +1,43 @@
+use std::cell::Cell;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+thread_local! {
+    /// Time returned instead of system time while frozen by a test
+    static FROZEN: Cell<Option<u64>> = Cell::new(None);
+}
+
+/// Current unix time in seconds. Repo takes time only from here, so
+/// tests can freeze it.
+pub fn now() -> u64 {
+    FROZEN.with(Cell::get).unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs())
+    })
+}
+
+/// Freeze `now` at `time` until returned guard is dropped
+#[cfg(feature = "test")]
+pub fn freeze(time: u64) -> Frozen {
+    FROZEN.with(|f| f.set(Some(time)));
+    Frozen(())
+}
+
+/// Guard of frozen clock
+#[cfg(feature = "test")]
+pub struct Frozen(());
+
+#[cfg(feature = "test")]
+impl Frozen {
+    /// Move frozen time `secs` forward
+    pub fn advance(&self, secs: u64) {
+        FROZEN.with(|f| f.set(f.get().map(|time| time + secs)));
+    }
+}
+
+#[cfg(feature = "test")]
+impl Drop for Frozen {
+    fn drop(&mut self) {
+        FROZEN.with(|f| f.set(None));
+    }
+}
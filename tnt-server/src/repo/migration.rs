@@ -0,0 +1,798 @@
+use crate::repo::search::SearchIndex;
+use crate::repo::{clock, RepoErr, RepoSpaces};
+use std::collections::HashMap;
+use tarantool::error::Error as TrtlErr;
+use tarantool::index::{IteratorType, SequenceOpt};
+use tarantool::space::{FieldType, Space, UpdateOps};
+use tarantool::transaction::transaction;
+
+/// Space with versions of applied migrations
+const MIGRATIONS_SPACE: &str = "_migrations";
+
+/// Versioned schema change. Applied once, in order of versions.
+pub struct Migration {
+    pub version: u32,
+    pub name: &'static str,
+    pub up: fn() -> Result<(), RepoErr>,
+    /// Tarantool can't check format or build index of a filled space
+    /// inside a transaction. Such migrations run without one and must be
+    /// idempotent, so a failed migration is completed by the next start.
+    pub transactional: bool,
+}
+
+/// Application schema migrations, ordered by version. Released migrations
+/// must never change, schema changes are added as new migrations.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "create user and todo spaces",
+        up: create_user_and_todo,
+        transactional: true,
+    },
+    Migration {
+        version: 2,
+        name: "add todo owner",
+        up: add_todo_owner,
+        transactional: false,
+    },
+    Migration {
+        version: 3,
+        name: "add user password",
+        up: add_user_password,
+        transactional: false,
+    },
+    Migration {
+        version: 4,
+        name: "create session space",
+        up: create_session_space,
+        transactional: true,
+    },
+    Migration {
+        version: 5,
+        name: "add todo position",
+        up: add_todo_position,
+        transactional: false,
+    },
+    Migration {
+        version: 6,
+        name: "add todo timestamps",
+        up: add_todo_timestamps,
+        transactional: false,
+    },
+    Migration {
+        version: 7,
+        name: "add todo due date",
+        up: add_todo_due,
+        transactional: false,
+    },
+    Migration {
+        version: 8,
+        name: "create notification space",
+        up: create_notification_space,
+        transactional: true,
+    },
+    Migration {
+        version: 9,
+        name: "add todo tags",
+        up: add_todo_tags,
+        transactional: false,
+    },
+    Migration {
+        version: 10,
+        name: "create todo search index",
+        up: create_todo_token_space,
+        transactional: false,
+    },
+    Migration {
+        version: 11,
+        name: "create todo trash space",
+        up: create_todo_trash_space,
+        transactional: true,
+    },
+    Migration {
+        version: 12,
+        name: "create undo space",
+        up: create_undo_space,
+        transactional: true,
+    },
+    Migration {
+        version: 13,
+        name: "create todo audit space",
+        up: create_todo_audit_space,
+        transactional: true,
+    },
+    Migration {
+        version: 14,
+        name: "add undo user index",
+        up: add_undo_user_index,
+        transactional: false,
+    },
+];
+
+/// Name of the user owning todos created before todo ownership
+const DEFAULT_USER: &str = "default";
+
+/// `todo` format with owner
+const TODO_OWNER_FORMAT: &str = r#"
+box.space.todo:format({
+    {name = 'id', type = 'unsigned'},
+    {name = 'title', type = 'string'},
+    {name = 'completed', type = 'boolean'},
+    {name = 'owner_id', type = 'unsigned'},
+})
+"#;
+
+/// `user` format with password hash
+const USER_PASSWORD_FORMAT: &str = r#"
+box.space.user:format({
+    {name = 'id', type = 'unsigned'},
+    {name = 'name', type = 'string'},
+    {name = 'password_hash', type = 'string'},
+})
+"#;
+
+/// `todo` format with position
+const TODO_POSITION_FORMAT: &str = r#"
+box.space.todo:format({
+    {name = 'id', type = 'unsigned'},
+    {name = 'title', type = 'string'},
+    {name = 'completed', type = 'boolean'},
+    {name = 'owner_id', type = 'unsigned'},
+    {name = 'position', type = 'unsigned'},
+})
+"#;
+
+/// `todo` format with timestamps
+const TODO_TIMESTAMPS_FORMAT: &str = r#"
+box.space.todo:format({
+    {name = 'id', type = 'unsigned'},
+    {name = 'title', type = 'string'},
+    {name = 'completed', type = 'boolean'},
+    {name = 'owner_id', type = 'unsigned'},
+    {name = 'position', type = 'unsigned'},
+    {name = 'created_at', type = 'unsigned'},
+    {name = 'updated_at', type = 'unsigned'},
+    {name = 'completed_at', type = 'unsigned', is_nullable = true},
+})
+"#;
+
+/// `todo` format with due date
+const TODO_DUE_FORMAT: &str = r#"
+box.space.todo:format({
+    {name = 'id', type = 'unsigned'},
+    {name = 'title', type = 'string'},
+    {name = 'completed', type = 'boolean'},
+    {name = 'owner_id', type = 'unsigned'},
+    {name = 'position', type = 'unsigned'},
+    {name = 'created_at', type = 'unsigned'},
+    {name = 'updated_at', type = 'unsigned'},
+    {name = 'completed_at', type = 'unsigned', is_nullable = true},
+    {name = 'due_at', type = 'unsigned', is_nullable = true},
+})
+"#;
+
+/// Index of todos by due date, todos without due date are indexed too
+const TODO_DUE_INDEX: &str = r#"
+box.space.todo:create_index('todo_due', {
+    parts = {{'due_at', 'unsigned', is_nullable = true}},
+    unique = false,
+    if_not_exists = true,
+})
+"#;
+
+/// `todo` format with tags
+const TODO_TAGS_FORMAT: &str = r#"
+box.space.todo:format({
+    {name = 'id', type = 'unsigned'},
+    {name = 'title', type = 'string'},
+    {name = 'completed', type = 'boolean'},
+    {name = 'owner_id', type = 'unsigned'},
+    {name = 'position', type = 'unsigned'},
+    {name = 'created_at', type = 'unsigned'},
+    {name = 'updated_at', type = 'unsigned'},
+    {name = 'completed_at', type = 'unsigned', is_nullable = true},
+    {name = 'due_at', type = 'unsigned', is_nullable = true},
+    {name = 'tags', type = 'array'},
+})
+"#;
+
+/// Multikey index of todos by owner and every tag
+const TODO_TAG_INDEX: &str = r#"
+box.space.todo:create_index('todo_tag', {
+    parts = {
+        {field = 'owner_id', type = 'unsigned'},
+        {field = 'tags', type = 'string', path = '[*]'},
+    },
+    unique = false,
+    if_not_exists = true,
+})
+"#;
+
+/// Distance between positions given to existing todos
+const TODO_POSITION_GAP: u64 = 1 << 16;
+
+/// Apply pending application migrations.
+/// Returns `Result` with schema version on success, otherwise
+/// returns an `repo error`.
+pub fn migrate() -> Result<u32, RepoErr> {
+    migrate_with(MIGRATIONS)
+}
+
+/// Apply pending `migrations`, transactional migration runs in its own
+/// transaction together with recording its version. Schema of a version
+/// unknown to `migrations` is refused.
+fn migrate_with(migrations: &[Migration]) -> Result<u32, RepoErr> {
+    let space = migrations_space()?;
+    let applied = schema_version()?;
+    let latest = migrations.last().map_or(0, |m| m.version);
+
+    if applied > latest {
+        return Err(RepoErr::UnknownSchemaVersion(applied));
+    }
+
+    for migration in migrations.iter().filter(|m| m.version > applied) {
+        let apply = || -> Result<(), RepoErr> {
+            (migration.up)()?;
+            space.insert(&(migration.version, migration.name))?;
+            Ok(())
+        };
+        let res = if migration.transactional {
+            transaction(apply).map_err(|err| err.to_string())
+        } else {
+            apply().map_err(|err| err.to_string())
+        };
+        res.map_err(|msg| RepoErr::MigrationFailed(migration.version, msg))?;
+    }
+
+    schema_version()
+}
+
+/// Version of the last applied migration, 0 for empty database
+pub fn schema_version() -> Result<u32, RepoErr> {
+    let version = migrations_space()?
+        .primary_key()
+        .max(&())?
+        .and_then(|t| t.field::<u32>(0).ok().flatten())
+        .unwrap_or(0);
+
+    Ok(version)
+}
+
+fn migrations_space() -> Result<Space, RepoErr> {
+    let space = Space::builder(MIGRATIONS_SPACE)
+        .format([
+            ("version", FieldType::Unsigned),
+            ("name", FieldType::String),
+        ])
+        .if_not_exists(true)
+        .create()?;
+
+    space
+        .index_builder("migrations_idx")
+        .part("version")
+        .if_not_exists(true)
+        .create()?;
+
+    Ok(space)
+}
+
+/// Version 1: spaces of the initial release. Existing spaces are kept.
+fn create_user_and_todo() -> Result<(), RepoErr> {
+    let user_space = Space::builder("user")
+        .format([("id", FieldType::Unsigned), ("name", FieldType::String)])
+        .if_not_exists(true)
+        .create()?;
+
+    user_space
+        .index_builder("user_idx")
+        .part("id")
+        .sequence(SequenceOpt::AutoGenerated(true))
+        .if_not_exists(true)
+        .create()?;
+
+    user_space
+        .index_builder("user_name")
+        .part("name")
+        .if_not_exists(true)
+        .create()?;
+
+    let todo_space = Space::builder("todo")
+        .format([
+            ("id", FieldType::Unsigned),
+            ("title", FieldType::String),
+            ("completed", FieldType::Boolean),
+        ])
+        .if_not_exists(true)
+        .create()?;
+
+    todo_space
+        .index_builder("todo_idx")
+        .part("id")
+        .sequence(SequenceOpt::AutoGenerated(true))
+        .if_not_exists(true)
+        .create()?;
+
+    Ok(())
+}
+
+/// Version 2: todo `owner_id` field with index. Todos without owner are
+/// given to `default` user.
+fn add_todo_owner() -> Result<(), RepoErr> {
+    let todo_space = RepoSpaces::Todo.find()?;
+
+    let unowned: Vec<u32> = todo_space
+        .select(IteratorType::All, &())?
+        .filter(|t| t.len() < 4)
+        .filter_map(|t| t.field::<u32>(0).ok().flatten())
+        .collect();
+    if !unowned.is_empty() {
+        let owner_id = default_user()?;
+        for id in unowned {
+            todo_space.update(&[id], [("!", 3, owner_id)])?;
+        }
+    }
+
+    tarantool::lua_state()
+        .exec(TODO_OWNER_FORMAT)
+        .map_err(TrtlErr::from)?;
+
+    todo_space
+        .index_builder("todo_owner")
+        .part("owner_id")
+        .unique(false)
+        .if_not_exists(true)
+        .create()?;
+
+    Ok(())
+}
+
+/// Version 3: user `password_hash` field. Users created before passwords
+/// get an empty hash, they can't sign in until password is set.
+fn add_user_password() -> Result<(), RepoErr> {
+    let user_space = RepoSpaces::User.find()?;
+
+    let without_password: Vec<u32> = user_space
+        .select(IteratorType::All, &())?
+        .filter(|t| t.len() < 3)
+        .filter_map(|t| t.field::<u32>(0).ok().flatten())
+        .collect();
+    for id in without_password {
+        user_space.update(&[id], [("!", 2, "")])?;
+    }
+
+    tarantool::lua_state()
+        .exec(USER_PASSWORD_FORMAT)
+        .map_err(TrtlErr::from)?;
+
+    Ok(())
+}
+
+/// Version 4: sessions of signed in users, looked up by token
+fn create_session_space() -> Result<(), RepoErr> {
+    let session_space = Space::builder("session")
+        .format([
+            ("token", FieldType::String),
+            ("user_id", FieldType::Unsigned),
+            ("expires_at", FieldType::Unsigned),
+        ])
+        .if_not_exists(true)
+        .create()?;
+
+    session_space
+        .index_builder("session_idx")
+        .part("token")
+        .if_not_exists(true)
+        .create()?;
+
+    session_space
+        .index_builder("session_user")
+        .part("user_id")
+        .unique(false)
+        .if_not_exists(true)
+        .create()?;
+
+    session_space
+        .index_builder("session_expires")
+        .part("expires_at")
+        .unique(false)
+        .if_not_exists(true)
+        .create()?;
+
+    Ok(())
+}
+
+/// Version 5: todo `position` field with index by owner and position.
+/// Existing todos of every owner are positioned in id order.
+fn add_todo_position() -> Result<(), RepoErr> {
+    let todo_space = RepoSpaces::Todo.find()?;
+
+    let mut last_position = HashMap::<u32, u64>::new();
+    let todos: Vec<(u32, u32, Option<u64>)> = todo_space
+        .select(IteratorType::All, &())?
+        .filter_map(|t| {
+            let id = t.field::<u32>(0).ok().flatten()?;
+            let owner_id = t.field::<u32>(3).ok().flatten()?;
+            Some((id, owner_id, t.field::<u64>(4).ok().flatten()))
+        })
+        .collect();
+    for &(_, owner_id, position) in &todos {
+        if let Some(position) = position {
+            let last = last_position.entry(owner_id).or_default();
+            *last = (*last).max(position);
+        }
+    }
+    for (id, owner_id, position) in todos {
+        if position.is_none() {
+            let last = last_position.entry(owner_id).or_default();
+            *last += TODO_POSITION_GAP;
+            todo_space.update(&[id], [("!", 4, *last)])?;
+        }
+    }
+
+    tarantool::lua_state()
+        .exec(TODO_POSITION_FORMAT)
+        .map_err(TrtlErr::from)?;
+
+    todo_space
+        .index_builder("todo_position")
+        .part("owner_id")
+        .part("position")
+        .unique(false)
+        .if_not_exists(true)
+        .create()?;
+
+    Ok(())
+}
+
+/// Version 6: todo `created_at`, `updated_at` and `completed_at` fields.
+/// Time of existing todos is unknown, migration time is used.
+fn add_todo_timestamps() -> Result<(), RepoErr> {
+    let todo_space = RepoSpaces::Todo.find()?;
+    let now = clock::now();
+
+    let todos: Vec<(u32, bool)> = todo_space
+        .select(IteratorType::All, &())?
+        .filter(|t| t.len() < 8)
+        .filter_map(|t| {
+            let id = t.field::<u32>(0).ok().flatten()?;
+            let completed = t.field::<bool>(2).ok().flatten()?;
+            Some((id, completed))
+        })
+        .collect();
+    for (id, completed) in todos {
+        let mut ops = UpdateOps::new();
+        ops.insert(5, now)?;
+        ops.insert(6, now)?;
+        ops.insert(7, completed.then_some(now))?;
+        todo_space.update(&[id], ops.as_slice())?;
+    }
+
+    tarantool::lua_state()
+        .exec(TODO_TIMESTAMPS_FORMAT)
+        .map_err(TrtlErr::from)?;
+
+    Ok(())
+}
+
+/// Version 7: todo `due_at` field with index. Existing todos have no
+/// due date.
+fn add_todo_due() -> Result<(), RepoErr> {
+    let todo_space = RepoSpaces::Todo.find()?;
+
+    let without_due: Vec<u32> = todo_space
+        .select(IteratorType::All, &())?
+        .filter(|t| t.len() < 9)
+        .filter_map(|t| t.field::<u32>(0).ok().flatten())
+        .collect();
+    for id in without_due {
+        todo_space.update(&[id], [("!", 8, None::<u64>)])?;
+    }
+
+    let lua = tarantool::lua_state();
+    lua.exec(TODO_DUE_FORMAT).map_err(TrtlErr::from)?;
+    lua.exec(TODO_DUE_INDEX).map_err(TrtlErr::from)?;
+
+    Ok(())
+}
+
+/// Version 8: notifications of users, e.g. about overdue todos. Todo has
+/// at most one notification of a kind.
+fn create_notification_space() -> Result<(), RepoErr> {
+    let notification_space = Space::builder("notification")
+        .format([
+            ("id", FieldType::Unsigned),
+            ("user_id", FieldType::Unsigned),
+            ("todo_id", FieldType::Unsigned),
+            ("kind", FieldType::String),
+            ("created_at", FieldType::Unsigned),
+        ])
+        .if_not_exists(true)
+        .create()?;
+
+    notification_space
+        .index_builder("notification_idx")
+        .part("id")
+        .sequence(SequenceOpt::AutoGenerated(true))
+        .if_not_exists(true)
+        .create()?;
+
+    notification_space
+        .index_builder("notification_user")
+        .part("user_id")
+        .unique(false)
+        .if_not_exists(true)
+        .create()?;
+
+    notification_space
+        .index_builder("notification_todo")
+        .part("todo_id")
+        .part("kind")
+        .if_not_exists(true)
+        .create()?;
+
+    Ok(())
+}
+
+/// Version 9: todo `tags` field with multikey index by owner and tag.
+/// Existing todos have no tags.
+fn add_todo_tags() -> Result<(), RepoErr> {
+    let todo_space = RepoSpaces::Todo.find()?;
+
+    let without_tags: Vec<u32> = todo_space
+        .select(IteratorType::All, &())?
+        .filter(|t| t.len() < 10)
+        .filter_map(|t| t.field::<u32>(0).ok().flatten())
+        .collect();
+    for id in without_tags {
+        todo_space.update(&[id], [("!", 9, Vec::<String>::new())])?;
+    }
+
+    let lua = tarantool::lua_state();
+    lua.exec(TODO_TAGS_FORMAT).map_err(TrtlErr::from)?;
+    lua.exec(TODO_TAG_INDEX).map_err(TrtlErr::from)?;
+
+    Ok(())
+}
+
+/// Version 10: inverted index of todo titles in `todo_token` space.
+/// Existing todos are indexed, indexing again replaces their tokens.
+fn create_todo_token_space() -> Result<(), RepoErr> {
+    let token_space = Space::builder("todo_token")
+        .format([
+            ("owner_id", FieldType::Unsigned),
+            ("token", FieldType::String),
+            ("todo_id", FieldType::Unsigned),
+        ])
+        .if_not_exists(true)
+        .create()?;
+
+    token_space
+        .index_builder("todo_token_idx")
+        .part("owner_id")
+        .part("token")
+        .part("todo_id")
+        .if_not_exists(true)
+        .create()?;
+
+    token_space
+        .index_builder("todo_token_todo")
+        .part("todo_id")
+        .unique(false)
+        .if_not_exists(true)
+        .create()?;
+
+    let search_index = SearchIndex::find()?;
+    let todos: Vec<(u32, String, u32)> = RepoSpaces::Todo
+        .find()?
+        .select(IteratorType::All, &())?
+        .filter_map(|t| {
+            let id = t.field::<u32>(0).ok().flatten()?;
+            let title = t.field::<String>(1).ok().flatten()?;
+            let owner_id = t.field::<u32>(3).ok().flatten()?;
+            Some((id, title, owner_id))
+        })
+        .collect();
+    for (id, title, owner_id) in todos {
+        search_index.index(id, owner_id, &title)?;
+    }
+
+    Ok(())
+}
+
+/// Version 11: deleted todos kept until restored or purged
+fn create_todo_trash_space() -> Result<(), RepoErr> {
+    let trash_space = Space::builder("todo_trash")
+        .format([
+            ("id", FieldType::Unsigned),
+            ("owner_id", FieldType::Unsigned),
+            ("deleted_at", FieldType::Unsigned),
+            ("todo", FieldType::Array),
+        ])
+        .if_not_exists(true)
+        .create()?;
+
+    trash_space
+        .index_builder("todo_trash_idx")
+        .part("id")
+        .if_not_exists(true)
+        .create()?;
+
+    trash_space
+        .index_builder("todo_trash_owner")
+        .part("owner_id")
+        .unique(false)
+        .if_not_exists(true)
+        .create()?;
+
+    trash_space
+        .index_builder("todo_trash_deleted")
+        .part("deleted_at")
+        .unique(false)
+        .if_not_exists(true)
+        .create()?;
+
+    Ok(())
+}
+
+/// Version 12: inverse operations of destructive actions, looked up by
+/// undo token
+fn create_undo_space() -> Result<(), RepoErr> {
+    let undo_space = Space::builder("undo")
+        .format([
+            ("token", FieldType::String),
+            ("user_id", FieldType::Unsigned),
+            ("expires_at", FieldType::Unsigned),
+            ("op", FieldType::Any),
+        ])
+        .if_not_exists(true)
+        .create()?;
+
+    undo_space
+        .index_builder("undo_idx")
+        .part("token")
+        .if_not_exists(true)
+        .create()?;
+
+    undo_space
+        .index_builder("undo_expires")
+        .part("expires_at")
+        .unique(false)
+        .if_not_exists(true)
+        .create()?;
+
+    Ok(())
+}
+
+/// Version 13: append-only log of todo changes, looked up by todo or
+/// user and change time. Changes made before are not logged.
+fn create_todo_audit_space() -> Result<(), RepoErr> {
+    let audit_space = Space::builder("todo_audit")
+        .format([
+            ("id", FieldType::Unsigned),
+            ("todo_id", FieldType::Unsigned),
+            ("user_id", FieldType::Unsigned),
+            ("op", FieldType::String),
+            ("at", FieldType::Unsigned),
+            ("before", FieldType::Any),
+            ("after", FieldType::Any),
+        ])
+        .if_not_exists(true)
+        .create()?;
+
+    audit_space
+        .index_builder("todo_audit_idx")
+        .part("id")
+        .sequence(SequenceOpt::AutoGenerated(true))
+        .if_not_exists(true)
+        .create()?;
+
+    audit_space
+        .index_builder("todo_audit_todo")
+        .part("todo_id")
+        .part("at")
+        .unique(false)
+        .if_not_exists(true)
+        .create()?;
+
+    audit_space
+        .index_builder("todo_audit_user")
+        .part("user_id")
+        .part("at")
+        .unique(false)
+        .if_not_exists(true)
+        .create()?;
+
+    Ok(())
+}
+
+/// Version 14: undo actions looked up by user, deleted with the user
+fn add_undo_user_index() -> Result<(), RepoErr> {
+    RepoSpaces::Undo
+        .find()?
+        .index_builder("undo_user")
+        .part("user_id")
+        .unique(false)
+        .if_not_exists(true)
+        .create()?;
+
+    Ok(())
+}
+
+/// Id of `default` user, user is created if missing
+fn default_user() -> Result<u32, RepoErr> {
+    let user_space = RepoSpaces::User.find()?;
+    let user_name = user_space
+        .index_cached("user_name")
+        .ok_or(RepoErr::IndexNotFound(String::from("user_name")))?;
+
+    let user = match user_name.get(&(DEFAULT_USER,))? {
+        Some(user) => user,
+        None => user_space.insert(&(None::<u32>, DEFAULT_USER))?,
+    };
+
+    user.field::<u32>(0)?
+        .ok_or(RepoErr::FieldNotExsist(String::from("id")))
+}
+
+#[cfg(feature = "test")]
+mod tests {
+    use super::*;
+    use crate::repo::todo::{create_todo, list_todos};
+
+    const OWNER_ID: u32 = 2001;
+
+    fn latest_version() -> u32 {
+        MIGRATIONS.last().map_or(0, |m| m.version)
+    }
+
+    fn failing_migration() -> Result<(), RepoErr> {
+        create_todo(OWNER_ID, "Migrated todo")?;
+        Err(RepoErr::EmptyTodoTitle)
+    }
+
+    #[tarantool_test::test]
+    fn migrations_ordered() {
+        for (i, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version, i as u32 + 1);
+        }
+    }
+
+    #[tarantool_test::test]
+    fn migrate_twice() {
+        assert_eq!(migrate().unwrap(), latest_version());
+        assert_eq!(migrate().unwrap(), latest_version());
+    }
+
+    #[tarantool_test::test]
+    fn refuse_newer_schema() {
+        let newer = latest_version() + 1;
+        let space = migrations_space().unwrap();
+        space.insert(&(newer, "newer migration")).unwrap();
+
+        let result = migrate();
+        space.delete(&(newer,)).unwrap();
+
+        assert_eq!(result, Err(RepoErr::UnknownSchemaVersion(newer)));
+    }
+
+    #[tarantool_test::test]
+    fn rollback_failed_migration() {
+        let failing = Migration {
+            version: latest_version() + 1,
+            name: "failing migration",
+            up: failing_migration,
+            transactional: true,
+        };
+        let result = migrate_with(&[failing]);
+
+        // Todo was inserted, migration failed after it
+        let empty_title = RepoErr::EmptyTodoTitle.to_string();
+        assert!(matches!(
+            result,
+            Err(RepoErr::MigrationFailed(_, msg)) if msg.contains(&empty_title)
+        ));
+        assert_eq!(schema_version().unwrap(), latest_version());
+        let todos = list_todos(OWNER_ID).unwrap();
+        assert!(!todos.iter().any(|t| t.title == "Migrated todo"));
+    }
+}
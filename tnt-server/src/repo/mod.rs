@@ -1,72 +1,250 @@
 use core::fmt;
+use front_app::AppErr;
+use schema::SchemaDiff;
 use std::error::Error;
 use strum_macros::EnumIter;
 use tarantool::error::Error as TrtlErr;
-use tarantool::index::SequenceOpt;
-use tarantool::space::{FieldType, Space};
-use tarantool::transaction::TransactionError;
+use tarantool::space::Space;
+use tarantool::transaction::{self, TransactionError};
 
+pub mod audit;
+pub mod cache;
+pub mod clock;
+pub mod migration;
+pub mod notification;
+pub mod schema;
+pub mod search;
+pub mod session;
 pub mod todo;
+pub mod trash;
+pub mod undo;
 pub mod user;
 
+/// Run `f` in a new transaction, or in the caller's one when it is
+/// already open, so repo functions can be combined in one transaction.
+/// Error of `f` in the caller's transaction is returned to the caller,
+/// which rolls the whole transaction back.
+pub fn transaction<T, E, F>(f: F) -> Result<T, TransactionError<E>>
+where
+    F: FnOnce() -> Result<T, E>,
+{
+    if transaction::is_in_transaction() {
+        f().map_err(TransactionError::RolledBack)
+    } else {
+        transaction::transaction(f)
+    }
+}
+
+/// Index expected by repo functions, parts are field names
+#[derive(Debug, PartialEq)]
+pub struct IndexSpec {
+    pub name: &'static str,
+    pub parts: &'static [&'static str],
+}
+
 /// Tarantool Spaces used by application
 #[derive(EnumIter, Debug, PartialEq)]
 pub enum RepoSpaces {
     User,
     Todo,
+    Session,
+    Notification,
+    TodoToken,
+    TodoTrash,
+    Undo,
+    TodoAudit,
 }
 
 impl RepoSpaces {
-    pub fn create(&self) -> Result<(), RepoErr> {
-        let space_name = self.name();
+    pub fn name(&self) -> &'static str {
         match self {
-            Self::User => {
-                let user_space = Space::builder(space_name)
-                    .format([("id", FieldType::Unsigned), ("name", FieldType::String)])
-                    .if_not_exists(true)
-                    .create()?;
-
-                user_space
-                    .index_builder("user_idx")
-                    .part("id")
-                    .sequence(SequenceOpt::AutoGenerated(true))
-                    .if_not_exists(true)
-                    .create()?;
-
-                user_space
-                    .index_builder("user_name")
-                    .part("name")
-                    .if_not_exists(true)
-                    .create()?;
-
-                Ok(())
-            }
-            Self::Todo => {
-                let todo_space = Space::builder("todo")
-                    .format([
-                        ("id", FieldType::Unsigned),
-                        ("title", FieldType::String),
-                        ("completed", FieldType::Boolean),
-                    ])
-                    .if_not_exists(true)
-                    .create()?;
-
-                todo_space
-                    .index_builder("todo_idx")
-                    .part("id")
-                    .sequence(SequenceOpt::AutoGenerated(true))
-                    .if_not_exists(true)
-                    .create()?;
+            Self::User => "user",
+            Self::Todo => "todo",
+            Self::Session => "session",
+            Self::Notification => "notification",
+            Self::TodoToken => "todo_token",
+            Self::TodoTrash => "todo_trash",
+            Self::Undo => "undo",
+            Self::TodoAudit => "todo_audit",
+        }
+    }
 
-                Ok(())
-            }
+    /// Fields expected by repo functions with their types, in tuple order
+    pub fn fields(&self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Self::User => &[
+                ("id", "unsigned"),
+                ("name", "string"),
+                ("password_hash", "string"),
+            ],
+            Self::Todo => &[
+                ("id", "unsigned"),
+                ("title", "string"),
+                ("completed", "boolean"),
+                ("owner_id", "unsigned"),
+                ("position", "unsigned"),
+                ("created_at", "unsigned"),
+                ("updated_at", "unsigned"),
+                ("completed_at", "unsigned"),
+                ("due_at", "unsigned"),
+                ("tags", "array"),
+            ],
+            Self::Session => &[
+                ("token", "string"),
+                ("user_id", "unsigned"),
+                ("expires_at", "unsigned"),
+            ],
+            Self::Notification => &[
+                ("id", "unsigned"),
+                ("user_id", "unsigned"),
+                ("todo_id", "unsigned"),
+                ("kind", "string"),
+                ("created_at", "unsigned"),
+            ],
+            Self::TodoToken => &[
+                ("owner_id", "unsigned"),
+                ("token", "string"),
+                ("todo_id", "unsigned"),
+            ],
+            Self::TodoTrash => &[
+                ("id", "unsigned"),
+                ("owner_id", "unsigned"),
+                ("deleted_at", "unsigned"),
+                ("todo", "array"),
+            ],
+            Self::Undo => &[
+                ("token", "string"),
+                ("user_id", "unsigned"),
+                ("expires_at", "unsigned"),
+                ("op", "any"),
+            ],
+            Self::TodoAudit => &[
+                ("id", "unsigned"),
+                ("todo_id", "unsigned"),
+                ("user_id", "unsigned"),
+                ("op", "string"),
+                ("at", "unsigned"),
+                ("before", "any"),
+                ("after", "any"),
+            ],
         }
     }
 
-    pub fn name(&self) -> &'static str {
+    /// Indexes expected by repo functions
+    pub fn indexes(&self) -> &'static [IndexSpec] {
         match self {
-            Self::User => "user",
-            Self::Todo => "todo",
+            Self::User => &[
+                IndexSpec {
+                    name: "user_idx",
+                    parts: &["id"],
+                },
+                IndexSpec {
+                    name: "user_name",
+                    parts: &["name"],
+                },
+            ],
+            Self::Todo => &[
+                IndexSpec {
+                    name: "todo_idx",
+                    parts: &["id"],
+                },
+                IndexSpec {
+                    name: "todo_owner",
+                    parts: &["owner_id"],
+                },
+                IndexSpec {
+                    name: "todo_position",
+                    parts: &["owner_id", "position"],
+                },
+                IndexSpec {
+                    name: "todo_due",
+                    parts: &["due_at"],
+                },
+                IndexSpec {
+                    name: "todo_tag",
+                    parts: &["owner_id", "tags"],
+                },
+            ],
+            Self::Session => &[
+                IndexSpec {
+                    name: "session_idx",
+                    parts: &["token"],
+                },
+                IndexSpec {
+                    name: "session_user",
+                    parts: &["user_id"],
+                },
+                IndexSpec {
+                    name: "session_expires",
+                    parts: &["expires_at"],
+                },
+            ],
+            Self::Notification => &[
+                IndexSpec {
+                    name: "notification_idx",
+                    parts: &["id"],
+                },
+                IndexSpec {
+                    name: "notification_user",
+                    parts: &["user_id"],
+                },
+                IndexSpec {
+                    name: "notification_todo",
+                    parts: &["todo_id", "kind"],
+                },
+            ],
+            Self::TodoToken => &[
+                IndexSpec {
+                    name: "todo_token_idx",
+                    parts: &["owner_id", "token", "todo_id"],
+                },
+                IndexSpec {
+                    name: "todo_token_todo",
+                    parts: &["todo_id"],
+                },
+            ],
+            Self::TodoTrash => &[
+                IndexSpec {
+                    name: "todo_trash_idx",
+                    parts: &["id"],
+                },
+                IndexSpec {
+                    name: "todo_trash_owner",
+                    parts: &["owner_id"],
+                },
+                IndexSpec {
+                    name: "todo_trash_deleted",
+                    parts: &["deleted_at"],
+                },
+            ],
+            Self::Undo => &[
+                IndexSpec {
+                    name: "undo_idx",
+                    parts: &["token"],
+                },
+                IndexSpec {
+                    name: "undo_expires",
+                    parts: &["expires_at"],
+                },
+                IndexSpec {
+                    name: "undo_user",
+                    parts: &["user_id"],
+                },
+            ],
+            Self::TodoAudit => &[
+                IndexSpec {
+                    name: "todo_audit_idx",
+                    parts: &["id"],
+                },
+                IndexSpec {
+                    name: "todo_audit_todo",
+                    parts: &["todo_id", "at"],
+                },
+                IndexSpec {
+                    name: "todo_audit_user",
+                    parts: &["user_id", "at"],
+                },
+            ],
         }
     }
 
@@ -91,7 +269,19 @@ pub enum RepoErr {
     SpaceNotFinded(String),
     FieldNotExsist(String),
     EmptyTodoTitle,
+    EmptyTag,
     EmptyUserName,
+    EmptyPassword,
+    UserExists(String),
+    InvalidCredentials,
+    PasswordHash(String),
+    UserNotFound(u32),
+    TodoNotFound(u32),
+    UndoNotFound,
+    IndexNotFound(String),
+    UnknownSchemaVersion(u32),
+    MigrationFailed(u32, String),
+    SchemaMismatch(Vec<SchemaDiff>),
     TrtlErr(TrtlErr),
 }
 
@@ -101,7 +291,21 @@ impl PartialEq for RepoErr {
             (RepoErr::SpaceNotFinded(msg1), RepoErr::SpaceNotFinded(msg2)) => msg1 == msg2,
             (RepoErr::FieldNotExsist(msg1), RepoErr::FieldNotExsist(msg2)) => msg1 == msg2,
             (RepoErr::EmptyTodoTitle, RepoErr::EmptyTodoTitle) => true,
+            (RepoErr::EmptyTag, RepoErr::EmptyTag) => true,
             (RepoErr::EmptyUserName, RepoErr::EmptyUserName) => true,
+            (RepoErr::EmptyPassword, RepoErr::EmptyPassword) => true,
+            (RepoErr::UserExists(name1), RepoErr::UserExists(name2)) => name1 == name2,
+            (RepoErr::InvalidCredentials, RepoErr::InvalidCredentials) => true,
+            (RepoErr::PasswordHash(msg1), RepoErr::PasswordHash(msg2)) => msg1 == msg2,
+            (RepoErr::UserNotFound(id1), RepoErr::UserNotFound(id2)) => id1 == id2,
+            (RepoErr::TodoNotFound(id1), RepoErr::TodoNotFound(id2)) => id1 == id2,
+            (RepoErr::UndoNotFound, RepoErr::UndoNotFound) => true,
+            (RepoErr::IndexNotFound(msg1), RepoErr::IndexNotFound(msg2)) => msg1 == msg2,
+            (RepoErr::UnknownSchemaVersion(v1), RepoErr::UnknownSchemaVersion(v2)) => v1 == v2,
+            (RepoErr::MigrationFailed(v1, msg1), RepoErr::MigrationFailed(v2, msg2)) => {
+                v1 == v2 && msg1 == msg2
+            }
+            (RepoErr::SchemaMismatch(diffs1), RepoErr::SchemaMismatch(diffs2)) => diffs1 == diffs2,
             (RepoErr::TrtlErr(_), RepoErr::TrtlErr(_)) => true,
             _ => false,
         }
@@ -114,6 +318,16 @@ impl From<TransactionError<TrtlErr>> for RepoErr {
     }
 }
 
+/// Error of repo functions combined in a transaction is returned as is
+impl From<TransactionError<RepoErr>> for RepoErr {
+    fn from(err: TransactionError<RepoErr>) -> RepoErr {
+        match err {
+            TransactionError::RolledBack(err) => err,
+            err => RepoErr::TrtlErr(TrtlErr::other(err.to_string())),
+        }
+    }
+}
+
 impl From<TrtlErr> for RepoErr {
     fn from(err: TrtlErr) -> RepoErr {
         RepoErr::TrtlErr(err)
@@ -126,7 +340,35 @@ impl fmt::Display for RepoErr {
             RepoErr::SpaceNotFinded(space) => write!(f, "space {space} not found"),
             RepoErr::FieldNotExsist(field) => write!(f, "field {field} not exist"),
             RepoErr::EmptyTodoTitle => write!(f, "empty todo title"),
+            RepoErr::EmptyTag => write!(f, "empty tag"),
             RepoErr::EmptyUserName => write!(f, "empty user name"),
+            RepoErr::EmptyPassword => write!(f, "empty password"),
+            RepoErr::UserExists(name) => write!(f, "user {name} already exists"),
+            RepoErr::InvalidCredentials => write!(f, "invalid user name or password"),
+            RepoErr::PasswordHash(msg) => write!(f, "password hash error: {msg}"),
+            RepoErr::UserNotFound(id) => write!(f, "user {id} not found"),
+            RepoErr::TodoNotFound(id) => write!(f, "todo {id} not found"),
+            RepoErr::UndoNotFound => write!(f, "nothing to undo"),
+            RepoErr::IndexNotFound(index) => write!(f, "index {index} not found"),
+            RepoErr::UnknownSchemaVersion(version) => {
+                write!(
+                    f,
+                    "unknown schema version {version}, application is outdated"
+                )
+            }
+            RepoErr::MigrationFailed(version, msg) => {
+                write!(f, "migration {version} failed: {msg}")
+            }
+            RepoErr::SchemaMismatch(diffs) => {
+                write!(f, "schema mismatch: ")?;
+                for (i, diff) in diffs.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, "; ")?;
+                    }
+                    write!(f, "{diff}")?;
+                }
+                Ok(())
+            }
             RepoErr::TrtlErr(..) => write!(f, "tarantool error"),
         }
     }
@@ -138,8 +380,44 @@ impl Error for RepoErr {
             RepoErr::SpaceNotFinded(..) => None,
             RepoErr::FieldNotExsist(..) => None,
             RepoErr::EmptyTodoTitle => None,
+            RepoErr::EmptyTag => None,
             RepoErr::EmptyUserName => None,
+            RepoErr::EmptyPassword => None,
+            RepoErr::UserExists(..) => None,
+            RepoErr::InvalidCredentials => None,
+            RepoErr::PasswordHash(..) => None,
+            RepoErr::UserNotFound(..) => None,
+            RepoErr::TodoNotFound(..) => None,
+            RepoErr::UndoNotFound => None,
+            RepoErr::IndexNotFound(..) => None,
+            RepoErr::UnknownSchemaVersion(..) => None,
+            RepoErr::MigrationFailed(..) => None,
+            RepoErr::SchemaMismatch(..) => None,
             RepoErr::TrtlErr(ref e) => Some(e),
         }
     }
 }
+
+impl From<RepoErr> for AppErr {
+    fn from(err: RepoErr) -> AppErr {
+        match err {
+            RepoErr::SpaceNotFinded(space) => AppErr::SpaceNotFound(space),
+            RepoErr::FieldNotExsist(field) => AppErr::FieldNotExist(field),
+            RepoErr::EmptyTodoTitle => AppErr::EmptyTodoTitle,
+            RepoErr::EmptyTag => AppErr::EmptyTag,
+            RepoErr::EmptyUserName => AppErr::EmptyUserName,
+            RepoErr::EmptyPassword => AppErr::EmptyPassword,
+            RepoErr::UserExists(name) => AppErr::UserExists(name),
+            RepoErr::InvalidCredentials => AppErr::InvalidCredentials,
+            RepoErr::UserNotFound(id) => AppErr::UserNotFound(id),
+            RepoErr::TodoNotFound(id) => AppErr::TodoNotFound(id),
+            RepoErr::UndoNotFound => AppErr::UndoNotFound,
+            RepoErr::TrtlErr(err) => AppErr::Database(err.to_string()),
+            err @ (RepoErr::PasswordHash(..)
+            | RepoErr::IndexNotFound(..)
+            | RepoErr::UnknownSchemaVersion(..)
+            | RepoErr::MigrationFailed(..)
+            | RepoErr::SchemaMismatch(..)) => AppErr::Database(err.to_string()),
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use crate::repo::transaction;
+use crate::repo::RepoErr;
+use crate::repo::RepoSpaces;
+use front_app::Todo;
+use tarantool::error::Error as TrtlErr;
+use tarantool::index::{Index, IteratorType};
+
+/// Kind of notification about todo not completed by its due date
+pub const OVERDUE: &str = "overdue";
+
+/// Record overdue notification for every todo not completed by `now`,
+/// whose due date passed after `since`. Only due dates in `(since, now]`
+/// are scanned, so the caller keeps the time of the last check.
+/// Todo is notified once, until its due date is changed.
+/// Returns `Result` with number of new notifications on success,
+/// otherwise returns an `repo error`.
+pub fn record_overdue(since: u64, now: u64) -> Result<usize, RepoErr> {
+    let space_notification = RepoSpaces::Notification.find()?;
+    let notification_todo = todo_index()?;
+    let due_index = due_index()?;
+
+    let res = transaction(|| -> Result<usize, TrtlErr> {
+        // Todos without due date are indexed last in descending order
+        let mut overdue = Vec::new();
+        for tuple in due_index.select(IteratorType::LE, &(now,))? {
+            let todo = tuple.decode::<Todo>()?;
+            if todo.due_at.map_or(true, |due_at| due_at <= since) {
+                break;
+            }
+            if !todo.completed {
+                overdue.push(todo);
+            }
+        }
+
+        let mut recorded = 0;
+        for todo in overdue {
+            if notification_todo.get(&(todo.id, OVERDUE))?.is_some() {
+                continue;
+            }
+            space_notification.insert(&(None::<u32>, todo.owner_id, todo.id, OVERDUE, now))?;
+            recorded += 1;
+        }
+
+        Ok(recorded)
+    })?;
+
+    Ok(res)
+}
+
+/// Earliest due date after `now`.
+/// Returns `Result` with optional due date on success, otherwise
+/// returns an `repo error`.
+pub fn next_due(now: u64) -> Result<Option<u64>, RepoErr> {
+    let next = due_index()?
+        .select(IteratorType::GT, &(now,))?
+        .next()
+        .map(|t| t.decode::<Todo>())
+        .transpose()?
+        .and_then(|t| t.due_at);
+
+    Ok(next)
+}
+
+/// Index of `notification` space by todo and kind
+pub fn todo_index() -> Result<Index, RepoErr> {
+    RepoSpaces::Notification
+        .find()?
+        .index_cached("notification_todo")
+        .ok_or(RepoErr::IndexNotFound(String::from("notification_todo")))
+}
+
+/// Index of `todo` space by due date
+fn due_index() -> Result<Index, RepoErr> {
+    RepoSpaces::Todo
+        .find()?
+        .index_cached("todo_due")
+        .ok_or(RepoErr::IndexNotFound(String::from("todo_due")))
+}
+
+#[cfg(feature = "test")]
+mod tests {
+    use super::*;
+    use crate::repo::todo::{change_completed, change_due, create_todo};
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn notified(todo_id: u32) -> Option<(u32, u32, u32, String, u64)> {
+        todo_index()
+            .unwrap()
+            .get(&(todo_id, OVERDUE))
+            .unwrap()
+            .map(|t| t.decode().unwrap())
+    }
+
+    #[tarantool_test::test]
+    fn notify_overdue_todo() {
+        let owner_id = 7001;
+        let todo = create_todo(owner_id, "Overdue todo").unwrap();
+        change_due(owner_id, todo.id, Some(NOW - 60)).unwrap();
+
+        assert!(record_overdue(0, NOW).unwrap() >= 1);
+        let (_, user_id, todo_id, kind, created_at) = notified(todo.id).unwrap();
+        assert_eq!((user_id, todo_id), (owner_id, todo.id));
+        assert_eq!((kind.as_str(), created_at), (OVERDUE, NOW));
+    }
+
+    #[tarantool_test::test]
+    fn notify_once() {
+        let owner_id = 7002;
+        let todo = create_todo(owner_id, "Overdue todo").unwrap();
+        change_due(owner_id, todo.id, Some(NOW - 60)).unwrap();
+
+        record_overdue(0, NOW).unwrap();
+        record_overdue(0, NOW + 60).unwrap();
+        assert_eq!(notified(todo.id).map(|n| n.4), Some(NOW));
+    }
+
+    #[tarantool_test::test]
+    fn skip_completed_and_not_due_todos() {
+        let owner_id = 7003;
+        let completed = create_todo(owner_id, "Completed todo").unwrap();
+        change_due(owner_id, completed.id, Some(NOW - 60)).unwrap();
+        change_completed(owner_id, completed.id, true).unwrap();
+        let not_due = create_todo(owner_id, "Not due todo").unwrap();
+        change_due(owner_id, not_due.id, Some(NOW + 60)).unwrap();
+        let without_due = create_todo(owner_id, "Todo without due date").unwrap();
+
+        record_overdue(0, NOW).unwrap();
+        assert_eq!(notified(completed.id), None);
+        assert_eq!(notified(not_due.id), None);
+        assert_eq!(notified(without_due.id), None);
+    }
+
+    #[tarantool_test::test]
+    fn notify_again_after_due_change() {
+        let owner_id = 7004;
+        let todo = create_todo(owner_id, "Postponed todo").unwrap();
+        change_due(owner_id, todo.id, Some(NOW - 60)).unwrap();
+        record_overdue(0, NOW).unwrap();
+
+        change_due(owner_id, todo.id, Some(NOW + 60)).unwrap();
+        assert_eq!(notified(todo.id), None);
+        record_overdue(NOW, NOW + 60).unwrap();
+        assert_eq!(notified(todo.id).map(|n| n.4), Some(NOW + 60));
+    }
+
+    #[tarantool_test::test]
+    fn skip_todos_due_before_last_check() {
+        let owner_id = 7006;
+        let checked = create_todo(owner_id, "Checked todo").unwrap();
+        change_due(owner_id, checked.id, Some(NOW - 60)).unwrap();
+        let due = create_todo(owner_id, "Due todo").unwrap();
+        change_due(owner_id, due.id, Some(NOW + 30)).unwrap();
+
+        record_overdue(NOW, NOW + 60).unwrap();
+        assert_eq!(notified(checked.id), None);
+        assert_eq!(notified(due.id).map(|n| n.4), Some(NOW + 60));
+    }
+
+    #[tarantool_test::test]
+    fn next_due_date() {
+        let owner_id = 7005;
+        let todo = create_todo(owner_id, "Future todo").unwrap();
+        // Far from other todos, which are due around NOW
+        let due_at = NOW + 1_000_000;
+        change_due(owner_id, todo.id, Some(due_at)).unwrap();
+
+        assert_eq!(next_due(due_at - 1).unwrap(), Some(due_at));
+    }
+}
@@ -0,0 +1,306 @@
+use crate::repo::{IndexSpec, RepoErr, RepoSpaces};
+use core::fmt;
+use strum::IntoEnumIterator;
+use tarantool::error::Error as TrtlErr;
+
+/// Fields of a space as `name:type` strings, nil for missing space
+const SPACE_FORMAT: &str = r#"
+local space = box.space[...]
+if space == nil then
+    return nil
+end
+local fields = {}
+for i, field in ipairs(space:format()) do
+    fields[i] = field.name .. ':' .. field.type
+end
+return fields
+"#;
+
+/// Indexes of a space as `name:part,part` strings, parts are field names
+const SPACE_INDEXES: &str = r#"
+local space = box.space[...]
+local format = space:format()
+local indexes = {}
+for name, index in pairs(space.index) do
+    if type(name) == 'string' then
+        local parts = {}
+        for i, part in ipairs(index.parts) do
+            local field = format[part.fieldno]
+            parts[i] = field and field.name or tostring(part.fieldno)
+        end
+        table.insert(indexes, name .. ':' .. table.concat(parts, ','))
+    end
+end
+return indexes
+"#;
+
+/// Difference between existing space and schema expected by repo
+#[derive(Debug, PartialEq, Clone)]
+pub enum SchemaDiff {
+    MissingSpace(String),
+    MissingField {
+        space: String,
+        field: String,
+    },
+    UnexpectedField {
+        space: String,
+        position: usize,
+        expected: String,
+        actual: String,
+    },
+    ExtraField {
+        space: String,
+        field: String,
+    },
+    WrongFieldType {
+        space: String,
+        field: String,
+        expected: String,
+        actual: String,
+    },
+    MissingIndex {
+        space: String,
+        index: String,
+    },
+    WrongIndexParts {
+        space: String,
+        index: String,
+        expected: Vec<String>,
+        actual: Vec<String>,
+    },
+}
+
+impl fmt::Display for SchemaDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SchemaDiff::MissingSpace(space) => write!(f, "space {space} is missing"),
+            SchemaDiff::MissingField { space, field } => {
+                write!(f, "space {space}: field {field} is missing")
+            }
+            SchemaDiff::UnexpectedField {
+                space,
+                position,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "space {space}: field #{position} is {actual}, expected {expected}"
+            ),
+            SchemaDiff::ExtraField { space, field } => {
+                write!(f, "space {space}: unexpected field {field}")
+            }
+            SchemaDiff::WrongFieldType {
+                space,
+                field,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "space {space}: field {field} has type {actual}, expected {expected}"
+            ),
+            SchemaDiff::MissingIndex { space, index } => {
+                write!(f, "space {space}: index {index} is missing")
+            }
+            SchemaDiff::WrongIndexParts {
+                space,
+                index,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "space {space}: index {index} has parts ({}), expected ({})",
+                actual.join(", "),
+                expected.join(", ")
+            ),
+        }
+    }
+}
+
+/// Compare existing application spaces with schema expected by repo.
+/// Returns `Result` with unit on success, otherwise returns an `repo
+/// error` listing all differences.
+pub fn verify() -> Result<(), RepoErr> {
+    let mut diffs = Vec::new();
+    for space in RepoSpaces::iter() {
+        diffs.extend(diff_space(space.name(), space.fields(), space.indexes())?);
+    }
+
+    if diffs.is_empty() {
+        Ok(())
+    } else {
+        Err(RepoErr::SchemaMismatch(diffs))
+    }
+}
+
+/// Differences of space `name` from expected `fields` (name and type, in
+/// tuple order) and `indexes`
+fn diff_space(
+    name: &str,
+    fields: &[(&str, &str)],
+    indexes: &[IndexSpec],
+) -> Result<Vec<SchemaDiff>, RepoErr> {
+    let lua = tarantool::lua_state();
+    let actual_fields: Option<Vec<String>> =
+        lua.eval_with(SPACE_FORMAT, name).map_err(TrtlErr::from)?;
+    let Some(actual_fields) = actual_fields else {
+        return Ok(vec![SchemaDiff::MissingSpace(name.to_string())]);
+    };
+
+    let mut diffs = Vec::new();
+    let space = name.to_string();
+
+    for (position, &(field, field_type)) in fields.iter().enumerate() {
+        let Some(actual) = actual_fields.get(position) else {
+            diffs.push(SchemaDiff::MissingField {
+                space: space.clone(),
+                field: field.to_string(),
+            });
+            continue;
+        };
+        let (actual_name, actual_type) = actual.split_once(':').unwrap_or((actual, ""));
+        if actual_name != field {
+            diffs.push(SchemaDiff::UnexpectedField {
+                space: space.clone(),
+                position: position + 1,
+                expected: field.to_string(),
+                actual: actual_name.to_string(),
+            });
+        } else if actual_type != field_type {
+            diffs.push(SchemaDiff::WrongFieldType {
+                space: space.clone(),
+                field: field.to_string(),
+                expected: field_type.to_string(),
+                actual: actual_type.to_string(),
+            });
+        }
+    }
+    for actual in actual_fields.iter().skip(fields.len()) {
+        let (actual_name, _) = actual.split_once(':').unwrap_or((actual, ""));
+        diffs.push(SchemaDiff::ExtraField {
+            space: space.clone(),
+            field: actual_name.to_string(),
+        });
+    }
+
+    let actual_indexes: Vec<String> = lua.eval_with(SPACE_INDEXES, name).map_err(TrtlErr::from)?;
+
+    for &IndexSpec { name: index, parts } in indexes {
+        let actual = actual_indexes
+            .iter()
+            .filter_map(|i| i.split_once(':'))
+            .find(|(actual_name, _)| *actual_name == index);
+        match actual {
+            None => diffs.push(SchemaDiff::MissingIndex {
+                space: space.clone(),
+                index: index.to_string(),
+            }),
+            Some((_, actual_parts)) => {
+                let actual_parts: Vec<String> = actual_parts.split(',').map(String::from).collect();
+                if actual_parts != parts {
+                    diffs.push(SchemaDiff::WrongIndexParts {
+                        space: space.clone(),
+                        index: index.to_string(),
+                        expected: parts.iter().map(|p| p.to_string()).collect(),
+                        actual: actual_parts,
+                    });
+                }
+            }
+        }
+    }
+
+    Ok(diffs)
+}
+
+#[cfg(feature = "test")]
+mod tests {
+    use super::*;
+    use tarantool::space::{FieldType, Space};
+
+    #[tarantool_test::test]
+    fn verify_current_schema() {
+        assert_eq!(verify(), Ok(()));
+    }
+
+    #[tarantool_test::test]
+    fn diff_missing_space() {
+        let result = diff_space("schema_missing", &[("id", "unsigned")], &[]).unwrap();
+        assert_eq!(
+            result,
+            vec![SchemaDiff::MissingSpace("schema_missing".to_string())]
+        );
+    }
+
+    #[tarantool_test::test]
+    fn diff_changed_space() {
+        let space = Space::builder("schema_changed")
+            .format([
+                ("id", FieldType::Unsigned),
+                ("title", FieldType::Unsigned),
+                ("done", FieldType::Boolean),
+            ])
+            .if_not_exists(true)
+            .create()
+            .unwrap();
+        space
+            .index_builder("schema_changed_idx")
+            .part("title")
+            .if_not_exists(true)
+            .create()
+            .unwrap();
+
+        let result = diff_space(
+            "schema_changed",
+            &[
+                ("id", "unsigned"),
+                ("title", "string"),
+                ("completed", "boolean"),
+                ("owner_id", "unsigned"),
+            ],
+            &[
+                IndexSpec {
+                    name: "schema_changed_idx",
+                    parts: &["id"],
+                },
+                IndexSpec {
+                    name: "schema_changed_owner",
+                    parts: &["owner_id"],
+                },
+            ],
+        )
+        .unwrap();
+        space.drop().unwrap();
+
+        let space = "schema_changed".to_string();
+        assert_eq!(
+            result,
+            vec![
+                SchemaDiff::WrongFieldType {
+                    space: space.clone(),
+                    field: "title".into(),
+                    expected: "string".into(),
+                    actual: "unsigned".into(),
+                },
+                SchemaDiff::UnexpectedField {
+                    space: space.clone(),
+                    position: 3,
+                    expected: "completed".into(),
+                    actual: "done".into(),
+                },
+                SchemaDiff::MissingField {
+                    space: space.clone(),
+                    field: "owner_id".into(),
+                },
+                SchemaDiff::WrongIndexParts {
+                    space: space.clone(),
+                    index: "schema_changed_idx".into(),
+                    expected: vec!["id".into()],
+                    actual: vec!["title".into()],
+                },
+                SchemaDiff::MissingIndex {
+                    space,
+                    index: "schema_changed_owner".into(),
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,157 @@
+use crate::repo::RepoErr;
+use crate::repo::RepoSpaces;
+use front_app::Todo;
+use std::collections::BTreeSet;
+use tarantool::error::Error as TrtlErr;
+use tarantool::index::{Index, IteratorType};
+use tarantool::space::Space;
+
+/// Inverted index of todo titles in `todo_token` space, one tuple per
+/// owner, token and todo. Changed by repo functions in the transaction
+/// changing the todo.
+pub struct SearchIndex {
+    space: Space,
+    by_todo: Index,
+}
+
+impl SearchIndex {
+    pub fn find() -> Result<SearchIndex, RepoErr> {
+        let space = RepoSpaces::TodoToken.find()?;
+        let by_todo = space
+            .index_cached("todo_token_todo")
+            .ok_or(RepoErr::IndexNotFound(String::from("todo_token_todo")))?;
+        Ok(SearchIndex { space, by_todo })
+    }
+
+    /// Index tokens of todo `id` title, replacing previous ones
+    pub fn index(&self, id: u32, owner_id: u32, title: &str) -> Result<(), TrtlErr> {
+        self.remove(id)?;
+        for token in tokens(title) {
+            self.space.replace(&(owner_id, token, id))?;
+        }
+        Ok(())
+    }
+
+    /// Remove tokens of todo `id`
+    pub fn remove(&self, id: u32) -> Result<(), TrtlErr> {
+        let keys: Vec<(u32, String, u32)> = self
+            .by_todo
+            .select(IteratorType::Eq, &(id,))?
+            .map(|t| t.decode::<(u32, String, u32)>())
+            .collect::<Result<_, TrtlErr>>()?;
+        for key in keys {
+            self.space.delete(&key)?;
+        }
+        Ok(())
+    }
+
+    /// Ids of todos of `owner_id` having a token starting with `prefix`
+    fn matching(&self, owner_id: u32, prefix: &str) -> Result<BTreeSet<u32>, TrtlErr> {
+        let mut ids = BTreeSet::new();
+        for tuple in self.space.select(IteratorType::GE, &(owner_id, prefix))? {
+            let (owner, token, id) = tuple.decode::<(u32, String, u32)>()?;
+            if owner != owner_id || !token.starts_with(prefix) {
+                break;
+            }
+            ids.insert(id);
+        }
+        Ok(ids)
+    }
+}
+
+/// Lowercase words of `text` without duplicates, words are split on
+/// anything but letters and digits
+pub fn tokens(text: &str) -> BTreeSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Search todos of `owner_id` by `query`, case-insensitive. Every word
+/// of the query must start a word of todo title, empty query finds
+/// nothing.
+/// Returns `Result` with Todos in position order on success, otherwise
+/// returns an `repo error`.
+pub fn search_todos(owner_id: u32, query: &str) -> Result<Vec<Todo>, RepoErr> {
+    let space_todo = RepoSpaces::Todo.find()?;
+    let search_index = SearchIndex::find()?;
+
+    let mut found: Option<BTreeSet<u32>> = None;
+    for token in tokens(query) {
+        let ids = search_index.matching(owner_id, &token)?;
+        found = Some(match found {
+            Some(found) => found.intersection(&ids).copied().collect(),
+            None => ids,
+        });
+    }
+
+    let mut todos = Vec::new();
+    for id in found.unwrap_or_default() {
+        if let Some(tuple) = space_todo.get(&[id])? {
+            todos.push(tuple.decode::<Todo>()?);
+        }
+    }
+    todos.sort_by_key(|t| (t.position, t.id));
+
+    Ok(todos)
+}
+
+#[cfg(feature = "test")]
+mod tests {
+    use super::*;
+    use crate::repo::todo::tests::titles;
+    use crate::repo::todo::{change_title, create_todo, delete_todo};
+
+    #[tarantool_test::test]
+    fn split_tokens() {
+        let result: Vec<String> = tokens("Buy milk, buy BREAD!").into_iter().collect();
+        assert_eq!(result, ["bread", "buy", "milk"]);
+    }
+
+    #[tarantool_test::test]
+    fn search_case_insensitive() {
+        let owner_id = 8001;
+        create_todo(owner_id, "Buy milk").unwrap();
+        create_todo(owner_id, "Call mom").unwrap();
+
+        let result = search_todos(owner_id, "MILK").unwrap();
+        assert_eq!(titles(&result), ["Buy milk"]);
+    }
+
+    #[tarantool_test::test]
+    fn search_all_tokens_by_prefix() {
+        let owner_id = 8002;
+        create_todo(owner_id, "Buy milk").unwrap();
+        create_todo(owner_id, "Buy bread").unwrap();
+        create_todo(owner_id, "Bake bread").unwrap();
+
+        let result = search_todos(owner_id, "bu").unwrap();
+        assert_eq!(titles(&result), ["Buy milk", "Buy bread"]);
+        let result = search_todos(owner_id, "buy bre").unwrap();
+        assert_eq!(titles(&result), ["Buy bread"]);
+        assert_eq!(search_todos(owner_id, " ").unwrap(), Vec::new());
+    }
+
+    #[tarantool_test::test]
+    fn search_todos_of_owner() {
+        let owner_id = 8003;
+        create_todo(owner_id + 1, "Other owner todo").unwrap();
+        assert_eq!(search_todos(owner_id, "owner").unwrap(), Vec::new());
+    }
+
+    #[tarantool_test::test]
+    fn search_follows_changes() {
+        let owner_id = 8004;
+        let todo = create_todo(owner_id, "Old title").unwrap();
+        change_title(owner_id, todo.id, "New title").unwrap();
+        assert_eq!(search_todos(owner_id, "old").unwrap(), Vec::new());
+        assert_eq!(
+            titles(&search_todos(owner_id, "new").unwrap()),
+            ["New title"]
+        );
+
+        delete_todo(owner_id, todo.id).unwrap();
+        assert_eq!(search_todos(owner_id, "title").unwrap(), Vec::new());
+    }
+}
@@ -0,0 +1,154 @@
+use crate::repo::RepoErr;
+use crate::repo::RepoSpaces;
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use core::time::Duration;
+use front_app::Session;
+use std::env;
+use std::fmt::Write;
+use tarantool::index::IteratorType;
+
+/// Lifetime of a new session. Configured with `SESSION_TTL_SECS`
+/// environment variable, 7 days by default.
+pub fn ttl_from_env() -> Duration {
+    env::var("SESSION_TTL_SECS")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .filter(|secs| *secs > 0)
+        .map(Duration::from_secs)
+        .unwrap_or(Duration::from_secs(7 * 24 * 3600))
+}
+
+/// Insert session of `user_id` with a random token, valid for `ttl` from
+/// `now`.
+/// Returns `Result` with inserted Session on success, otherwise
+/// returns an `repo error`.
+pub fn create_session(user_id: u32, now: u64, ttl: Duration) -> Result<Session, RepoErr> {
+    let session = Session {
+        token: new_token(),
+        user_id,
+        expires_at: now + ttl.as_secs(),
+    };
+    let space_session = RepoSpaces::Session.find()?;
+    space_session.insert(&(&session.token, session.user_id, session.expires_at))?;
+
+    Ok(session)
+}
+
+/// Find session by `token`, expired session is not returned.
+/// Returns `Result` with optional Session on success, otherwise
+/// returns an `repo error`.
+pub fn find_session(token: &str, now: u64) -> Result<Option<Session>, RepoErr> {
+    let space_session = RepoSpaces::Session.find()?;
+    let Some(tuple) = space_session.get(&(token,))? else {
+        return Ok(None);
+    };
+    let (token, user_id, expires_at) = tuple.decode::<(String, u32, u64)>()?;
+    if expires_at <= now {
+        return Ok(None);
+    }
+
+    Ok(Some(Session {
+        token,
+        user_id,
+        expires_at,
+    }))
+}
+
+/// Delete session by `token`, missing session is not an error.
+/// Returns `Result` with unit on success, otherwise returns an
+/// `repo error`.
+pub fn delete_session(token: &str) -> Result<(), RepoErr> {
+    let space_session = RepoSpaces::Session.find()?;
+    space_session.delete(&(token,))?;
+    Ok(())
+}
+
+/// Delete sessions expired by `now`.
+/// Returns `Result` with number of deleted sessions on success,
+/// otherwise returns an `repo error`.
+pub fn purge_expired(now: u64) -> Result<usize, RepoErr> {
+    let space_session = RepoSpaces::Session.find()?;
+    let expires = space_session
+        .index_cached("session_expires")
+        .ok_or(RepoErr::IndexNotFound(String::from("session_expires")))?;
+
+    let expired: Vec<String> = expires
+        .select(IteratorType::LE, &(now,))?
+        .filter_map(|t| t.field::<String>(0).ok().flatten())
+        .collect();
+    for token in &expired {
+        space_session.delete(&(token,))?;
+    }
+
+    Ok(expired.len())
+}
+
+/// 32 random bytes as hex string
+pub fn new_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    bytes
+        .iter()
+        .fold(String::with_capacity(64), |mut token, b| {
+            let _ = write!(token, "{b:02x}");
+            token
+        })
+}
+
+#[cfg(feature = "test")]
+mod tests {
+    use super::*;
+    use crate::repo::clock::now;
+
+    const USER_ID: u32 = 6001;
+    const TTL: Duration = Duration::from_secs(60);
+
+    #[tarantool_test::test]
+    fn find_created_session() {
+        let now = now();
+        let session = create_session(USER_ID, now, TTL).unwrap();
+        assert_eq!(session.token.len(), 64);
+        assert_eq!(session.expires_at, now + 60);
+
+        let result = find_session(&session.token, now).unwrap();
+        assert_eq!(result, Some(session));
+    }
+
+    #[tarantool_test::test]
+    fn unique_tokens() {
+        let now = now();
+        let first = create_session(USER_ID, now, TTL).unwrap();
+        let second = create_session(USER_ID, now, TTL).unwrap();
+        assert_ne!(first.token, second.token);
+    }
+
+    #[tarantool_test::test]
+    fn skip_expired_session() {
+        let now = now();
+        let session = create_session(USER_ID, now, TTL).unwrap();
+        let result = find_session(&session.token, now + 60).unwrap();
+        assert_eq!(result, None);
+    }
+
+    #[tarantool_test::test]
+    fn delete_created_session() {
+        let now = now();
+        let session = create_session(USER_ID, now, TTL).unwrap();
+        delete_session(&session.token).unwrap();
+        assert_eq!(find_session(&session.token, now).unwrap(), None);
+        assert_eq!(delete_session(&session.token), Ok(()));
+    }
+
+    #[tarantool_test::test]
+    fn purge_expired_sessions() {
+        let now = now();
+        let expired = create_session(USER_ID, now - 120, TTL).unwrap();
+        let valid = create_session(USER_ID, now, TTL).unwrap();
+
+        assert!(purge_expired(now).unwrap() >= 1);
+
+        let space_session = RepoSpaces::Session.find().unwrap();
+        assert!(space_session.get(&(&expired.token,)).unwrap().is_none());
+        assert!(space_session.get(&(&valid.token,)).unwrap().is_some());
+    }
+}
@@ -1,85 +1,257 @@
+use crate::repo::audit::{self, Audit};
+use crate::repo::clock;
+use crate::repo::notification::{self, OVERDUE};
+use crate::repo::search::SearchIndex;
+use crate::repo::transaction;
+use crate::repo::trash::Trash;
 use crate::repo::RepoErr;
 use crate::repo::RepoSpaces;
-use front_app::Todo;
+use front_app::{Todo, TodoCounts, TodoPage};
 use tarantool::error::Error as TrtlErr;
-use tarantool::index::IteratorType;
-use tarantool::transaction::transaction;
+use tarantool::index::{Index, IteratorType};
+use tarantool::space::{Space, UpdateOps};
 
-/// Insert `todo` for the provided title. Empty title not allowed.
+/// Distance between positions of neighbour todos. Moved todo takes the
+/// middle of the gap, todos are renumbered when the gap is exhausted.
+pub const POSITION_GAP: u64 = 1 << 16;
+
+/// Insert `todo` of `owner_id` for the provided title, after the last
+/// todo of the owner. Empty title not allowed. Creation time is taken
+/// from repo clock, title is added to search index.
 /// Returns `Result` with inserted Todo on success, otherwise
 /// returns an `repo error`.
-pub fn create_todo(title: &str) -> Result<Todo, RepoErr> {
+pub fn create_todo(owner_id: u32, title: &str) -> Result<Todo, RepoErr> {
     use RepoErr::EmptyTodoTitle;
 
     if title.is_empty() {
         return Err(EmptyTodoTitle);
     }
     let space_todo = RepoSpaces::Todo.find()?;
-    let todo = space_todo
-        .insert(&(None::<u32>, title, false))?
-        .decode::<Todo>()?;
+    let last = position_index(&space_todo)?
+        .select(IteratorType::Req, &(owner_id,))?
+        .next()
+        .map(|t| t.decode::<Todo>())
+        .transpose()?;
+    let position = last.map_or(0, |t| t.position) + POSITION_GAP;
+    let now = clock::now();
+    let search_index = SearchIndex::find()?;
+    let audit = Audit::find()?;
+
+    let todo = transaction(|| -> Result<Todo, TrtlErr> {
+        let todo = space_todo
+            .insert(&(
+                None::<u32>,
+                title,
+                false,
+                owner_id,
+                position,
+                now,
+                now,
+                None::<u64>,
+                None::<u64>,
+                Vec::<String>::new(),
+            ))?
+            .decode::<Todo>()?;
+        search_index.index(todo.id, owner_id, &todo.title)?;
+        audit.record(audit::CREATE, owner_id, None, Some(&todo), now)?;
+        Ok(todo)
+    })?;
 
     Ok(todo)
 }
 
-/// Delete `todo` for the provided id.
+/// Delete `todo` of `owner_id` for the provided id, todo is moved to
+/// trash.
 /// Returns `Result` with deleted Todo on success, otherwise
 /// returns an `repo error`.
-pub fn delete_todo(id: u32) -> Result<Todo, RepoErr> {
+pub fn delete_todo(owner_id: u32, id: u32) -> Result<Todo, RepoErr> {
     let space_todo = RepoSpaces::Todo.find()?;
-
-    let todo = space_todo.delete(&[id])?.unwrap().decode::<Todo>()?;
+    check_owner(&space_todo, owner_id, id)?;
+    let search_index = SearchIndex::find()?;
+    let trash = Trash::find()?;
+    let audit = Audit::find()?;
+    let now = clock::now();
+
+    let todo = transaction(|| -> Result<Option<Todo>, TrtlErr> {
+        search_index.remove(id)?;
+        let todo = space_todo
+            .delete(&[id])?
+            .map(|t| t.decode::<Todo>())
+            .transpose()?;
+        if let Some(todo) = &todo {
+            trash.put(todo, now)?;
+            audit.record(audit::DELETE, owner_id, Some(todo), None, now)?;
+        }
+        Ok(todo)
+    })?
+    .ok_or(RepoErr::TodoNotFound(id))?;
 
     Ok(todo)
 }
 
-/// List all `todo`s.
+/// List all `todo`s of `owner_id` in position order.
 /// Returns `Result` with Todos on success, otherwise
 /// returns an `repo error`.
-pub fn list_todos() -> Result<Vec<Todo>, RepoErr> {
+pub fn list_todos(owner_id: u32) -> Result<Vec<Todo>, RepoErr> {
     let space_todo = RepoSpaces::Todo.find()?;
 
-    let todos = space_todo
-        .select(IteratorType::All, &())?
-        .map(|t| t.decode::<Todo>().unwrap())
-        .collect();
+    let todos = position_index(&space_todo)?
+        .select(IteratorType::Eq, &(owner_id,))?
+        .map(|t| t.decode::<Todo>())
+        .collect::<Result<Vec<Todo>, TrtlErr>>()?;
 
     Ok(todos)
 }
 
-/// Change `completed` status of selected todo.
-/// Returns `Result` with Todo on success, otherwise
+/// List at most `limit` todos of `owner_id` in position order, starting
+/// after todo `after_id`, from the first todo when `None`. Todos of the
+/// same position are ordered by id. Only todos of `completed` status are
+/// listed when it is set.
+/// Returns `Result` with TodoPage on success, otherwise
+/// returns an `repo error`.
+pub fn list_todos_page(
+    owner_id: u32,
+    after_id: Option<u32>,
+    limit: usize,
+    completed: Option<bool>,
+) -> Result<TodoPage, RepoErr> {
+    let space_todo = RepoSpaces::Todo.find()?;
+    let position_index = position_index(&space_todo)?;
+    let mut cursor = None;
+    let tuples = match after_id {
+        Some(after_id) => {
+            let after = owned_todo(&space_todo, owner_id, after_id)?;
+            cursor = Some((after.position, after.id));
+            position_index.select(IteratorType::GE, &(owner_id, after.position))?
+        }
+        None => position_index.select(IteratorType::Eq, &(owner_id,))?,
+    };
+
+    // One extra todo tells that the next page exists
+    let mut todos = Vec::with_capacity(limit + 1);
+    for tuple in tuples {
+        let todo = tuple.decode::<Todo>()?;
+        if todo.owner_id != owner_id || todos.len() > limit {
+            break;
+        }
+        // Non-unique position index orders todos of a position by id
+        if cursor.is_some_and(|cursor| (todo.position, todo.id) <= cursor) {
+            continue;
+        }
+        if completed.is_some_and(|completed| todo.completed != completed) {
+            continue;
+        }
+        todos.push(todo);
+    }
+    let next = if todos.len() > limit {
+        todos.truncate(limit);
+        todos.last().map(|t| t.id)
+    } else {
+        None
+    };
+
+    Ok(TodoPage { todos, next })
+}
+
+/// Count active and completed todos of `owner_id`.
+/// Returns `Result` with TodoCounts on success, otherwise
 /// returns an `repo error`.
-pub fn change_completed(id: u32, completed: bool) -> Result<Todo, RepoErr> {
+pub fn count_todos(owner_id: u32) -> Result<TodoCounts, RepoErr> {
     let space_todo = RepoSpaces::Todo.find()?;
 
-    let todo = space_todo
-        .update(&[id], [("=", 2, completed)])?
-        .unwrap()
-        .decode::<Todo>()?;
+    let mut counts = TodoCounts::default();
+    for tuple in position_index(&space_todo)?.select(IteratorType::Eq, &(owner_id,))? {
+        match tuple.field::<bool>(2)? {
+            Some(true) => counts.completed += 1,
+            _ => counts.active += 1,
+        }
+    }
 
-    Ok(todo)
+    Ok(counts)
 }
 
-/// Change `completed` status of all todos.
+/// List `todo`s of `owner_id` tagged with `tag` in position order.
 /// Returns `Result` with Todos on success, otherwise
 /// returns an `repo error`.
-pub fn change_all_completed(completed: bool) -> Result<Vec<Todo>, RepoErr> {
+pub fn list_todos_by_tag(owner_id: u32, tag: &str) -> Result<Vec<Todo>, RepoErr> {
     let space_todo = RepoSpaces::Todo.find()?;
+    let tag_index = space_todo
+        .index_cached("todo_tag")
+        .ok_or(RepoErr::IndexNotFound(String::from("todo_tag")))?;
+
+    let mut todos = tag_index
+        .select(IteratorType::Eq, &(owner_id, tag))?
+        .map(|t| t.decode::<Todo>())
+        .collect::<Result<Vec<Todo>, TrtlErr>>()?;
+    todos.sort_by_key(|t| (t.position, t.id));
+
+    Ok(todos)
+}
+
+/// Change `completed` status of selected todo of `owner_id`, completion
+/// time is set or cleared.
+/// Returns `Result` with Todo on success, otherwise
+/// returns an `repo error`.
+pub fn change_completed(owner_id: u32, id: u32, completed: bool) -> Result<Todo, RepoErr> {
+    let space_todo = RepoSpaces::Todo.find()?;
+    let before = owned_todo(&space_todo, owner_id, id)?;
+    let audit = Audit::find()?;
+    let now = clock::now();
+
+    let todo = transaction(|| -> Result<Option<Todo>, TrtlErr> {
+        let todo = space_todo
+            .update(&[id], completed_ops(completed, now)?.as_slice())?
+            .map(|t| t.decode::<Todo>())
+            .transpose()?;
+        if let Some(todo) = &todo {
+            audit.record(
+                audit::CHANGE_COMPLETED,
+                owner_id,
+                Some(&before),
+                Some(todo),
+                now,
+            )?;
+        }
+        Ok(todo)
+    })?
+    .ok_or(RepoErr::TodoNotFound(id))?;
+
+    Ok(todo)
+}
+
+/// Change `completed` status of all todos of `owner_id`. Todos already
+/// in this status are not touched.
+/// Returns `Result` with changed Todos on success, otherwise
+/// returns an `repo error`.
+pub fn change_all_completed(owner_id: u32, completed: bool) -> Result<Vec<Todo>, RepoErr> {
+    let space_todo = RepoSpaces::Todo.find()?;
+    let position_index = position_index(&space_todo)?;
+    let audit = Audit::find()?;
+    let now = clock::now();
 
     let res = transaction(|| -> Result<Vec<Todo>, TrtlErr> {
-        let todos: Vec<Todo> = space_todo
-            .select(IteratorType::All, &())?
+        let todos: Vec<Todo> = position_index
+            .select(IteratorType::Eq, &(owner_id,))?
             .map(|t| t.decode::<Todo>().unwrap())
             .collect();
 
-        let mut res = Vec::<Todo>::with_capacity(todos.len());
+        let mut res = Vec::<Todo>::new();
         for todo in todos {
+            if todo.completed == completed {
+                continue;
+            }
             let new_todo = space_todo
-                .update(&[todo.id], [("=", 2, completed)])?
+                .update(&[todo.id], completed_ops(completed, now)?.as_slice())?
                 .unwrap()
                 .decode::<Todo>()
                 .unwrap();
+            audit.record(
+                audit::CHANGE_COMPLETED,
+                owner_id,
+                Some(&todo),
+                Some(&new_todo),
+                now,
+            )?;
             res.push(new_todo);
         }
 
@@ -89,135 +261,695 @@ pub fn change_all_completed(completed: bool) -> Result<Vec<Todo>, RepoErr> {
     Ok(res)
 }
 
-/// Delete `completed` todos.
-/// Returns `Result` with non completed Todos on success, otherwise
+/// Delete `completed` todos of `owner_id`, todos are moved to trash.
+/// Returns `Result` with deleted Todos on success, otherwise
 /// returns an `repo error`.
-pub fn delete_completed() -> Result<Vec<Todo>, RepoErr> {
+pub fn delete_completed(owner_id: u32) -> Result<Vec<Todo>, RepoErr> {
     let space_todo = RepoSpaces::Todo.find()?;
+    let position_index = position_index(&space_todo)?;
+    let search_index = SearchIndex::find()?;
+    let trash = Trash::find()?;
+    let audit = Audit::find()?;
+    let now = clock::now();
 
     let res = transaction(|| -> Result<Vec<Todo>, TrtlErr> {
-        let todos: Vec<Todo> = space_todo
-            .select(IteratorType::All, &())?
+        let completed: Vec<Todo> = position_index
+            .select(IteratorType::Eq, &(owner_id,))?
             .map(|t| t.decode::<Todo>().unwrap())
+            .filter(|t| t.completed)
             .collect();
 
-        for todo in todos.iter().filter(|t| t.completed) {
+        for todo in &completed {
+            search_index.remove(todo.id)?;
             space_todo.delete(&[todo.id])?;
+            trash.put(todo, now)?;
+            audit.record(audit::DELETE, owner_id, Some(todo), None, now)?;
         }
 
-        let todos: Vec<Todo> = todos.iter().filter(|t| !t.completed).cloned().collect();
-
-        Ok(todos)
+        Ok(completed)
     })?;
 
     Ok(res)
 }
 
-/// Delete `completed` todos.
-/// Returns `Result` with non completed Todos on success, otherwise
+/// Change title of selected todo of `owner_id`, update time is set and
+/// search index is updated.
+/// Returns `Result` with Todo on success, otherwise
+/// returns an `repo error`.
+pub fn change_title(owner_id: u32, id: u32, title: &str) -> Result<Todo, RepoErr> {
+    let space_todo = RepoSpaces::Todo.find()?;
+    let before = owned_todo(&space_todo, owner_id, id)?;
+
+    let search_index = SearchIndex::find()?;
+    let audit = Audit::find()?;
+    let now = clock::now();
+
+    let mut ops = UpdateOps::new();
+    ops.assign(1, title)?;
+    ops.assign(6, now)?;
+    let todo = transaction(|| -> Result<Option<Todo>, TrtlErr> {
+        let todo = space_todo
+            .update(&[id], ops.as_slice())?
+            .map(|t| t.decode::<Todo>())
+            .transpose()?;
+        if let Some(todo) = &todo {
+            search_index.index(todo.id, owner_id, &todo.title)?;
+            audit.record(
+                audit::CHANGE_TITLE,
+                owner_id,
+                Some(&before),
+                Some(todo),
+                now,
+            )?;
+        }
+        Ok(todo)
+    })?
+    .ok_or(RepoErr::TodoNotFound(id))?;
+
+    Ok(todo)
+}
+
+/// Change due date of selected todo of `owner_id`, `None` removes it.
+/// Overdue notification of the todo is cleared, so it is notified again
+/// when the new due date passes.
+/// Returns `Result` with Todo on success, otherwise
+/// returns an `repo error`.
+pub fn change_due(owner_id: u32, id: u32, due_at: Option<u64>) -> Result<Todo, RepoErr> {
+    let space_todo = RepoSpaces::Todo.find()?;
+    let before = owned_todo(&space_todo, owner_id, id)?;
+
+    let notification_todo = notification::todo_index()?;
+    let audit = Audit::find()?;
+    let now = clock::now();
+
+    let mut ops = UpdateOps::new();
+    ops.assign(6, now)?;
+    ops.assign(8, due_at)?;
+    let todo = transaction(|| -> Result<Option<Todo>, TrtlErr> {
+        notification_todo.delete(&(id, OVERDUE))?;
+        let todo = space_todo
+            .update(&[id], ops.as_slice())?
+            .map(|t| t.decode::<Todo>())
+            .transpose()?;
+        if let Some(todo) = &todo {
+            audit.record(audit::CHANGE_DUE, owner_id, Some(&before), Some(todo), now)?;
+        }
+        Ok(todo)
+    })?
+    .ok_or(RepoErr::TodoNotFound(id))?;
+
+    Ok(todo)
+}
+
+/// Add trimmed `tag` to selected todo of `owner_id`. Empty tag not
+/// allowed, tag already present is not added again.
+/// Returns `Result` with Todo on success, otherwise
+/// returns an `repo error`.
+pub fn add_tag(owner_id: u32, id: u32, tag: &str) -> Result<Todo, RepoErr> {
+    let tag = tag.trim();
+    if tag.is_empty() {
+        return Err(RepoErr::EmptyTag);
+    }
+    let space_todo = RepoSpaces::Todo.find()?;
+    let todo = owned_todo(&space_todo, owner_id, id)?;
+    if todo.tags.iter().any(|t| t == tag) {
+        return Ok(todo);
+    }
+
+    let mut tags = todo.tags.clone();
+    tags.push(tag.to_string());
+    change_tags(&space_todo, &todo, &tags)
+}
+
+/// Remove `tag` from selected todo of `owner_id`, missing tag is not an
+/// error.
+/// Returns `Result` with Todo on success, otherwise
 /// returns an `repo error`.
-pub fn change_title(id: u32, title: &str) -> Result<Todo, RepoErr> {
+pub fn remove_tag(owner_id: u32, id: u32, tag: &str) -> Result<Todo, RepoErr> {
     let space_todo = RepoSpaces::Todo.find()?;
+    let todo = owned_todo(&space_todo, owner_id, id)?;
+    if !todo.tags.iter().any(|t| t == tag) {
+        return Ok(todo);
+    }
 
-    let todo = space_todo
-        .update(&[id], [("=", 1, title)])?
-        .unwrap()
-        .decode::<Todo>()
-        .unwrap();
+    let tags: Vec<String> = todo.tags.iter().filter(|t| *t != tag).cloned().collect();
+    change_tags(&space_todo, &todo, &tags)
+}
+
+/// Move todo of `owner_id` before todo `before_id`, to the end of the
+/// list when `None`. Todo takes the middle of the gap between its new
+/// neighbours, todos of the owner are renumbered when there is no gap.
+/// Update time of every todo changing position is set.
+/// Returns `Result` with Todos changed position, in new order, on
+/// success, otherwise returns an `repo error`.
+pub fn move_todo(owner_id: u32, id: u32, before_id: Option<u32>) -> Result<Vec<Todo>, RepoErr> {
+    let space_todo = RepoSpaces::Todo.find()?;
+    let todo = owned_todo(&space_todo, owner_id, id)?;
+    if let Some(before_id) = before_id {
+        check_owner(&space_todo, owner_id, before_id)?;
+    }
+    if before_id == Some(id) {
+        return Ok(Vec::new());
+    }
+
+    let others: Vec<Todo> = list_todos(owner_id)?
+        .into_iter()
+        .filter(|t| t.id != id)
+        .collect();
+    let slot = before_id
+        .and_then(|before_id| others.iter().position(|t| t.id == before_id))
+        .unwrap_or(others.len());
+    let prev = slot.checked_sub(1).map_or(0, |i| others[i].position);
+    let next = others
+        .get(slot)
+        .map_or(prev + 2 * POSITION_GAP, |t| t.position);
+    let audit = Audit::find()?;
+    let now = clock::now();
+
+    let moved = transaction(|| -> Result<Vec<Todo>, TrtlErr> {
+        let mut moved = Vec::new();
+        let mut set_position = |before: &Todo, position: u64| -> Result<(), TrtlErr> {
+            if before.position == position {
+                return Ok(());
+            }
+            let ops = [("=", 4, position), ("=", 6, now)];
+            if let Some(after) = space_todo.update(&[before.id], ops)? {
+                let after = after.decode::<Todo>()?;
+                audit.record(audit::MOVE, owner_id, Some(before), Some(&after), now)?;
+                moved.push(after);
+            }
+            Ok(())
+        };
+
+        if next - prev >= 2 {
+            set_position(&todo, prev + (next - prev) / 2)?;
+        } else {
+            let order = others[..slot]
+                .iter()
+                .chain([&todo])
+                .chain(others[slot..].iter());
+            for (i, before) in order.enumerate() {
+                set_position(before, (i as u64 + 1) * POSITION_GAP)?;
+            }
+        }
+        Ok(moved)
+    })?;
+
+    Ok(moved)
+}
+
+/// Replace tags of todo `before`, update time is set
+fn change_tags(space_todo: &Space, before: &Todo, tags: &[String]) -> Result<Todo, RepoErr> {
+    let audit = Audit::find()?;
+    let now = clock::now();
+
+    let mut ops = UpdateOps::new();
+    ops.assign(6, now)?;
+    ops.assign(9, tags)?;
+    let todo = transaction(|| -> Result<Option<Todo>, TrtlErr> {
+        let todo = space_todo
+            .update(&[before.id], ops.as_slice())?
+            .map(|t| t.decode::<Todo>())
+            .transpose()?;
+        if let Some(todo) = &todo {
+            audit.record(
+                audit::CHANGE_TAGS,
+                before.owner_id,
+                Some(before),
+                Some(todo),
+                now,
+            )?;
+        }
+        Ok(todo)
+    })?
+    .ok_or(RepoErr::TodoNotFound(before.id))?;
 
     Ok(todo)
 }
 
+/// Update of `completed` status with its time, update time is `now`
+fn completed_ops(completed: bool, now: u64) -> Result<UpdateOps, TrtlErr> {
+    let mut ops = UpdateOps::new();
+    ops.assign(2, completed)?;
+    ops.assign(6, now)?;
+    ops.assign(7, completed.then_some(now))?;
+    Ok(ops)
+}
+
+/// Index of `todo` space by owner and position
+fn position_index(space_todo: &Space) -> Result<Index, RepoErr> {
+    space_todo
+        .index_cached("todo_position")
+        .ok_or(RepoErr::IndexNotFound(String::from("todo_position")))
+}
+
+/// Check that todo exists and belongs to `owner_id`. Todos of other
+/// owners are reported as not found.
+fn check_owner(space_todo: &Space, owner_id: u32, id: u32) -> Result<(), RepoErr> {
+    owned_todo(space_todo, owner_id, id).map(|_| ())
+}
+
+/// Get todo `id` belonging to `owner_id`, todos of other owners are
+/// reported as not found
+fn owned_todo(space_todo: &Space, owner_id: u32, id: u32) -> Result<Todo, RepoErr> {
+    space_todo
+        .get(&[id])?
+        .map(|t| t.decode::<Todo>())
+        .transpose()?
+        .filter(|t| t.owner_id == owner_id)
+        .ok_or(RepoErr::TodoNotFound(id))
+}
+
 #[cfg(feature = "test")]
-mod tests {
+pub(crate) mod tests {
     use super::*;
 
+    const NOW: u64 = 1_700_000_000;
+
+    /// Titles of `todos`, shared by tests of other repo modules
+    pub(crate) fn titles(todos: &[Todo]) -> Vec<&str> {
+        todos.iter().map(|t| t.title.as_str()).collect()
+    }
+
+    // Every test uses its own owners, so tests don't see each other todos
+
     #[tarantool_test::test]
     fn all_todos() {
+        let owner_id = 1001;
         let expected: Vec<Todo> = (1..4)
             .map(|i| format!("New Todo {i:?}"))
-            .map(|t| create_todo(&t).unwrap())
+            .map(|t| create_todo(owner_id, &t).unwrap())
             .collect();
-        let result = list_todos().unwrap();
+        let result = list_todos(owner_id).unwrap();
         assert_eq!(result, expected);
     }
 
     #[tarantool_test::test]
     fn insert_todo() {
+        let owner_id = 1002;
         let title = "New Todo 3";
-        let result = create_todo(title).unwrap();
+        let _clock = clock::freeze(NOW);
+        let result = create_todo(owner_id, title).unwrap();
         let expected = Todo {
             id: result.id,
             title: title.to_string(),
             completed: false,
+            owner_id,
+            position: POSITION_GAP,
+            created_at: NOW,
+            updated_at: NOW,
+            completed_at: None,
+            due_at: None,
+            tags: Vec::new(),
         };
         assert_eq!(result, expected);
     }
 
     #[tarantool_test::test]
     fn insert_same_data() {
+        let owner_id = 1003;
         let title = "New Todo 4";
-        create_todo(title).unwrap();
-        create_todo(title).unwrap();
+        create_todo(owner_id, title).unwrap();
+        create_todo(owner_id, title).unwrap();
     }
 
     #[tarantool_test::test]
     fn insert_empty_title() {
+        let owner_id = 1004;
         let title = "";
-        let result = create_todo(title);
+        let result = create_todo(owner_id, title);
         assert_eq!(result, Err(RepoErr::EmptyTodoTitle));
     }
 
     #[tarantool_test::test]
     fn destroy_todo() {
+        let owner_id = 1005;
         let title = "New Todo 5";
-        let result = create_todo(title).unwrap();
+        let result = create_todo(owner_id, title).unwrap();
         let expected = Todo {
             id: result.id,
             title: title.to_string(),
             completed: false,
+            owner_id,
+            position: result.position,
+            created_at: result.created_at,
+            updated_at: result.updated_at,
+            completed_at: None,
+            due_at: None,
+            tags: Vec::new(),
         };
-        let result = delete_todo(result.id).unwrap();
+        let result = delete_todo(owner_id, result.id).unwrap();
         assert_eq!(result, expected);
     }
 
+    #[tarantool_test::test]
+    fn destroy_missing_todo() {
+        let owner_id = 1006;
+        let result = delete_todo(owner_id, u32::MAX);
+        assert_eq!(result, Err(RepoErr::TodoNotFound(u32::MAX)));
+    }
+
+    #[tarantool_test::test]
+    fn destroy_todo_of_other_owner() {
+        let owner_id = 1007;
+        let other_id = 1008;
+        let todo = create_todo(owner_id, "Owned todo").unwrap();
+        let result = delete_todo(other_id, todo.id);
+        assert_eq!(result, Err(RepoErr::TodoNotFound(todo.id)));
+        assert_eq!(list_todos(owner_id).unwrap(), vec![todo]);
+    }
+
     #[tarantool_test::test]
     fn change_completed_status() {
-        let todo = create_todo("New Todo 6").unwrap();
-        let result = change_completed(todo.id, true).unwrap();
+        let owner_id = 1009;
+        let todo = create_todo(owner_id, "New Todo 6").unwrap();
+        let result = change_completed(owner_id, todo.id, true).unwrap();
         assert_eq!(result.completed, true);
     }
 
+    #[tarantool_test::test]
+    fn change_missing_todo_title() {
+        let owner_id = 1010;
+        let result = change_title(owner_id, u32::MAX, "New Todo 7");
+        assert_eq!(result, Err(RepoErr::TodoNotFound(u32::MAX)));
+    }
+
     #[tarantool_test::test]
     fn activate_all_todo() {
-        let result = change_all_completed(false).unwrap();
-        assert!(result.iter().all(|t| t.completed));
+        let owner_id = 1011;
+        let todo = create_todo(owner_id, "New Todo 8").unwrap();
+        change_completed(owner_id, todo.id, true).unwrap();
+        let result = change_all_completed(owner_id, false).unwrap();
+        assert!(result.iter().all(|t| !t.completed));
     }
 
     #[tarantool_test::test]
     fn complete_all_todo() {
-        let result = change_all_completed(true).unwrap();
+        let owner_id = 1012;
+        create_todo(owner_id, "New Todo 9").unwrap();
+        let result = change_all_completed(owner_id, true).unwrap();
         assert!(result.iter().all(|t| t.completed));
     }
 
+    #[tarantool_test::test]
+    fn complete_all_todo_of_owner() {
+        let owner_id = 1013;
+        let other_id = 1014;
+        let other_todo = create_todo(other_id, "Other todo").unwrap();
+        change_all_completed(owner_id, true).unwrap();
+        assert_eq!(list_todos(other_id).unwrap(), vec![other_todo]);
+    }
+
     #[tarantool_test::test]
     fn delete_completed_todos() {
-        let result = delete_completed().unwrap();
-        assert_eq!(result, Vec::new());
+        let owner_id = 1015;
+        let todo = create_todo(owner_id, "New Todo 10").unwrap();
+        change_completed(owner_id, todo.id, true).unwrap();
+        let result = delete_completed(owner_id).unwrap();
+        assert_eq!(result.iter().map(|t| t.id).collect::<Vec<u32>>(), [todo.id]);
+        assert_eq!(list_todos(owner_id).unwrap(), Vec::new());
     }
 
     #[tarantool_test::test]
     fn change_todo_title() {
-        let todo = create_todo("New Todo 1").unwrap();
+        let owner_id = 1016;
+        let clock = clock::freeze(NOW);
+        let todo = create_todo(owner_id, "New Todo 1").unwrap();
         let new_title = "New Todo 2";
-        let result = change_title(todo.id, new_title).unwrap();
+        clock.advance(60);
+        let result = change_title(owner_id, todo.id, new_title).unwrap();
         assert_eq!(
             result,
             Todo {
                 id: todo.id,
                 title: new_title.to_string(),
-                completed: false
+                completed: false,
+                owner_id,
+                position: todo.position,
+                created_at: NOW,
+                updated_at: NOW + 60,
+                completed_at: None,
+                due_at: None,
+                tags: Vec::new(),
             }
         );
     }
+
+    #[tarantool_test::test]
+    fn completion_time() {
+        let owner_id = 1021;
+        let clock = clock::freeze(NOW);
+        let todo = create_todo(owner_id, "Timed todo").unwrap();
+
+        clock.advance(60);
+        let result = change_completed(owner_id, todo.id, true).unwrap();
+        assert_eq!(result.completed_at, Some(NOW + 60));
+        assert_eq!(result.updated_at, NOW + 60);
+        assert_eq!(result.created_at, NOW);
+
+        clock.advance(60);
+        let result = change_completed(owner_id, todo.id, false).unwrap();
+        assert_eq!(result.completed_at, None);
+        assert_eq!(result.updated_at, NOW + 120);
+    }
+
+    #[tarantool_test::test]
+    fn complete_all_keeps_completion_time() {
+        let owner_id = 1022;
+        let clock = clock::freeze(NOW);
+        let done = create_todo(owner_id, "Done todo").unwrap();
+        create_todo(owner_id, "Open todo").unwrap();
+        change_completed(owner_id, done.id, true).unwrap();
+
+        clock.advance(60);
+        let result = change_all_completed(owner_id, true).unwrap();
+        assert_eq!(titles(&result), ["Open todo"]);
+        let todos = list_todos(owner_id).unwrap();
+        let times: Vec<Option<u64>> = todos.iter().map(|t| t.completed_at).collect();
+        assert_eq!(times, [Some(NOW), Some(NOW + 60)]);
+    }
+
+    #[tarantool_test::test]
+    fn change_due_date() {
+        let owner_id = 1023;
+        let clock = clock::freeze(NOW);
+        let todo = create_todo(owner_id, "Due todo").unwrap();
+        assert_eq!(todo.due_at, None);
+
+        clock.advance(60);
+        let result = change_due(owner_id, todo.id, Some(NOW + 3600)).unwrap();
+        assert_eq!(result.due_at, Some(NOW + 3600));
+        assert_eq!(result.updated_at, NOW + 60);
+
+        let result = change_due(owner_id, todo.id, None).unwrap();
+        assert_eq!(result.due_at, None);
+    }
+
+    #[tarantool_test::test]
+    fn change_due_of_other_owner() {
+        let owner_id = 1024;
+        let todo = create_todo(owner_id, "Other owner todo").unwrap();
+        let result = change_due(owner_id + 1, todo.id, Some(NOW));
+        assert_eq!(result, Err(RepoErr::TodoNotFound(todo.id)));
+    }
+
+    #[tarantool_test::test]
+    fn add_and_remove_tags() {
+        let owner_id = 1025;
+        let todo = create_todo(owner_id, "Tagged todo").unwrap();
+
+        add_tag(owner_id, todo.id, " work ").unwrap();
+        let result = add_tag(owner_id, todo.id, "home").unwrap();
+        assert_eq!(result.tags, ["work", "home"]);
+        let result = add_tag(owner_id, todo.id, "work").unwrap();
+        assert_eq!(result.tags, ["work", "home"]);
+
+        let result = remove_tag(owner_id, todo.id, "work").unwrap();
+        assert_eq!(result.tags, ["home"]);
+        let result = remove_tag(owner_id, todo.id, "work").unwrap();
+        assert_eq!(result.tags, ["home"]);
+    }
+
+    #[tarantool_test::test]
+    fn add_empty_tag() {
+        let owner_id = 1026;
+        let todo = create_todo(owner_id, "Tagged todo").unwrap();
+        let result = add_tag(owner_id, todo.id, "  ");
+        assert_eq!(result, Err(RepoErr::EmptyTag));
+    }
+
+    #[tarantool_test::test]
+    fn add_tag_of_other_owner() {
+        let owner_id = 1027;
+        let todo = create_todo(owner_id, "Other owner todo").unwrap();
+        let result = add_tag(owner_id + 1, todo.id, "work");
+        assert_eq!(result, Err(RepoErr::TodoNotFound(todo.id)));
+    }
+
+    #[tarantool_test::test]
+    fn todos_by_tag() {
+        let owner_id = 1029;
+        let other_id = 1030;
+        let first = create_todo(owner_id, "First").unwrap();
+        let second = create_todo(owner_id, "Second").unwrap();
+        create_todo(owner_id, "Untagged").unwrap();
+        let other = create_todo(other_id, "Other owner todo").unwrap();
+        add_tag(owner_id, second.id, "work").unwrap();
+        add_tag(owner_id, first.id, "work").unwrap();
+        add_tag(owner_id, first.id, "home").unwrap();
+        add_tag(other_id, other.id, "work").unwrap();
+
+        let result = list_todos_by_tag(owner_id, "work").unwrap();
+        assert_eq!(titles(&result), ["First", "Second"]);
+        let result = list_todos_by_tag(owner_id, "home").unwrap();
+        assert_eq!(titles(&result), ["First"]);
+    }
+
+    #[tarantool_test::test]
+    fn todo_pages() {
+        let owner_id = 1031;
+        create_todo(owner_id + 1, "Other owner todo").unwrap();
+        let todos: Vec<Todo> = (1..6)
+            .map(|i| create_todo(owner_id, &format!("Todo {i}")).unwrap())
+            .collect();
+
+        let page = list_todos_page(owner_id, None, 2, None).unwrap();
+        assert_eq!(page.todos, todos[..2]);
+        assert_eq!(page.next, Some(todos[1].id));
+
+        let page = list_todos_page(owner_id, page.next, 2, None).unwrap();
+        assert_eq!(page.todos, todos[2..4]);
+
+        let page = list_todos_page(owner_id, page.next, 2, None).unwrap();
+        assert_eq!(page.todos, todos[4..]);
+        assert_eq!(page.next, None);
+    }
+
+    #[tarantool_test::test]
+    fn todo_pages_by_status() {
+        let owner_id = 1033;
+        let todos: Vec<Todo> = (1..5)
+            .map(|i| create_todo(owner_id, &format!("Todo {i}")).unwrap())
+            .collect();
+        change_completed(owner_id, todos[1].id, true).unwrap();
+        change_completed(owner_id, todos[3].id, true).unwrap();
+
+        let page = list_todos_page(owner_id, None, 1, Some(true)).unwrap();
+        assert_eq!(titles(&page.todos), ["Todo 2"]);
+        assert_eq!(page.next, Some(todos[1].id));
+
+        let page = list_todos_page(owner_id, page.next, 1, Some(true)).unwrap();
+        assert_eq!(titles(&page.todos), ["Todo 4"]);
+        assert_eq!(page.next, None);
+
+        let page = list_todos_page(owner_id, None, 10, Some(false)).unwrap();
+        assert_eq!(titles(&page.todos), ["Todo 1", "Todo 3"]);
+    }
+
+    #[tarantool_test::test]
+    fn todo_pages_of_same_position() {
+        let owner_id = 1035;
+        let todos: Vec<Todo> = (1..4)
+            .map(|i| create_todo(owner_id, &format!("Todo {i}")).unwrap())
+            .collect();
+        let space_todo = RepoSpaces::Todo.find().unwrap();
+        for todo in &todos {
+            space_todo
+                .update(&[todo.id], [("=", 4, POSITION_GAP)])
+                .unwrap();
+        }
+
+        let page = list_todos_page(owner_id, None, 1, None).unwrap();
+        assert_eq!(titles(&page.todos), ["Todo 1"]);
+        let page = list_todos_page(owner_id, page.next, 1, None).unwrap();
+        assert_eq!(titles(&page.todos), ["Todo 2"]);
+        let page = list_todos_page(owner_id, page.next, 10, None).unwrap();
+        assert_eq!(titles(&page.todos), ["Todo 3"]);
+    }
+
+    #[tarantool_test::test]
+    fn count_active_and_completed() {
+        let owner_id = 1036;
+        create_todo(owner_id + 1, "Other owner todo").unwrap();
+        let todos: Vec<Todo> = (1..4)
+            .map(|i| create_todo(owner_id, &format!("Todo {i}")).unwrap())
+            .collect();
+        change_completed(owner_id, todos[0].id, true).unwrap();
+
+        let expected = TodoCounts {
+            active: 2,
+            completed: 1,
+        };
+        assert_eq!(count_todos(owner_id).unwrap(), expected);
+    }
+
+    #[tarantool_test::test]
+    fn todo_page_after_todo_of_other_owner() {
+        let owner_id = 1034;
+        let todo = create_todo(owner_id, "Other owner todo").unwrap();
+        let result = list_todos_page(owner_id + 1, Some(todo.id), 10, None);
+        assert_eq!(result, Err(RepoErr::TodoNotFound(todo.id)));
+    }
+
+    #[tarantool_test::test]
+    fn move_todo_before_other() {
+        let owner_id = 1017;
+        let clock = clock::freeze(NOW);
+        let first = create_todo(owner_id, "First").unwrap();
+        create_todo(owner_id, "Second").unwrap();
+        let third = create_todo(owner_id, "Third").unwrap();
+
+        clock.advance(60);
+        let result = move_todo(owner_id, third.id, Some(first.id)).unwrap();
+        assert_eq!(titles(&result), ["Third"]);
+        assert_eq!(result[0].updated_at, NOW + 60);
+        let todos = list_todos(owner_id).unwrap();
+        assert_eq!(titles(&todos), ["Third", "First", "Second"]);
+        assert_eq!(todos[0], result[0]);
+    }
+
+    #[tarantool_test::test]
+    fn move_todo_to_end() {
+        let owner_id = 1018;
+        let first = create_todo(owner_id, "First").unwrap();
+        create_todo(owner_id, "Second").unwrap();
+
+        move_todo(owner_id, first.id, None).unwrap();
+        assert_eq!(titles(&list_todos(owner_id).unwrap()), ["Second", "First"]);
+    }
+
+    #[tarantool_test::test]
+    fn move_todo_renumbers_exhausted_gap() {
+        let owner_id = 1019;
+        let clock = clock::freeze(NOW);
+        let first = create_todo(owner_id, "First").unwrap();
+        let second = create_todo(owner_id, "Second").unwrap();
+        let third = create_todo(owner_id, "Third").unwrap();
+
+        // Every move halves the gap before `second`
+        let mut moved = third.id;
+        let mut other = first.id;
+        for _ in 0..POSITION_GAP.trailing_zeros() {
+            move_todo(owner_id, moved, Some(second.id)).unwrap();
+            (moved, other) = (other, moved);
+        }
+        // Last move renumbers every todo
+        clock.advance(60);
+        let renumbered = move_todo(owner_id, moved, Some(second.id)).unwrap();
+        assert!(!renumbered.is_empty());
+        assert!(renumbered.iter().all(|t| t.updated_at == NOW + 60));
+
+        let result = list_todos(owner_id).unwrap();
+        assert_eq!(result.len(), 3);
+        assert_eq!(result.last().map(|t| t.id), Some(second.id));
+        assert!(result.windows(2).all(|w| w[0].position < w[1].position));
+    }
+
+    #[tarantool_test::test]
+    fn move_todo_of_other_owner() {
+        let owner_id = 1020;
+        let todo = create_todo(owner_id, "Other owner todo").unwrap();
+        let result = move_todo(owner_id + 1, todo.id, None);
+        assert_eq!(result, Err(RepoErr::TodoNotFound(todo.id)));
+    }
 }
@@ -0,0 +1,311 @@
+use crate::repo::audit::{self, Audit};
+use crate::repo::clock;
+use crate::repo::search::SearchIndex;
+use crate::repo::todo::POSITION_GAP;
+use crate::repo::transaction;
+use crate::repo::RepoErr;
+use crate::repo::RepoSpaces;
+use core::time::Duration;
+use front_app::{Todo, TrashedTodo};
+use std::cmp::Reverse;
+use std::env;
+use tarantool::error::Error as TrtlErr;
+use tarantool::fiber;
+use tarantool::index::{Index, IteratorType};
+use tarantool::space::Space;
+use tarantool::tuple::Tuple;
+
+/// How long deleted todos are kept. Configured with
+/// `TRASH_RETENTION_SECS` environment variable, 30 days by default.
+pub fn retention_from_env() -> Duration {
+    env::var("TRASH_RETENTION_SECS")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .filter(|secs| *secs > 0)
+        .map(Duration::from_secs)
+        .unwrap_or(Duration::from_secs(30 * 24 * 3600))
+}
+
+/// Deleted todos in `todo_trash` space. Todo tuple is kept whole in
+/// `todo` field, so it is restored as it was.
+pub struct Trash {
+    space: Space,
+}
+
+impl Trash {
+    pub fn find() -> Result<Trash, RepoErr> {
+        let space = RepoSpaces::TodoTrash.find()?;
+        Ok(Trash { space })
+    }
+
+    /// Put deleted `todo` into trash
+    pub fn put(&self, todo: &Todo, now: u64) -> Result<(), TrtlErr> {
+        self.space.replace(&(todo.id, todo.owner_id, now, todo))?;
+        Ok(())
+    }
+}
+
+/// List deleted todos of `owner_id`, recently deleted first.
+/// Returns `Result` with TrashedTodos on success, otherwise
+/// returns an `repo error`.
+pub fn list_trash(owner_id: u32) -> Result<Vec<TrashedTodo>, RepoErr> {
+    let space_trash = RepoSpaces::TodoTrash.find()?;
+
+    let mut trash = owner_index(&space_trash)?
+        .select(IteratorType::Eq, &(owner_id,))?
+        .map(|t| decode(&t))
+        .collect::<Result<Vec<TrashedTodo>, TrtlErr>>()?;
+    trash.sort_by_key(|t| (Reverse(t.deleted_at), t.todo.id));
+
+    Ok(trash)
+}
+
+/// Move deleted todo of `owner_id` back to the list. Todo keeps its
+/// position, unless it was taken meanwhile, then it goes last.
+/// Returns `Result` with restored Todo on success, otherwise
+/// returns an `repo error`.
+pub fn restore_todo(owner_id: u32, id: u32) -> Result<Todo, RepoErr> {
+    let space_trash = RepoSpaces::TodoTrash.find()?;
+    let space_todo = RepoSpaces::Todo.find()?;
+    let position_index = space_todo
+        .index_cached("todo_position")
+        .ok_or(RepoErr::IndexNotFound(String::from("todo_position")))?;
+    let search_index = SearchIndex::find()?;
+    let audit = Audit::find()?;
+    let now = clock::now();
+
+    let mut todo = space_trash
+        .get(&[id])?
+        .map(|t| decode(&t))
+        .transpose()?
+        .filter(|t| t.todo.owner_id == owner_id)
+        .ok_or(RepoErr::TodoNotFound(id))?
+        .todo;
+
+    let todo = transaction(|| -> Result<Todo, TrtlErr> {
+        let taken = position_index
+            .select(IteratorType::Eq, &(owner_id, todo.position))?
+            .next()
+            .is_some();
+        if taken {
+            let last = position_index
+                .select(IteratorType::Req, &(owner_id,))?
+                .next()
+                .map(|t| t.decode::<Todo>())
+                .transpose()?;
+            todo.position = last.map_or(0, |t| t.position) + POSITION_GAP;
+        }
+
+        space_trash.delete(&[id])?;
+        space_todo.insert(&todo)?;
+        search_index.index(todo.id, owner_id, &todo.title)?;
+        audit.record(audit::RESTORE, owner_id, None, Some(&todo), now)?;
+        Ok(todo)
+    })?;
+
+    Ok(todo)
+}
+
+/// Permanently delete trashed todos of `owner_id`.
+/// Returns `Result` with number of deleted todos on success, otherwise
+/// returns an `repo error`.
+pub fn empty_trash(owner_id: u32) -> Result<usize, RepoErr> {
+    let space_trash = RepoSpaces::TodoTrash.find()?;
+    let owner_index = owner_index(&space_trash)?;
+    let audit = Audit::find()?;
+    let now = clock::now();
+
+    let res = transaction(|| -> Result<usize, TrtlErr> {
+        let trashed = owner_index
+            .select(IteratorType::Eq, &(owner_id,))?
+            .map(|t| decode(&t))
+            .collect::<Result<Vec<TrashedTodo>, TrtlErr>>()?;
+        for TrashedTodo { todo, .. } in &trashed {
+            space_trash.delete(&[todo.id])?;
+            audit.record(audit::PURGE, owner_id, Some(todo), None, now)?;
+        }
+        Ok(trashed.len())
+    })?;
+
+    Ok(res)
+}
+
+/// Todos deleted by one purge transaction
+const PURGE_BATCH: u32 = 100;
+
+/// Permanently delete todos trashed at `before` or earlier. Purge is
+/// recorded in audit log as made by the todo owner. Todos are deleted by
+/// batches of `PURGE_BATCH` in separate transactions, the fiber yields
+/// between them.
+/// Returns `Result` with number of deleted todos on success, otherwise
+/// returns an `repo error`.
+pub fn purge_trash(before: u64) -> Result<usize, RepoErr> {
+    let space_trash = RepoSpaces::TodoTrash.find()?;
+    let deleted_index = space_trash
+        .index_cached("todo_trash_deleted")
+        .ok_or(RepoErr::IndexNotFound(String::from("todo_trash_deleted")))?;
+    let audit = Audit::find()?;
+    let now = clock::now();
+
+    let mut purged = 0;
+    loop {
+        let batch = transaction(|| -> Result<usize, TrtlErr> {
+            let expired = deleted_index
+                .select(IteratorType::LE, &(before,))?
+                .take(PURGE_BATCH as usize)
+                .map(|t| decode(&t))
+                .collect::<Result<Vec<TrashedTodo>, TrtlErr>>()?;
+            for TrashedTodo { todo, .. } in &expired {
+                space_trash.delete(&[todo.id])?;
+                audit.record(audit::PURGE, todo.owner_id, Some(todo), None, now)?;
+            }
+            Ok(expired.len())
+        })?;
+        purged += batch;
+        if batch < PURGE_BATCH as usize {
+            return Ok(purged);
+        }
+        fiber::reschedule();
+    }
+}
+
+/// Index of `todo_trash` space by owner
+pub fn owner_index(space_trash: &Space) -> Result<Index, RepoErr> {
+    space_trash
+        .index_cached("todo_trash_owner")
+        .ok_or(RepoErr::IndexNotFound(String::from("todo_trash_owner")))
+}
+
+/// Decode `todo_trash` tuple
+fn decode(tuple: &Tuple) -> Result<TrashedTodo, TrtlErr> {
+    let (_id, _owner_id, deleted_at, todo) = tuple.decode::<(u32, u32, u64, Todo)>()?;
+    Ok(TrashedTodo { todo, deleted_at })
+}
+
+#[cfg(feature = "test")]
+mod tests {
+    use super::*;
+    use crate::repo::todo::{
+        change_completed, create_todo, delete_completed, delete_todo, list_todos,
+    };
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn ids(todos: &[Todo]) -> Vec<u32> {
+        todos.iter().map(|t| t.id).collect()
+    }
+
+    #[tarantool_test::test]
+    fn delete_into_trash() {
+        let owner_id = 9001;
+        let _clock = clock::freeze(NOW);
+        let todo = create_todo(owner_id, "Trashed todo").unwrap();
+        delete_todo(owner_id, todo.id).unwrap();
+
+        assert_eq!(list_todos(owner_id).unwrap(), vec![]);
+        let expected = TrashedTodo {
+            todo,
+            deleted_at: NOW,
+        };
+        assert_eq!(list_trash(owner_id).unwrap(), vec![expected]);
+    }
+
+    #[tarantool_test::test]
+    fn restore_deleted_todo() {
+        let owner_id = 9002;
+        let first = create_todo(owner_id, "First").unwrap();
+        let second = create_todo(owner_id, "Second").unwrap();
+        delete_todo(owner_id, first.id).unwrap();
+
+        assert_eq!(restore_todo(owner_id, first.id).unwrap(), first);
+        assert_eq!(list_todos(owner_id).unwrap(), vec![first, second]);
+        assert_eq!(list_trash(owner_id).unwrap(), vec![]);
+    }
+
+    #[tarantool_test::test]
+    fn restore_todo_to_end_when_position_taken() {
+        let owner_id = 9003;
+        create_todo(owner_id, "First").unwrap();
+        let second = create_todo(owner_id, "Second").unwrap();
+        delete_todo(owner_id, second.id).unwrap();
+        // New last todo takes position of the deleted one
+        let third = create_todo(owner_id, "Third").unwrap();
+        assert_eq!(third.position, second.position);
+
+        let restored = restore_todo(owner_id, second.id).unwrap();
+        assert_eq!(restored.position, third.position + POSITION_GAP);
+        let todos = list_todos(owner_id).unwrap();
+        assert_eq!(todos.last(), Some(&restored));
+    }
+
+    #[tarantool_test::test]
+    fn restore_todo_of_other_owner() {
+        let owner_id = 9004;
+        let todo = create_todo(owner_id, "Other owner todo").unwrap();
+        delete_todo(owner_id, todo.id).unwrap();
+        let result = restore_todo(owner_id + 1, todo.id);
+        assert_eq!(result, Err(RepoErr::TodoNotFound(todo.id)));
+    }
+
+    #[tarantool_test::test]
+    fn delete_completed_into_trash() {
+        let owner_id = 9005;
+        let todo = create_todo(owner_id, "Completed todo").unwrap();
+        change_completed(owner_id, todo.id, true).unwrap();
+        delete_completed(owner_id).unwrap();
+
+        let trash: Vec<Todo> = list_trash(owner_id)
+            .unwrap()
+            .into_iter()
+            .map(|t| t.todo)
+            .collect();
+        assert_eq!(ids(&trash), [todo.id]);
+    }
+
+    #[tarantool_test::test]
+    fn empty_owner_trash() {
+        let owner_id = 9006;
+        let other_id = 9007;
+        let todo = create_todo(owner_id, "Trashed todo").unwrap();
+        let other = create_todo(other_id, "Other owner todo").unwrap();
+        delete_todo(owner_id, todo.id).unwrap();
+        delete_todo(other_id, other.id).unwrap();
+
+        assert_eq!(empty_trash(owner_id).unwrap(), 1);
+        assert_eq!(list_trash(owner_id).unwrap(), vec![]);
+        assert_eq!(list_trash(other_id).unwrap().len(), 1);
+    }
+
+    #[tarantool_test::test]
+    fn purge_old_trash() {
+        let owner_id = 9008;
+        let clock = clock::freeze(NOW);
+        let old = create_todo(owner_id, "Old todo").unwrap();
+        let recent = create_todo(owner_id, "Recent todo").unwrap();
+        delete_todo(owner_id, old.id).unwrap();
+        clock.advance(60);
+        delete_todo(owner_id, recent.id).unwrap();
+
+        // Trash of other owners may be purged too
+        purge_trash(NOW).unwrap();
+        let trash: Vec<Todo> = list_trash(owner_id)
+            .unwrap()
+            .into_iter()
+            .map(|t| t.todo)
+            .collect();
+        assert_eq!(ids(&trash), [recent.id]);
+    }
+
+    #[tarantool_test::test]
+    fn purge_trash_by_batches() {
+        let owner_id = 9009;
+        let _clock = clock::freeze(NOW);
+        for i in 0..=PURGE_BATCH {
+            let todo = create_todo(owner_id, &format!("Todo {i}")).unwrap();
+            delete_todo(owner_id, todo.id).unwrap();
+        }
+
+        assert!(purge_trash(NOW).unwrap() > PURGE_BATCH as usize);
+        assert_eq!(list_trash(owner_id).unwrap(), vec![]);
+    }
+}
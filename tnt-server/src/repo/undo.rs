@@ -0,0 +1,301 @@
+use crate::repo::audit::{self, Audit};
+use crate::repo::session::new_token;
+use crate::repo::todo::{change_all_completed, delete_completed, delete_todo, list_todos};
+use crate::repo::trash::restore_todo;
+use crate::repo::{clock, transaction, RepoErr, RepoSpaces};
+use core::time::Duration;
+use front_app::{Todo, TodoChanges, UndoableChanges};
+use serde::{Deserialize, Serialize};
+use tarantool::error::Error as TrtlErr;
+use tarantool::index::IteratorType;
+use tarantool::space::UpdateOps;
+
+/// How long destructive action can be undone
+pub const UNDO_TTL: Duration = Duration::from_secs(30);
+
+/// Inverse of a destructive action, kept in `undo` space
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+enum UndoOp {
+    /// Restore todos from trash
+    Restore(Vec<u32>),
+    /// Return todos to previous status and completion time
+    SetCompleted(Vec<(u32, bool, Option<u64>)>),
+}
+
+/// Delete todo of `owner_id`, see `todo::delete_todo`. Todo is deleted
+/// and undo action is saved in one transaction.
+/// Returns `Result` with UndoableChanges on success, otherwise
+/// returns an `repo error`.
+pub fn undoable_delete_todo(owner_id: u32, id: u32) -> Result<UndoableChanges, RepoErr> {
+    let changes = transaction(|| -> Result<UndoableChanges, RepoErr> {
+        delete_todo(owner_id, id)?;
+        let undo_token = save(owner_id, UndoOp::Restore(vec![id]))?;
+        Ok(UndoableChanges {
+            changes: TodoChanges::deleted(vec![id]),
+            undo_token,
+        })
+    })?;
+
+    Ok(changes)
+}
+
+/// Delete completed todos of `owner_id`, see `todo::delete_completed`.
+/// Todos are deleted and undo action is saved in one transaction.
+/// Returns `Result` with UndoableChanges on success, otherwise
+/// returns an `repo error`.
+pub fn undoable_delete_completed(owner_id: u32) -> Result<UndoableChanges, RepoErr> {
+    let changes = transaction(|| -> Result<UndoableChanges, RepoErr> {
+        let deleted: Vec<u32> = delete_completed(owner_id)?
+            .into_iter()
+            .map(|t| t.id)
+            .collect();
+        let undo_token = save(owner_id, UndoOp::Restore(deleted.clone()))?;
+        Ok(UndoableChanges {
+            changes: TodoChanges::deleted(deleted),
+            undo_token,
+        })
+    })?;
+
+    Ok(changes)
+}
+
+/// Change status of all todos of `owner_id`, see
+/// `todo::change_all_completed`. Todos are changed and undo action is
+/// saved in one transaction.
+/// Returns `Result` with UndoableChanges on success, otherwise
+/// returns an `repo error`.
+pub fn undoable_change_all_completed(
+    owner_id: u32,
+    completed: bool,
+) -> Result<UndoableChanges, RepoErr> {
+    let changes = transaction(|| -> Result<UndoableChanges, RepoErr> {
+        let states: Vec<(u32, bool, Option<u64>)> = list_todos(owner_id)?
+            .into_iter()
+            .filter(|t| t.completed != completed)
+            .map(|t| (t.id, t.completed, t.completed_at))
+            .collect();
+        let changed = change_all_completed(owner_id, completed)?;
+        let undo_token = save(owner_id, UndoOp::SetCompleted(states))?;
+        Ok(UndoableChanges {
+            changes: TodoChanges::changed(changed),
+            undo_token,
+        })
+    })?;
+
+    Ok(changes)
+}
+
+/// Undo action of `owner_id` by its `token`. Action is undone once,
+/// within `UNDO_TTL`, token is deleted in the transaction undoing it.
+/// Todos purged or deleted since the action are skipped.
+/// Returns `Result` with TodoChanges on success, otherwise returns an
+/// `repo error`.
+pub fn undo(owner_id: u32, token: &str) -> Result<TodoChanges, RepoErr> {
+    let space_undo = RepoSpaces::Undo.find()?;
+
+    let changed = transaction(|| -> Result<Vec<Todo>, RepoErr> {
+        let (_token, user_id, expires_at, op) = space_undo
+            .get(&(token,))?
+            .map(|t| t.decode::<(String, u32, u64, UndoOp)>())
+            .transpose()?
+            .ok_or(RepoErr::UndoNotFound)?;
+        if user_id != owner_id || expires_at <= clock::now() {
+            return Err(RepoErr::UndoNotFound);
+        }
+        space_undo.delete(&(token,))?;
+
+        match op {
+            UndoOp::Restore(ids) => {
+                let mut restored = Vec::with_capacity(ids.len());
+                for id in ids {
+                    match restore_todo(owner_id, id) {
+                        Ok(todo) => restored.push(todo),
+                        Err(RepoErr::TodoNotFound(_)) => {}
+                        Err(err) => return Err(err),
+                    }
+                }
+                Ok(restored)
+            }
+            UndoOp::SetCompleted(states) => set_completed(owner_id, &states),
+        }
+    })?;
+
+    Ok(TodoChanges::changed(changed))
+}
+
+/// Delete undo actions expired by `now`.
+/// Returns `Result` with number of deleted actions on success,
+/// otherwise returns an `repo error`.
+pub fn purge_expired(now: u64) -> Result<usize, RepoErr> {
+    let space_undo = RepoSpaces::Undo.find()?;
+    let expires = space_undo
+        .index_cached("undo_expires")
+        .ok_or(RepoErr::IndexNotFound(String::from("undo_expires")))?;
+
+    let expired: Vec<String> = expires
+        .select(IteratorType::LE, &(now,))?
+        .filter_map(|t| t.field::<String>(0).ok().flatten())
+        .collect();
+    for token in &expired {
+        space_undo.delete(&(token,))?;
+    }
+
+    Ok(expired.len())
+}
+
+/// Save inverse `op` of action of `owner_id`, returns its token
+fn save(owner_id: u32, op: UndoOp) -> Result<String, RepoErr> {
+    let space_undo = RepoSpaces::Undo.find()?;
+    let token = new_token();
+    let expires_at = clock::now() + UNDO_TTL.as_secs();
+    space_undo.insert(&(&token, owner_id, expires_at, op))?;
+    Ok(token)
+}
+
+/// Return todos of `owner_id` to saved `states`, update time is set.
+/// Returns changed todos.
+fn set_completed(owner_id: u32, states: &[(u32, bool, Option<u64>)]) -> Result<Vec<Todo>, RepoErr> {
+    let space_todo = RepoSpaces::Todo.find()?;
+    let audit = Audit::find()?;
+    let now = clock::now();
+
+    let changed = transaction(|| -> Result<Vec<Todo>, TrtlErr> {
+        let mut changed = Vec::with_capacity(states.len());
+        for &(id, completed, completed_at) in states {
+            let before = space_todo
+                .get(&[id])?
+                .map(|t| t.decode::<Todo>())
+                .transpose()?
+                .filter(|t| t.owner_id == owner_id);
+            let Some(before) = before else {
+                continue;
+            };
+            let mut ops = UpdateOps::new();
+            ops.assign(2, completed)?;
+            ops.assign(6, now)?;
+            ops.assign(7, completed_at)?;
+            if let Some(after) = space_todo.update(&[id], ops.as_slice())? {
+                let after = after.decode::<Todo>()?;
+                audit.record(
+                    audit::CHANGE_COMPLETED,
+                    owner_id,
+                    Some(&before),
+                    Some(&after),
+                    now,
+                )?;
+                changed.push(after);
+            }
+        }
+        Ok(changed)
+    })?;
+
+    Ok(changed)
+}
+
+#[cfg(feature = "test")]
+mod tests {
+    use super::*;
+    use crate::repo::todo::tests::titles;
+    use crate::repo::todo::{change_completed, create_todo};
+
+    const NOW: u64 = 1_700_000_000;
+
+    #[tarantool_test::test]
+    fn undo_delete_todo() {
+        let owner_id = 10001;
+        let todo = create_todo(owner_id, "Deleted todo").unwrap();
+        let token = undoable_delete_todo(owner_id, todo.id).unwrap().undo_token;
+        assert_eq!(list_todos(owner_id).unwrap(), vec![]);
+
+        let changes = undo(owner_id, &token).unwrap();
+        assert_eq!(changes, TodoChanges::changed(vec![todo.clone()]));
+        assert_eq!(list_todos(owner_id).unwrap(), vec![todo]);
+    }
+
+    #[tarantool_test::test]
+    fn undo_delete_completed() {
+        let owner_id = 10002;
+        let done = create_todo(owner_id, "Done").unwrap();
+        create_todo(owner_id, "Open").unwrap();
+        change_completed(owner_id, done.id, true).unwrap();
+        let token = undoable_delete_completed(owner_id).unwrap().undo_token;
+        assert_eq!(titles(&list_todos(owner_id).unwrap()), ["Open"]);
+
+        undo(owner_id, &token).unwrap();
+        assert_eq!(titles(&list_todos(owner_id).unwrap()), ["Done", "Open"]);
+    }
+
+    #[tarantool_test::test]
+    fn undo_change_all_completed() {
+        let owner_id = 10003;
+        let clock = clock::freeze(NOW);
+        let done = create_todo(owner_id, "Done").unwrap();
+        create_todo(owner_id, "Open").unwrap();
+        change_completed(owner_id, done.id, true).unwrap();
+
+        clock.advance(10);
+        let token = undoable_change_all_completed(owner_id, true)
+            .unwrap()
+            .undo_token;
+        undo(owner_id, &token).unwrap();
+
+        let states: Vec<(bool, Option<u64>)> = list_todos(owner_id)
+            .unwrap()
+            .iter()
+            .map(|t| (t.completed, t.completed_at))
+            .collect();
+        assert_eq!(states, [(true, Some(NOW)), (false, None)]);
+    }
+
+    #[tarantool_test::test]
+    fn undo_once() {
+        let owner_id = 10004;
+        let todo = create_todo(owner_id, "Deleted todo").unwrap();
+        let token = undoable_delete_todo(owner_id, todo.id).unwrap().undo_token;
+        undo(owner_id, &token).unwrap();
+        assert_eq!(undo(owner_id, &token), Err(RepoErr::UndoNotFound));
+    }
+
+    #[tarantool_test::test]
+    fn undo_expired() {
+        let owner_id = 10005;
+        let clock = clock::freeze(NOW);
+        let todo = create_todo(owner_id, "Deleted todo").unwrap();
+        let token = undoable_delete_todo(owner_id, todo.id).unwrap().undo_token;
+
+        clock.advance(UNDO_TTL.as_secs());
+        assert_eq!(undo(owner_id, &token), Err(RepoErr::UndoNotFound));
+        assert!(purge_expired(NOW + UNDO_TTL.as_secs()).unwrap() >= 1);
+    }
+
+    #[tarantool_test::test]
+    fn undoable_action_rolls_back_with_caller() {
+        let owner_id = 10007;
+        let todo = create_todo(owner_id, "Kept todo").unwrap();
+
+        let result = transaction(|| -> Result<(), RepoErr> {
+            undoable_delete_todo(owner_id, todo.id)?;
+            Err(RepoErr::EmptyTodoTitle)
+        });
+
+        assert!(result.is_err());
+        assert_eq!(list_todos(owner_id).unwrap(), vec![todo]);
+        let tokens = RepoSpaces::Undo
+            .find()
+            .unwrap()
+            .select(IteratorType::All, &())
+            .unwrap()
+            .filter(|t| t.field::<u32>(1).unwrap() == Some(owner_id))
+            .count();
+        assert_eq!(tokens, 0);
+    }
+
+    #[tarantool_test::test]
+    fn undo_of_other_owner() {
+        let owner_id = 10006;
+        let todo = create_todo(owner_id, "Deleted todo").unwrap();
+        let token = undoable_delete_todo(owner_id, todo.id).unwrap().undo_token;
+        assert_eq!(undo(owner_id + 1, &token), Err(RepoErr::UndoNotFound));
+        assert_eq!(list_todos(owner_id).unwrap(), vec![]);
+    }
+}
@@ -1,39 +1,392 @@
+use crate::repo::search::SearchIndex;
+use crate::repo::transaction;
+use crate::repo::trash;
 use crate::repo::RepoErr;
 use crate::repo::RepoSpaces;
+use crate::wait_readable;
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::sync::mpsc::{self, Sender, TryRecvError};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use tarantool::error::{Error as TrtlErr, TarantoolErrorCode};
+use tarantool::index::{Index, IteratorType};
+use tarantool::space::Space;
+use tarantool::tuple::Tuple;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct User {
-    id: u32,
-    name: String,
+    pub id: u32,
+    pub name: String,
 }
 
-/// Insert `user` for the provided name. Empty name not allowed.
+impl User {
+    /// Decode `user` tuple, password hash is not exposed
+    pub fn decode(tuple: &Tuple) -> Result<User, RepoErr> {
+        let (id, name, _password_hash) = tuple.decode::<(u32, String, String)>()?;
+        Ok(User { id, name })
+    }
+}
+
+/// Insert `user` for the provided name and password. Empty name or
+/// password not allowed, name must be unique. Password is hashed before
+/// the insert, so concurrent insert of the same name is refused by
+/// `user_name` index.
 /// Returns `Result` with inserted User on success, otherwise
 /// returns an `repo error`.
-#[allow(dead_code)]
-pub fn create_user(name: &str) -> Result<User, RepoErr> {
-    use RepoErr::{EmptyUserName, FieldNotExsist};
+pub fn create_user(name: &str, password: &str) -> Result<User, RepoErr> {
+    use RepoErr::{EmptyPassword, EmptyUserName, UserExists};
+
+    if name.is_empty() {
+        return Err(EmptyUserName);
+    }
+    if password.is_empty() {
+        return Err(EmptyPassword);
+    }
+    let space_user = RepoSpaces::User.find()?;
+    if name_index(&space_user)?.get(&(name,))?.is_some() {
+        return Err(UserExists(name.to_string()));
+    }
+
+    let password_hash = hash_password(password)?;
+    let user = space_user
+        .insert(&(None::<u32>, name, password_hash))
+        .map_err(|err| match err {
+            TrtlErr::Tarantool(e) if e.error_code() == TarantoolErrorCode::TupleFound as u32 => {
+                UserExists(name.to_string())
+            }
+            err => err.into(),
+        })?;
+
+    User::decode(&user)
+}
+
+/// Get `user` for the provided id.
+/// Returns `Result` with User on success, otherwise
+/// returns an `repo error`.
+pub fn get_user(id: u32) -> Result<User, RepoErr> {
+    let space_user = RepoSpaces::User.find()?;
+    let user = space_user.get(&[id])?.ok_or(RepoErr::UserNotFound(id))?;
+
+    User::decode(&user)
+}
+
+/// Find `user` by name.
+/// Returns `Result` with optional User on success, otherwise
+/// returns an `repo error`.
+pub fn find_user_by_name(name: &str) -> Result<Option<User>, RepoErr> {
+    let space_user = RepoSpaces::User.find()?;
+    let user = name_index(&space_user)?.get(&(name,))?;
+
+    user.as_ref().map(User::decode).transpose()
+}
+
+/// Change name of selected user. Empty name not allowed, name must be
+/// unique.
+/// Returns `Result` with User on success, otherwise
+/// returns an `repo error`.
+pub fn rename_user(id: u32, name: &str) -> Result<User, RepoErr> {
+    use RepoErr::{EmptyUserName, UserExists, UserNotFound};
 
     if name.is_empty() {
         return Err(EmptyUserName);
     }
     let space_user = RepoSpaces::User.find()?;
-    let todo = space_user.insert(&(None::<u32>, name))?;
+    if let Some(other) = name_index(&space_user)?.get(&(name,))? {
+        if User::decode(&other)?.id != id {
+            return Err(UserExists(name.to_string()));
+        }
+    }
+
+    let user = space_user
+        .update(&[id], [("=", 1, name)])?
+        .ok_or(UserNotFound(id))?;
+
+    User::decode(&user)
+}
+
+/// Delete selected user with their todos, search index of the todos,
+/// trashed todos, sessions, notifications and undo actions in a
+/// transaction. Audit log is append-only, entries of the user are kept
+/// as the history of their todos.
+/// Returns `Result` with deleted User on success, otherwise
+/// returns an `repo error`.
+pub fn delete_user(id: u32) -> Result<User, RepoErr> {
+    let space_user = RepoSpaces::User.find()?;
+    let space_todo = RepoSpaces::Todo.find()?;
+    let space_session = RepoSpaces::Session.find()?;
+    let space_notification = RepoSpaces::Notification.find()?;
+    let todo_owner = find_index(&space_todo, "todo_owner")?;
+    let session_user = find_index(&space_session, "session_user")?;
+    let notification_user = find_index(&space_notification, "notification_user")?;
+    let space_undo = RepoSpaces::Undo.find()?;
+    let undo_user = find_index(&space_undo, "undo_user")?;
+    let search_index = SearchIndex::find()?;
+    let space_trash = RepoSpaces::TodoTrash.find()?;
+    let trash_owner = trash::owner_index(&space_trash)?;
+    let user = get_user(id)?;
+
+    transaction(|| -> Result<(), TrtlErr> {
+        let todos: Vec<u32> = todo_owner
+            .select(IteratorType::Eq, &(id,))?
+            .filter_map(|t| t.field::<u32>(0).ok().flatten())
+            .collect();
+        for todo_id in todos {
+            search_index.remove(todo_id)?;
+            space_todo.delete(&[todo_id])?;
+        }
+
+        let trashed: Vec<u32> = trash_owner
+            .select(IteratorType::Eq, &(id,))?
+            .filter_map(|t| t.field::<u32>(0).ok().flatten())
+            .collect();
+        for todo_id in trashed {
+            space_trash.delete(&[todo_id])?;
+        }
+
+        let sessions: Vec<String> = session_user
+            .select(IteratorType::Eq, &(id,))?
+            .filter_map(|t| t.field::<String>(0).ok().flatten())
+            .collect();
+        for token in sessions {
+            space_session.delete(&(token,))?;
+        }
+
+        let notifications: Vec<u32> = notification_user
+            .select(IteratorType::Eq, &(id,))?
+            .filter_map(|t| t.field::<u32>(0).ok().flatten())
+            .collect();
+        for notification_id in notifications {
+            space_notification.delete(&[notification_id])?;
+        }
+
+        let undo_tokens: Vec<String> = undo_user
+            .select(IteratorType::Eq, &(id,))?
+            .filter_map(|t| t.field::<String>(0).ok().flatten())
+            .collect();
+        for token in undo_tokens {
+            space_undo.delete(&(token,))?;
+        }
+
+        space_user.delete(&[id])?;
+        Ok(())
+    })?;
+
+    Ok(user)
+}
+
+/// List at most `limit` users ordered by id, starting after user id
+/// `after`, from the first user when `None`.
+/// Returns `Result` with Users on success, otherwise
+/// returns an `repo error`.
+pub fn list_users(after: Option<u32>, limit: usize) -> Result<Vec<User>, RepoErr> {
+    let space_user = RepoSpaces::User.find()?;
+    let users = match after {
+        Some(after) => space_user.select(IteratorType::GT, &(after,))?,
+        None => space_user.select(IteratorType::All, &())?,
+    };
+
+    users.take(limit).map(|t| User::decode(&t)).collect()
+}
+
+/// Find user by name and check the password.
+/// Returns `Result` with User on success, otherwise returns an
+/// `repo error`. Unknown name and wrong password are not distinguished,
+/// password of unknown name is checked against a dummy hash, so both
+/// take the same time.
+pub fn verify_credentials(name: &str, password: &str) -> Result<User, RepoErr> {
+    use RepoErr::{FieldNotExsist, InvalidCredentials};
+
+    let space_user = RepoSpaces::User.find()?;
+    let user = name_index(&space_user)?.get(&(name,))?;
+
+    let password_hash: String = match &user {
+        Some(user) => user
+            .get(2)
+            .ok_or(FieldNotExsist(String::from("password_hash")))?,
+        None => dummy_hash()?,
+    };
+    let matches = password_matches(&password_hash, password)?;
+
+    match user {
+        Some(user) if matches => User::decode(&user),
+        _ => Err(InvalidCredentials),
+    }
+}
+
+/// Replace password of user `id`, `current` password must match. New
+/// password is hashed before the update.
+/// Returns `Result` with unit on success, otherwise returns an
+/// `repo error`.
+pub fn change_password(id: u32, current: &str, password: &str) -> Result<(), RepoErr> {
+    use RepoErr::{EmptyPassword, FieldNotExsist, InvalidCredentials};
+
+    if password.is_empty() {
+        return Err(EmptyPassword);
+    }
+    let space_user = RepoSpaces::User.find()?;
+    let user = space_user.get(&(id,))?.ok_or(InvalidCredentials)?;
+
+    let password_hash: String = user
+        .get(2)
+        .ok_or(FieldNotExsist(String::from("password_hash")))?;
+    if !password_matches(&password_hash, current)? {
+        return Err(InvalidCredentials);
+    }
+
+    let password_hash = hash_password(password)?;
+    space_user.update(&[id], [("=", 2, password_hash)])?;
+    Ok(())
+}
+
+/// Index of `user` space by name
+fn name_index(space_user: &Space) -> Result<Index, RepoErr> {
+    find_index(space_user, "user_name")
+}
+
+fn find_index(space: &Space, name: &str) -> Result<Index, RepoErr> {
+    space
+        .index_cached(name)
+        .ok_or(RepoErr::IndexNotFound(String::from(name)))
+}
+
+/// Argon2 hash of `password` with random salt, in PHC string format
+fn hash_password(password: &str) -> Result<String, RepoErr> {
+    let password = password.to_string();
+    off_tx_thread(move || {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+    })?
+    .map_err(|err| RepoErr::PasswordHash(err.to_string()))
+}
+
+/// Check `password` against stored hash. Users created before passwords
+/// have an empty hash and can't sign in, their password is checked
+/// against a dummy hash to take the same time.
+fn password_matches(password_hash: &str, password: &str) -> Result<bool, RepoErr> {
+    let dummy_hash = dummy_hash()?;
+    let (password_hash, password) = (password_hash.to_string(), password.to_string());
+    off_tx_thread(move || {
+        let (hash, valid) = match PasswordHash::new(&password_hash) {
+            Ok(hash) => (hash, true),
+            Err(_) => match PasswordHash::new(&dummy_hash) {
+                Ok(hash) => (hash, false),
+                Err(_) => return false,
+            },
+        };
+        let matches = Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok();
+        valid && matches
+    })
+}
+
+/// Hash of a fixed password, checked instead of a missing password hash
+fn dummy_hash() -> Result<String, RepoErr> {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+
+    if let Some(hash) = DUMMY_HASH.get() {
+        return Ok(hash.clone());
+    }
+    let hash = hash_password("dummy password")?;
+    Ok(DUMMY_HASH.get_or_init(|| hash).clone())
+}
+
+/// Number of threads hashing passwords, more hashes wait in queue
+const HASH_THREADS: usize = 2;
+
+/// Job run by a password hash thread
+type HashJob = Box<dyn FnOnce() + Send>;
+
+/// Queue of password hash threads, started on first use. Queue is
+/// closed when no thread could be started.
+fn hash_queue() -> &'static Mutex<Sender<HashJob>> {
+    static HASH_QUEUE: OnceLock<Mutex<Sender<HashJob>>> = OnceLock::new();
 
-    let id = todo.get(0).ok_or(FieldNotExsist(String::from("id")))?;
-    let name = todo.get(1).ok_or(FieldNotExsist(String::from("name")))?;
+    HASH_QUEUE.get_or_init(|| {
+        let (job_tx, job_rx) = mpsc::channel::<HashJob>();
+        let job_rx = Arc::new(Mutex::new(job_rx));
+        for _ in 0..HASH_THREADS {
+            let job_rx = job_rx.clone();
+            let res = thread::Builder::new()
+                .name("password hash".into())
+                .spawn(move || loop {
+                    let job = match job_rx.lock() {
+                        Ok(job_rx) => job_rx.recv(),
+                        Err(_) => return,
+                    };
+                    match job {
+                        Ok(job) => job(),
+                        Err(_) => return,
+                    }
+                });
+            if let Err(err) = res {
+                eprintln!("failed to start password hash thread: {err}");
+            }
+        }
+        Mutex::new(job_tx)
+    })
+}
+
+/// Run `f` on a password hash thread, current fiber yields until it is
+/// done. Argon2 takes tens of milliseconds of CPU, on tx thread it would
+/// stall every fiber.
+fn off_tx_thread<T, F>(f: F) -> Result<T, RepoErr>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let (notify, wakeup) =
+        UnixStream::pair().map_err(|err: io::Error| RepoErr::PasswordHash(err.to_string()))?;
+    let (res_tx, res_rx) = mpsc::channel();
+
+    let job: HashJob = Box::new(move || {
+        let _ = res_tx.send(f());
+        // Closed socket becomes readable and wakes up waiting fiber
+        drop(notify);
+    });
+    hash_queue()
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .send(job)
+        .map_err(|_| RepoErr::PasswordHash("no password hash thread".into()))?;
 
-    Ok(User { id, name })
+    loop {
+        match res_rx.try_recv() {
+            Ok(res) => return Ok(res),
+            Err(TryRecvError::Empty) => wait_readable(wakeup.as_raw_fd()),
+            Err(TryRecvError::Disconnected) => {
+                return Err(RepoErr::PasswordHash("hash thread panicked".into()))
+            }
+        }
+    }
 }
 
 #[cfg(feature = "test")]
 mod tests {
     use super::*;
+    use crate::repo::audit::list_audit;
+    use crate::repo::clock;
+    use crate::repo::session::{create_session, find_session};
+    use crate::repo::todo::{create_todo, list_todos};
+    use crate::repo::undo::undoable_delete_todo;
+    use core::time::Duration;
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use tarantool::fiber;
+
+    const PASSWORD: &str = "secret password";
 
     #[tarantool_test::test]
     fn insert_user() {
         let name = "New user 1";
-        let result = create_user(name).unwrap();
+        let result = create_user(name, PASSWORD).unwrap();
         let expected = User {
             id: result.id,
             name: name.to_string(),
@@ -44,15 +397,209 @@ mod tests {
     #[tarantool_test::test]
     fn insert_same_data() {
         let name = "New user 2";
-        create_user(name).unwrap();
-        let result = create_user(name);
-        assert!(result.is_err());
+        create_user(name, PASSWORD).unwrap();
+        let result = create_user(name, PASSWORD);
+        assert_eq!(result, Err(RepoErr::UserExists(name.to_string())));
     }
 
     #[tarantool_test::test]
     fn insert_empty_name() {
         let name = "";
-        let result = create_user(name);
+        let result = create_user(name, PASSWORD);
+        assert_eq!(result, Err(RepoErr::EmptyUserName));
+    }
+
+    #[tarantool_test::test]
+    fn insert_empty_password() {
+        let result = create_user("New user 3", "");
+        assert_eq!(result, Err(RepoErr::EmptyPassword));
+    }
+
+    #[tarantool_test::test]
+    fn password_is_hashed() {
+        let name = "New user 4";
+        let user = create_user(name, PASSWORD).unwrap();
+        let tuple = RepoSpaces::User.find().unwrap().get(&(user.id,)).unwrap();
+        let password_hash: String = tuple.unwrap().get(2).unwrap();
+        assert!(password_hash.starts_with("$argon2"));
+        assert!(!password_hash.contains(PASSWORD));
+    }
+
+    #[tarantool_test::test]
+    fn verify_user_credentials() {
+        let name = "Login user 1";
+        let user = create_user(name, PASSWORD).unwrap();
+        let result = verify_credentials(name, PASSWORD).unwrap();
+        assert_eq!(result, user);
+    }
+
+    #[tarantool_test::test]
+    fn verify_wrong_credentials() {
+        let name = "Login user 2";
+        create_user(name, PASSWORD).unwrap();
+        let result = verify_credentials(name, "wrong password");
+        assert_eq!(result, Err(RepoErr::InvalidCredentials));
+        let result = verify_credentials("Unknown user", PASSWORD);
+        assert_eq!(result, Err(RepoErr::InvalidCredentials));
+    }
+
+    #[tarantool_test::test]
+    fn verify_credentials_yields() {
+        let name = "Login user 5";
+        create_user(name, PASSWORD).unwrap();
+        let ticks = Rc::new(Cell::new(0));
+        let stop = Rc::new(Cell::new(false));
+        let ticker = {
+            let (ticks, stop) = (ticks.clone(), stop.clone());
+            fiber::start(move || {
+                while !stop.get() {
+                    ticks.set(ticks.get() + 1);
+                    fiber::sleep(Duration::from_millis(1));
+                }
+            })
+        };
+
+        verify_credentials(name, PASSWORD).unwrap();
+        verify_credentials("Unknown user", PASSWORD).unwrap_err();
+
+        // Other fibers run while passwords are checked
+        assert!(ticks.get() > 1);
+        stop.set(true);
+        ticker.join();
+    }
+
+    #[tarantool_test::test]
+    fn change_user_password() {
+        let name = "Login user 3";
+        let user = create_user(name, PASSWORD).unwrap();
+        change_password(user.id, PASSWORD, "new password").unwrap();
+        assert!(verify_credentials(name, PASSWORD).is_err());
+        assert_eq!(verify_credentials(name, "new password").unwrap(), user);
+    }
+
+    #[tarantool_test::test]
+    fn change_password_wrong_current() {
+        let name = "Login user 4";
+        let user = create_user(name, PASSWORD).unwrap();
+        let result = change_password(user.id, "wrong password", "new password");
+        assert_eq!(result, Err(RepoErr::InvalidCredentials));
+        assert_eq!(verify_credentials(name, PASSWORD).unwrap(), user);
+    }
+
+    #[tarantool_test::test]
+    fn get_created_user() {
+        let user = create_user("Get user 1", PASSWORD).unwrap();
+        assert_eq!(get_user(user.id).unwrap(), user);
+    }
+
+    #[tarantool_test::test]
+    fn get_missing_user() {
+        let result = get_user(u32::MAX);
+        assert_eq!(result, Err(RepoErr::UserNotFound(u32::MAX)));
+    }
+
+    #[tarantool_test::test]
+    fn find_user_by_its_name() {
+        let name = "Find user 1";
+        let user = create_user(name, PASSWORD).unwrap();
+        assert_eq!(find_user_by_name(name).unwrap(), Some(user));
+        assert_eq!(find_user_by_name("Unknown user").unwrap(), None);
+    }
+
+    #[tarantool_test::test]
+    fn rename_created_user() {
+        let user = create_user("Rename user 1", PASSWORD).unwrap();
+        let result = rename_user(user.id, "Renamed user 1").unwrap();
+        let expected = User {
+            id: user.id,
+            name: "Renamed user 1".to_string(),
+        };
+        assert_eq!(result, expected);
+        assert_eq!(find_user_by_name("Rename user 1").unwrap(), None);
+        assert!(verify_credentials("Renamed user 1", PASSWORD).is_ok());
+    }
+
+    #[tarantool_test::test]
+    fn rename_to_same_name() {
+        let user = create_user("Rename user 2", PASSWORD).unwrap();
+        assert_eq!(rename_user(user.id, "Rename user 2").unwrap(), user);
+    }
+
+    #[tarantool_test::test]
+    fn rename_to_taken_name() {
+        let user = create_user("Rename user 3", PASSWORD).unwrap();
+        create_user("Rename user 4", PASSWORD).unwrap();
+        let result = rename_user(user.id, "Rename user 4");
+        assert_eq!(
+            result,
+            Err(RepoErr::UserExists("Rename user 4".to_string()))
+        );
+    }
+
+    #[tarantool_test::test]
+    fn rename_to_empty_name() {
+        let user = create_user("Rename user 5", PASSWORD).unwrap();
+        let result = rename_user(user.id, "");
         assert_eq!(result, Err(RepoErr::EmptyUserName));
     }
+
+    #[tarantool_test::test]
+    fn rename_missing_user() {
+        let result = rename_user(u32::MAX, "Rename user 6");
+        assert_eq!(result, Err(RepoErr::UserNotFound(u32::MAX)));
+    }
+
+    #[tarantool_test::test]
+    fn delete_user_with_todos() {
+        let user = create_user("Delete user 1", PASSWORD).unwrap();
+        create_todo(user.id, "Todo of deleted user").unwrap();
+        let now = clock::now();
+        let session = create_session(user.id, now, Duration::from_secs(60)).unwrap();
+
+        assert_eq!(delete_user(user.id).unwrap(), user);
+
+        assert_eq!(get_user(user.id), Err(RepoErr::UserNotFound(user.id)));
+        assert_eq!(list_todos(user.id).unwrap(), vec![]);
+        assert_eq!(find_session(&session.token, now).unwrap(), None);
+    }
+
+    #[tarantool_test::test]
+    fn delete_user_with_undo_actions() {
+        let user = create_user("Delete user 2", PASSWORD).unwrap();
+        let todo = create_todo(user.id, "Deleted todo of deleted user").unwrap();
+        let token = undoable_delete_todo(user.id, todo.id).unwrap().undo_token;
+
+        delete_user(user.id).unwrap();
+
+        let space_undo = RepoSpaces::Undo.find().unwrap();
+        assert!(space_undo.get(&(token,)).unwrap().is_none());
+        // Audit log keeps history of todos of deleted users
+        let entries = list_audit(user.id, Some(todo.id), None, None, None, 10)
+            .unwrap()
+            .entries;
+        assert_eq!(entries.len(), 2);
+    }
+
+    #[tarantool_test::test]
+    fn delete_missing_user() {
+        let result = delete_user(u32::MAX);
+        assert_eq!(result, Err(RepoErr::UserNotFound(u32::MAX)));
+    }
+
+    #[tarantool_test::test]
+    fn list_users_by_pages() {
+        let first = create_user("List user 1", PASSWORD).unwrap();
+        let second = create_user("List user 2", PASSWORD).unwrap();
+        let third = create_user("List user 3", PASSWORD).unwrap();
+        let before_first = Some(first.id - 1);
+
+        let page = list_users(before_first, 2).unwrap();
+        assert_eq!(page, vec![first, second.clone()]);
+
+        let page = list_users(Some(second.id), 2).unwrap();
+        assert_eq!(page.first(), Some(&third));
+
+        let all = list_users(None, usize::MAX).unwrap();
+        assert!(all.windows(2).all(|w| w[0].id < w[1].id));
+    }
 }